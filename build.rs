@@ -1,77 +1,49 @@
 use anyhow::*;
 use glob::glob;
-use std::fs::{read_to_string, write};
+use std::env;
+use std::fs::write;
 use std::path::PathBuf;
 
-struct ShaderData {
-    src: String,
-    src_path: PathBuf,
-    spv_path: PathBuf,
-    kind: shaderc::ShaderKind,
-}
-
-impl ShaderData {
-    pub fn load(src_path: PathBuf) -> Result<Self> {
-        let extension = src_path
-            .extension()
-            .context("File has no extension")?
-            .to_str()
-            .context("Extension cannot be converted to &str")?;
-        let kind = match extension {
-            "vert" => shaderc::ShaderKind::Vertex,
-            "frag" => shaderc::ShaderKind::Fragment,
-            "comp" => shaderc::ShaderKind::Compute,
-            _ => bail!("Unsupported shader: {}", src_path.display()),
-        };
+// Shared with the game, which recompiles shaders with them when they are edited
+#[path = "src/shader/cache.rs"]
+mod cache;
+#[path = "src/shader/compiler.rs"]
+mod compiler;
+#[path = "src/shader/preprocessor.rs"]
+mod preprocessor;
 
-        let src = read_to_string(src_path.clone())?;
-        let spv_path = src_path.with_extension(format!("{}.spv", extension));
-
-        Ok(Self {
-            src,
-            src_path,
-            spv_path,
-            kind,
-        })
-    }
-}
+use cache::ShaderCache;
+use compiler::ShaderCompiler;
+use preprocessor::spirv_path;
 
 fn main() -> Result<()> {
     // This tells cargo to rerun this script if something in /src/ changes.
     println!("cargo:rerun-if-changed=src/*");
 
-    // Collect all shaders recursively within /src/
+    // Collect all shaders recursively within /src/. Headers (.glsl) only get compiled as part
+    // of the shaders including them.
     let mut shader_paths = [
         glob("./src/**/*.vert")?,
         glob("./src/**/*.frag")?,
         glob("./src/**/*.comp")?,
     ];
 
-    // This could be parallelized
-    let shaders = shader_paths
-        .iter_mut()
-        .flatten()
-        .map(|glob_result| ShaderData::load(glob_result?))
-        .collect::<Vec<Result<_>>>()
-        .into_iter()
-        .collect::<Result<Vec<_>>>();
-
-    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
-
-    // This can't be parallelized. The [shaderc::Compiler] is not
-    // thread safe. Also, it creates a lot of resources. You could
-    // spawn multiple processes to handle this, but it would probably
-    // be better just to only compile shaders that have been changed
-    // recently.
-    for shader in shaders? {
-        let compiled = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            None,
-        )?;
-        write(shader.spv_path, compiled.as_binary_u8())?;
+    // Shaders whose source, includes and defines didn't change since the last build come out
+    // of the cache instead of being compiled again.
+    let cache = ShaderCache::new(PathBuf::from(env::var("OUT_DIR")?).join("shader-cache"));
+    let mut compiler = ShaderCompiler::new(cache, vec![PathBuf::from("./src/assets/include")]);
+
+    for src_path in shader_paths.iter_mut().flatten() {
+        let src_path = src_path?;
+        let shader = compiler.preprocess(&src_path, &[])?;
+        let compiled = compiler.compile_preprocessed(&src_path, &shader)?;
+        write(spirv_path(&src_path, &[]), compiled)?;
+
+        // Every `#pragma permutation` gets its own .spv with those defines set
+        for defines in &shader.permutations {
+            let compiled = compiler.compile(&src_path, defines)?;
+            write(spirv_path(&src_path, defines), compiled)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,390 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use anyhow::*;
+use crossbeam_channel::{Receiver, Sender};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::gltf::{GltfLoadOptions, GltfLoader, GltfScene};
+
+/// Anything the `AssetServer` can load from a file.
+pub trait Asset: Send + Sync + Sized + 'static {
+    fn load(path: &Path) -> Result<Self>;
+}
+
+impl Asset for GltfScene {
+    fn load(path: &Path) -> Result<Self> {
+        GltfLoader::load_with_options(
+            path,
+            &GltfLoadOptions {
+                build_colliders: true,
+            },
+        )
+    }
+}
+
+impl Asset for image::DynamicImage {
+    fn load(path: &Path) -> Result<Self> {
+        Ok(image::open(path)?)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AssetId(u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
+    /// Every handle was dropped and the asset was freed.
+    Unloaded,
+}
+
+/// Reference to an asset of type `T`, the asset stays loaded while any clone of it is alive.
+pub struct Handle<T> {
+    id: AssetId,
+    reference: Arc<()>,
+    asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> AssetId {
+        self.id
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            reference: self.reference.clone(),
+            asset: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_tuple("Handle").field(&self.id).finish()
+    }
+}
+
+type LoadResult = std::result::Result<Box<dyn Any + Send + Sync>, String>;
+
+fn load_boxed<T: Asset>(path: &Path) -> LoadResult {
+    match T::load(path) {
+        Ok(asset) => Ok(Box::new(asset)),
+        Err(error) => Err(format!("{}: {:#}", path.display(), error)),
+    }
+}
+
+struct AssetEntry {
+    key: (TypeId, PathBuf),
+    /// Canonical path, changed files are matched against it when reloading.
+    path: PathBuf,
+    load: fn(&Path) -> LoadResult,
+    state: LoadState,
+    asset: Option<Box<dyn Any + Send + Sync>>,
+    /// Number of times the asset was (re)loaded successfully.
+    generation: u32,
+    /// Shared by all handles, the asset is unloaded once it has no strong references left.
+    references: Weak<()>,
+}
+
+/// Loads assets on a background thread pool and hands out `Handle`s to them. Loading the same
+/// path twice gives the same asset. Call `update` once a frame to pick up finished loads and
+/// free assets no handle refers to anymore.
+pub struct AssetServer {
+    thread_pool: ThreadPool,
+    loaded_sender: Sender<(AssetId, LoadResult)>,
+    loaded_receiver: Receiver<(AssetId, LoadResult)>,
+    entries: HashMap<AssetId, AssetEntry>,
+    ids: HashMap<(TypeId, PathBuf), AssetId>,
+    next_id: u64,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl AssetServer {
+    /// A `thread_count` of 0 uses one thread per CPU.
+    pub fn new(thread_count: usize) -> Self {
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .thread_name(|index| format!("asset loader {}", index))
+            .build()
+            .expect("failed creating asset loader threads?");
+        let (loaded_sender, loaded_receiver) = crossbeam_channel::unbounded();
+
+        Self {
+            thread_pool,
+            loaded_sender,
+            loaded_receiver,
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Starts loading the asset at `path` unless it is already loaded or loading.
+    pub fn load<T: Asset, P: AsRef<Path>>(&mut self, path: P) -> Handle<T> {
+        let key = (TypeId::of::<T>(), path.as_ref().to_path_buf());
+
+        if let Some(id) = self.ids.get(&key) {
+            let entry = self.entries.get_mut(id).expect("asset id without entry?");
+
+            // Dropped but not freed by `update` yet, so the asset can still be handed out
+            let reference = entry.references.upgrade().unwrap_or_else(|| {
+                let reference = Arc::new(());
+                entry.references = Arc::downgrade(&reference);
+                reference
+            });
+
+            return Handle {
+                id: *id,
+                reference,
+                asset: PhantomData,
+            };
+        }
+
+        let id = AssetId(self.next_id);
+        self.next_id += 1;
+
+        let reference = Arc::new(());
+        let path = path.as_ref().to_path_buf();
+        self.ids.insert(key.clone(), id);
+        self.entries.insert(
+            id,
+            AssetEntry {
+                key,
+                path: fs::canonicalize(&path).unwrap_or_else(|_| path.clone()),
+                load: load_boxed::<T>,
+                state: LoadState::Loading,
+                asset: None,
+                generation: 0,
+                references: Arc::downgrade(&reference),
+            },
+        );
+        self.start_load(id, path, load_boxed::<T>);
+
+        Handle {
+            id,
+            reference,
+            asset: PhantomData,
+        }
+    }
+
+    /// Loads every asset read from `path` again, returns whether there were any. Until the new
+    /// version is loaded, and if it fails to, the previous one stays in use.
+    pub fn reload(&mut self, path: &Path) -> bool {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let reloads = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.path == path)
+            .map(|(id, entry)| (*id, entry.load))
+            .collect::<Vec<_>>();
+
+        for (id, load) in &reloads {
+            self.start_load(*id, path.clone(), *load);
+        }
+
+        !reloads.is_empty()
+    }
+
+    fn start_load(&self, id: AssetId, path: PathBuf, load: fn(&Path) -> LoadResult) {
+        let loaded_sender = self.loaded_sender.clone();
+        self.thread_pool.spawn(move || {
+            // A panicking loader would take the whole pool down with it
+            let result = panic::catch_unwind(AssertUnwindSafe(|| load(&path)))
+                .unwrap_or_else(|_| Err(format!("{}: loader panicked", path.display())));
+
+            // The server being gone just means nobody is waiting for this anymore
+            let _ = loaded_sender.send((id, result));
+        });
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        self.entries
+            .get(&handle.id)
+            .map_or(LoadState::Unloaded, |entry| entry.state.clone())
+    }
+
+    /// The asset, if it finished loading successfully.
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T> {
+        self.entries
+            .get(&handle.id)
+            .and_then(|entry| entry.asset.as_ref())
+            .and_then(|asset| asset.downcast_ref::<T>())
+    }
+
+    /// Increases every time the asset is reloaded, to tell when something built from it is stale.
+    pub fn generation<T: Asset>(&self, handle: &Handle<T>) -> u32 {
+        self.entries
+            .get(&handle.id)
+            .map_or(0, |entry| entry.generation)
+    }
+
+    /// Blocks until the asset finished loading, for things that can't start without it.
+    pub fn wait<T: Asset>(&mut self, handle: &Handle<T>) -> Result<&T> {
+        while self.load_state(handle) == LoadState::Loading {
+            let (id, result) = self
+                .loaded_receiver
+                .recv()
+                .expect("asset loaded channel closed?");
+            self.finish_load(id, result);
+        }
+
+        match self.load_state(handle) {
+            LoadState::Failed(error) => bail!("Failed to load asset {}", error),
+            _ => self.get(handle).context("Asset was unloaded"),
+        }
+    }
+
+    /// Stores finished loads and frees the assets no handle refers to anymore.
+    pub fn update(&mut self) {
+        while let Ok((id, result)) = self.loaded_receiver.try_recv() {
+            self.finish_load(id, result);
+        }
+
+        let unreferenced = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.references.strong_count() == 0)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in unreferenced {
+            if let Some(entry) = self.entries.remove(&id) {
+                self.ids.remove(&entry.key);
+            }
+        }
+    }
+
+    fn finish_load(&mut self, id: AssetId, result: LoadResult) {
+        // Loads of assets that were freed while loading are thrown away
+        let entry = match self.entries.get_mut(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        match result {
+            Ok(asset) => {
+                entry.asset = Some(asset);
+                entry.state = LoadState::Loaded;
+                entry.generation += 1;
+            }
+            Err(error) if entry.asset.is_some() => {
+                log::warn!("asset failed to reload, keeping the old one: {}", error);
+            }
+            Err(error) => {
+                log::warn!("asset failed to load: {}", error);
+                entry.state = LoadState::Failed(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_directory::TestDirectory;
+    use std::fs;
+
+    struct Text(String);
+
+    impl Asset for Text {
+        fn load(path: &Path) -> Result<Self> {
+            Ok(Text(fs::read_to_string(path)?))
+        }
+    }
+
+    #[test]
+    fn loads_in_background_and_deduplicates() {
+        let directory = TestDirectory::new("asset_server");
+        let path = directory.write("text.txt", "hello");
+        let mut asset_server = AssetServer::new(1);
+
+        let handle = asset_server.load::<Text, _>(&path);
+        let again = asset_server.load::<Text, _>(&path);
+        assert_eq!(handle, again);
+
+        assert_eq!(asset_server.wait(&handle).unwrap().0, "hello");
+        assert_eq!(asset_server.load_state(&again), LoadState::Loaded);
+    }
+
+    #[test]
+    fn missing_file_fails() {
+        let mut asset_server = AssetServer::new(1);
+        let handle = asset_server.load::<Text, _>("./does/not/exist.txt");
+
+        assert!(asset_server.wait(&handle).is_err());
+        assert!(matches!(
+            asset_server.load_state(&handle),
+            LoadState::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn unloads_once_every_handle_is_dropped() {
+        let directory = TestDirectory::new("asset_server");
+        let path = directory.write("text.txt", "bye");
+        let mut asset_server = AssetServer::new(1);
+
+        let handle = asset_server.load::<Text, _>(&path);
+        let clone = handle.clone();
+        asset_server.wait(&handle).unwrap();
+
+        drop(handle);
+        asset_server.update();
+        assert_eq!(asset_server.load_state(&clone), LoadState::Loaded);
+
+        let id = clone.id();
+        drop(clone);
+        asset_server.update();
+
+        let reloaded = asset_server.load::<Text, _>(&path);
+        assert_ne!(reloaded.id(), id);
+    }
+
+    #[test]
+    fn reload_replaces_asset_unless_it_fails() {
+        let directory = TestDirectory::new("asset_server");
+        let path = directory.write("text.txt", "first");
+        let mut asset_server = AssetServer::new(1);
+        let handle = asset_server.load::<Text, _>(&path);
+        asset_server.wait(&handle).unwrap();
+
+        fs::write(&path, "second").unwrap();
+        assert!(asset_server.reload(&path));
+        while asset_server.generation(&handle) < 2 {
+            let (id, result) = asset_server.loaded_receiver.recv().unwrap();
+            asset_server.finish_load(id, result);
+        }
+        assert_eq!(asset_server.get(&handle).unwrap().0, "second");
+
+        fs::remove_file(&path).unwrap();
+        asset_server.reload(&path);
+        let (id, result) = asset_server.loaded_receiver.recv().unwrap();
+        assert!(result.is_err());
+        asset_server.finish_load(id, result);
+        assert_eq!(asset_server.get(&handle).unwrap().0, "second");
+        assert_eq!(asset_server.load_state(&handle), LoadState::Loaded);
+    }
+}
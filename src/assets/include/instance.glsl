@@ -0,0 +1,10 @@
+// Per instance, matches `INSTANCE_ATTRIBUTES` in src/renderer/instancing.rs. Vertex inputs can't
+// be matrices, so the model matrix comes in by column.
+layout(location=8) in vec4 i_model_0;
+layout(location=9) in vec4 i_model_1;
+layout(location=10) in vec4 i_model_2;
+layout(location=11) in vec4 i_model_3;
+
+mat4 instance_model() {
+    return mat4(i_model_0, i_model_1, i_model_2, i_model_3);
+}
@@ -0,0 +1,56 @@
+// Matches `GpuLight` in src/renderer/lights.rs
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+struct Light {
+    // w is the range, 0 for none
+    vec4 position_range;
+    // w is one of the LIGHT_ kinds
+    vec4 direction_kind;
+    vec4 color_intensity;
+    // x and y are the cosines of the inner and outer cone angles
+    vec4 cone;
+    // x is the first layer in `t_shadow_maps`, negative for none, y the number of layers, z the
+    // depth bias and w the slope bias
+    vec4 shadow;
+    // View depths up to which each cascade of a directional light is used
+    vec4 cascade_splits;
+};
+
+layout(set=1, binding=1) 
+readonly buffer Lights {
+    Light lights[];
+};
+
+// Light reaching `world_position`, and the direction back towards the light in `to_light`.
+vec3 incoming_light(Light light, vec3 world_position, out vec3 to_light) {
+    vec3 radiance = light.color_intensity.rgb * light.color_intensity.a;
+    int kind = int(light.direction_kind.w);
+
+    if (kind == LIGHT_DIRECTIONAL) {
+        to_light = -light.direction_kind.xyz;
+        return radiance;
+    }
+
+    vec3 offset = light.position_range.xyz - world_position;
+    float distance_squared = max(dot(offset, offset), 0.0001);
+    to_light = offset * inversesqrt(distance_squared);
+
+    // Inverse square falloff, windowed to reach zero at the range like KHR_lights_punctual
+    // suggests
+    float attenuation = 1.0 / distance_squared;
+    float range = light.position_range.w;
+    if (range > 0.0) {
+        float distance_ratio = distance_squared / (range * range);
+        float window = clamp(1.0 - distance_ratio * distance_ratio, 0.0, 1.0);
+        attenuation *= window * window;
+    }
+
+    if (kind == LIGHT_SPOT) {
+        float cos_angle = dot(light.direction_kind.xyz, -to_light);
+        attenuation *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+    }
+
+    return radiance * attenuation;
+}
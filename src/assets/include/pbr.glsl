@@ -0,0 +1,50 @@
+// Cook-Torrance with the GGX distribution, Smith-Schlick geometry and Schlick's Fresnel, the
+// metallic-roughness model of glTF.
+
+const float PI = 3.14159265359;
+// Reflectance of dielectrics looking straight at them
+const vec3 DIELECTRIC_F0 = vec3(0.04);
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha_squared = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Light of `radiance` arriving from `to_light` and leaving towards `to_camera`.
+vec3 shade(
+    vec3 normal,
+    vec3 to_camera,
+    vec3 to_light,
+    vec3 radiance,
+    vec3 base_color,
+    float metallic,
+    float roughness
+) {
+    vec3 halfway = normalize(to_camera + to_light);
+    float n_dot_l = max(dot(normal, to_light), 0.0);
+    float n_dot_v = max(dot(normal, to_camera), 0.0001);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+
+    vec3 f0 = mix(DIELECTRIC_F0, base_color, metallic);
+    vec3 fresnel = fresnel_schlick(max(dot(halfway, to_camera), 0.0), f0);
+    float distribution = distribution_ggx(n_dot_h, roughness);
+    float geometry =
+        geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+    vec3 specular = fresnel * distribution * geometry / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+    // Metals have no diffuse, whatever isn't reflected is absorbed
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color / PI;
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
@@ -0,0 +1,52 @@
+// Matches `ShadowPass` in src/renderer/shadows.rs, needs lights.glsl
+layout(set=2, binding=0) uniform texture2DArray t_shadow_maps;
+layout(set=2, binding=1) uniform samplerShadow s_shadow_maps;
+
+layout(set=2, binding=2)
+readonly buffer ShadowMaps {
+    // From world space to the clip space of each layer
+    mat4 shadow_view_projs[];
+};
+
+// How much of `light` reaches `world_position`, from 0 in full shadow to 1 fully lit.
+float shadow_factor(Light light, vec3 world_position, float view_depth, float n_dot_l) {
+    int first_layer = int(light.shadow.x);
+    int layer_count = int(light.shadow.y);
+    if (first_layer < 0 || n_dot_l <= 0.0) {
+        return 1.0;
+    }
+
+    // Cascades after the first cover slices further away from the camera
+    int cascade = 0;
+    while (cascade < layer_count - 1 && view_depth > light.cascade_splits[cascade]) {
+        cascade++;
+    }
+    int layer = first_layer + cascade;
+
+    vec4 clip = shadow_view_projs[layer] * vec4(world_position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    // Past the far end of the last cascade or outside the cone of a spot light
+    if (ndc.z > 1.0 || any(greaterThan(abs(ndc.xy), vec2(1.0)))) {
+        return 1.0;
+    }
+
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    // The steeper the light hits, the more depth changes across one texel
+    float tan_angle = sqrt(max(1.0 - n_dot_l * n_dot_l, 0.0)) / n_dot_l;
+    float reference = ndc.z - light.shadow.z - light.shadow.w * min(tan_angle, 10.0);
+
+    // 3x3 percentage closer filtering softens the texel staircase along shadow edges
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(t_shadow_maps, s_shadow_maps), 0).xy);
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * texel;
+            lit += texture(
+                sampler2DArrayShadow(t_shadow_maps, s_shadow_maps),
+                vec4(uv + offset, layer, reference)
+            );
+        }
+    }
+
+    return lit / 9.0;
+}
@@ -0,0 +1,10 @@
+// Per batch, matches `Uniforms` in src/renderer/mod.rs
+layout(set=1, binding=0) 
+uniform Uniforms {
+    mat4 u_view_proj;
+    vec4 u_camera_position;
+    // x is metallic, y is roughness
+    vec4 u_material;
+    // x is how many of `lights` are in use
+    uvec4 u_light_count;
+};
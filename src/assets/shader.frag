@@ -1,13 +1,40 @@
 #version 450
 
+#include "uniforms.glsl"
+#include "lights.glsl"
+#include "pbr.glsl"
+#include "shadows.glsl"
+
 layout(location=0) in vec2 v_tex_coords;
 layout(location=1) in vec3 v_normal;
+layout(location=2) in vec3 v_world_position;
 
 layout(location=0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
 
+// Stands in for light bouncing around the level, so faces turned away aren't pitch black
+const vec3 AMBIENT = vec3(0.03);
+
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords) * vec4(v_normal, 1.0);
-}
\ No newline at end of file
+    vec4 base_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+    float metallic = u_material.x;
+    float roughness = clamp(u_material.y, 0.04, 1.0);
+
+    vec3 normal = normalize(v_normal);
+    vec3 to_camera = normalize(u_camera_position.xyz - v_world_position);
+
+    // Clip space w is the view depth with a perspective projection
+    float view_depth = 1.0 / gl_FragCoord.w;
+
+    vec3 color = AMBIENT * base_color.rgb;
+    for (uint index = 0; index < u_light_count.x; index++) {
+        vec3 to_light;
+        vec3 radiance = incoming_light(lights[index], v_world_position, to_light);
+        radiance *= shadow_factor(lights[index], v_world_position, view_depth, dot(normal, to_light));
+        color += shade(normal, to_camera, to_light, radiance, base_color.rgb, metallic, roughness);
+    }
+
+    f_color = vec4(color, base_color.a);
+}
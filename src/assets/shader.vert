@@ -1,19 +1,23 @@
 #version 450
 
+#include "uniforms.glsl"
+
 layout(location=0) in vec3 a_position;
 layout(location=1) in vec3 a_normal;
 layout(location=2) in vec2 a_tex_coords;
+#include "instance.glsl"
 
 layout(location=0) out vec2 v_tex_coords;
 layout(location=1) out vec3 v_normal;
-
-layout(set=1, binding=0) 
-uniform Uniforms {
-    mat4 u_view_proj;
-};
+layout(location=2) out vec3 v_world_position;
 
 void main() {
+    mat4 model = instance_model();
+    vec4 world_position = model * vec4(a_position, 1.0);
+
     v_tex_coords = a_tex_coords;
-    v_normal = a_normal;
-    gl_Position = u_view_proj * vec4(a_position, 1.0);
-}
\ No newline at end of file
+    // Models are only moved and rotated, so normals can go through the model matrix as well
+    v_normal = mat3(model) * a_normal;
+    v_world_position = world_position.xyz;
+    gl_Position = u_view_proj * world_position;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+// Per layer, matches `ShadowUniforms` in src/renderer/shadows.rs
+layout(set=0, binding=0)
+uniform ShadowUniforms {
+    mat4 u_light_view_proj;
+};
+
+layout(location=0) in vec3 a_position;
+#include "instance.glsl"
+
+void main() {
+    gl_Position = u_light_view_proj * instance_model() * vec4(a_position, 1.0);
+}
@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
 use ultraviolet::*;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "CameraParameters", into = "CameraParameters")]
 pub struct Camera {
     pub aspect_w_div_h: f32,
     pub vertical_fov_in_rad: f32,
@@ -25,3 +28,34 @@ impl Camera {
         }
     }
 }
+
+/// What a `Camera` is saved as, the projection matrix is rebuilt from it on load.
+#[derive(Serialize, Deserialize)]
+struct CameraParameters {
+    aspect_w_div_h: f32,
+    vertical_fov_in_rad: f32,
+    z_near: f32,
+    z_far: f32,
+}
+
+impl From<CameraParameters> for Camera {
+    fn from(parameters: CameraParameters) -> Self {
+        Camera::new(
+            parameters.aspect_w_div_h,
+            parameters.vertical_fov_in_rad,
+            parameters.z_near,
+            parameters.z_far,
+        )
+    }
+}
+
+impl From<Camera> for CameraParameters {
+    fn from(camera: Camera) -> Self {
+        Self {
+            aspect_w_div_h: camera.aspect_w_div_h,
+            vertical_fov_in_rad: camera.vertical_fov_in_rad,
+            z_near: camera.z_near,
+            z_far: camera.z_far,
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+/// Marks an entity whose `Health` was depleted, removed again when it respawns.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Dead {
+    pub seconds_since_death: f32,
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_depleted(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    pub fn restore(&mut self) {
+        self.current = self.max;
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Armor {
+    pub current: f32,
+    pub max: f32,
+    /// Fraction of incoming damage taken by the armor instead of health.
+    pub absorption: f32,
+}
+
+impl Armor {
+    pub fn new(max: f32, absorption: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            absorption,
+        }
+    }
+
+    /// Absorbs what it can of `damage` and returns the remainder that goes through to health.
+    pub fn absorb(&mut self, damage: f32) -> f32 {
+        let absorbed = (damage * self.absorption).min(self.current);
+        self.current -= absorbed;
+
+        damage - absorbed
+    }
+
+    pub fn restore(&mut self) {
+        self.current = self.max;
+    }
+}
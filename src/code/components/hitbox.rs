@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use ultraviolet::Vec3;
+
+use crate::code::components::{position::Position, rotation::Rotation};
+use crate::collision::{Ray, Sphere};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HitRegion {
+    Head,
+    Body,
+    Limb,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct HitSphere {
+    pub offset: Vec3,
+    pub radius: f32,
+    pub region: HitRegion,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct DamageMultipliers {
+    pub head: f32,
+    pub body: f32,
+    pub limb: f32,
+}
+
+impl DamageMultipliers {
+    pub fn get(&self, region: HitRegion) -> f32 {
+        match region {
+            HitRegion::Head => self.head,
+            HitRegion::Body => self.body,
+            HitRegion::Limb => self.limb,
+        }
+    }
+}
+
+impl Default for DamageMultipliers {
+    fn default() -> Self {
+        Self {
+            head: 2.0,
+            body: 1.0,
+            limb: 0.75,
+        }
+    }
+}
+
+/// Shootable volume of an entity, made of spheres placed relative to its `Position`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hitbox {
+    pub spheres: Vec<HitSphere>,
+    pub damage_multipliers: DamageMultipliers,
+}
+
+impl Hitbox {
+    pub fn humanoid() -> Self {
+        Self {
+            spheres: vec![
+                HitSphere {
+                    offset: Vec3::new(0.0, 0.0, 0.0),
+                    radius: 0.15,
+                    region: HitRegion::Head,
+                },
+                HitSphere {
+                    offset: Vec3::new(0.0, -0.5, 0.0),
+                    radius: 0.35,
+                    region: HitRegion::Body,
+                },
+                HitSphere {
+                    offset: Vec3::new(0.0, -1.2, 0.0),
+                    radius: 0.3,
+                    region: HitRegion::Limb,
+                },
+            ],
+            damage_multipliers: DamageMultipliers::default(),
+        }
+    }
+
+    /// Returns the distance to and region of the closest sphere hit by the ray.
+    pub fn raycast(
+        &self,
+        position: Position,
+        rotation: Rotation,
+        ray: &Ray,
+        max_distance: f32,
+    ) -> Option<(f32, HitRegion)> {
+        self.spheres
+            .iter()
+            .filter_map(|sphere| {
+                let center = position + sphere.offset.rotated_by(rotation);
+                Sphere::new(center, sphere.radius)
+                    .intersect_ray(ray)
+                    .filter(|&distance| distance <= max_distance)
+                    .map(|distance| (distance, sphere.region))
+            })
+            .min_by(|(left, _), (right, _)| {
+                left.partial_cmp(right).unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+use ultraviolet::{Lerp, Slerp};
+
+use crate::code::components::{position::Position, rotation::Rotation};
+
+/// Samples kept per entity, far more than the interpolation delay ever needs.
+const MAX_SAMPLES: usize = 32;
+/// Jumps between consecutive samples further than this are teleports, like respawning,
+/// and are not interpolated across.
+const TELEPORT_DISTANCE: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone)]
+struct Sample {
+    tick: u32,
+    position: Position,
+    rotation: Rotation,
+}
+
+/// Server states of a remote entity, drawn a little in the past so there is always a newer
+/// state to interpolate towards.
+#[derive(Debug, Clone, Default)]
+pub struct InterpolationBuffer {
+    samples: VecDeque<Sample>,
+}
+
+impl InterpolationBuffer {
+    /// Snapshots can arrive out of order, samples older than the newest one are dropped.
+    pub fn push(&mut self, tick: u32, position: Position, rotation: Rotation) {
+        match self.samples.back() {
+            Some(newest) if newest.tick >= tick => return,
+            Some(newest) if (newest.position - position).mag() > TELEPORT_DISTANCE => {
+                self.samples.clear()
+            }
+            _ => {}
+        }
+
+        self.samples.push_back(Sample {
+            tick,
+            position,
+            rotation,
+        });
+
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// State at the fractional server tick `render_tick`. Past the newest sample the last known
+    /// velocity is continued for at most `max_extrapolation_ticks`, after that it holds still.
+    pub fn sample(
+        &self,
+        render_tick: f64,
+        max_extrapolation_ticks: f64,
+    ) -> Option<(Position, Rotation)> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+
+        if render_tick <= first.tick as f64 {
+            return Some((first.position, first.rotation));
+        }
+
+        if render_tick >= last.tick as f64 {
+            let previous = match self.samples.len() {
+                0 | 1 => return Some((last.position, last.rotation)),
+                len => self.samples[len - 2],
+            };
+
+            let velocity = (last.position - previous.position) / (last.tick - previous.tick) as f32;
+            let ticks_ahead = (render_tick - last.tick as f64).min(max_extrapolation_ticks);
+
+            return Some((last.position + velocity * ticks_ahead as f32, last.rotation));
+        }
+
+        let (from, to) = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .find(|(_, to)| render_tick < to.tick as f64)?;
+        let t = ((render_tick - from.tick as f64) / (to.tick - from.tick) as f64) as f32;
+
+        Some((
+            from.position.lerp(to.position, t),
+            from.rotation.slerp(to.rotation, t),
+        ))
+    }
+
+    /// Forgets samples no longer needed once drawing has moved past `render_tick`.
+    pub fn discard_before(&mut self, render_tick: f64) {
+        while self.samples.len() > 2 && (self.samples[1].tick as f64) <= render_tick {
+            self.samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ultraviolet::{Rotor3, Vec3};
+
+    #[test]
+    fn interpolation_blends_between_snapshots() {
+        let mut interpolation_buffer = InterpolationBuffer::default();
+        interpolation_buffer.push(10, Vec3::zero(), Rotor3::identity());
+        interpolation_buffer.push(12, Vec3::unit_x(), Rotor3::from_rotation_xz(1.0));
+        // Late duplicate of an older snapshot
+        interpolation_buffer.push(11, Vec3::unit_y(), Rotor3::identity());
+
+        let (position, rotation) = interpolation_buffer.sample(11.0, 0.0).unwrap();
+        assert!((position - Vec3::new(0.5, 0.0, 0.0)).mag() < 0.0001);
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let expected = forward.rotated_by(Rotor3::from_rotation_xz(0.5));
+        assert!((forward.rotated_by(rotation) - expected).mag() < 0.0001);
+
+        let (position, _) = interpolation_buffer.sample(5.0, 0.0).unwrap();
+        assert_eq!(position.x, 0.0);
+    }
+
+    #[test]
+    fn interpolation_extrapolates_briefly_on_loss() {
+        let mut interpolation_buffer = InterpolationBuffer::default();
+        interpolation_buffer.push(10, Vec3::zero(), Rotor3::identity());
+        interpolation_buffer.push(11, Vec3::new(0.1, 0.0, 0.0), Rotor3::identity());
+
+        let (position, _) = interpolation_buffer.sample(13.0, 5.0).unwrap();
+        assert!((position.x - 0.3).abs() < 0.0001);
+
+        // Stops after the extrapolation limit instead of running off forever
+        let (position, _) = interpolation_buffer.sample(100.0, 5.0).unwrap();
+        assert!((position.x - 0.6).abs() < 0.0001);
+    }
+
+    #[test]
+    fn interpolation_does_not_blend_across_teleports() {
+        let mut interpolation_buffer = InterpolationBuffer::default();
+        interpolation_buffer.push(10, Vec3::zero(), Rotor3::identity());
+        interpolation_buffer.push(11, Vec3::new(50.0, 0.0, 0.0), Rotor3::identity());
+
+        let (position, _) = interpolation_buffer.sample(10.5, 0.0).unwrap();
+        assert_eq!(position.x, 50.0);
+    }
+}
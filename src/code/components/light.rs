@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use ultraviolet::Vec3;
+
+/// Lights the scene from the entity's `Position`, shining along its `Rotation`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Whether the light casts shadows and how they are biased. Point lights never do.
+    #[serde(default)]
+    pub shadows: Option<ShadowSettings>,
+}
+
+/// The kinds of `KHR_lights_punctual`, angles are in radians.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Infinitely far away like the sun, only its direction matters.
+    Directional,
+    /// Shines in every direction, fading out completely at `range`. Without a range it fades
+    /// with the square of the distance and never quite reaches zero.
+    Point { range: Option<f32> },
+    /// A `Point` light limited to a cone, at full intensity up to `inner_cone_angle` from its
+    /// direction and fading out towards `outer_cone_angle`.
+    Spot {
+        range: Option<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// Shadow maps only store depth at texel centers, so surfaces end up shadowing themselves in
+/// stripes ("shadow acne") unless their depth is pulled towards the light a little. Too much
+/// bias detaches shadows from their casters instead ("peter panning").
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowSettings {
+    /// Subtracted from every depth, in the 0..1 depth range of the shadow map.
+    pub depth_bias: f32,
+    /// Also subtracted, scaled by how steeply the light hits the surface.
+    pub slope_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            depth_bias: 0.0005,
+            slope_bias: 0.001,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// How the `Model` of an entity is shaded, with the metallic-roughness model of glTF.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub diffuse_texture: PathBuf,
+    /// 0 for dielectrics like wood or plastic, 1 for bare metal.
+    #[serde(default)]
+    pub metallic: f32,
+    /// 0 is a perfect mirror, 1 is completely matte.
+    #[serde(default = "Material::default_roughness")]
+    pub roughness: f32,
+}
+
+impl Material {
+    pub fn new(diffuse_texture: PathBuf) -> Self {
+        Self {
+            diffuse_texture,
+            metallic: 0.0,
+            roughness: Self::default_roughness(),
+        }
+    }
+
+    pub fn default_roughness() -> f32 {
+        0.5
+    }
+}
@@ -1,2 +1,18 @@
+pub mod dead;
+pub mod health;
+pub mod hitbox;
+pub mod interpolation_buffer;
+pub mod light;
+pub mod material;
+pub mod model;
+pub mod parent;
+pub mod player;
+pub mod player_input;
 pub mod position;
-pub mod rotation;
\ No newline at end of file
+pub mod prediction_error;
+pub mod projectile;
+pub mod rotation;
+pub mod spawn_point;
+pub mod static_collider;
+pub mod team;
+pub mod weapon;
\ No newline at end of file
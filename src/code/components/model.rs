@@ -0,0 +1,9 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// glTF file with the meshes an entity is drawn with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    pub path: PathBuf,
+}
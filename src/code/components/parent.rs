@@ -0,0 +1,8 @@
+use legion::Entity;
+use serde::{Deserialize, Serialize};
+
+/// Entity this one was spawned under as a child of a prefab.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Parent {
+    pub entity: Entity,
+}
@@ -0,0 +1,7 @@
+use crate::net::packet::ClientId;
+
+/// Server side player entity of a connected client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Player {
+    pub client_id: ClientId,
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use ultraviolet::Vec2;
+
+use crate::code::components::rotation::Rotation;
+
+/// Inputs the server holds on to per player, a client sending more than this is dropping behind.
+const MAX_QUEUED_INPUTS: usize = 32;
+/// Extra inputs a player can bank by not having one ready in time, simulated on later ticks so
+/// a client whose inputs arrived late catches up. Beyond that it's one input per server tick.
+const MAX_CATCH_UP_INPUTS: u32 = 3;
+/// How many ticks a client's inputs may run ahead of the server, later ones are dropped until
+/// the server gets there.
+const MAX_INPUT_LEAD_TICKS: i64 = 16;
+
+/// Everything a player did during one fixed tick, simulated the same way on client and server.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    /// Sideways (x) and forward (y) movement, each component between -1 and 1 and at most 1 long.
+    pub movement: Vec2,
+    /// Where the player is looking, aiming stays client authoritative.
+    pub view: Rotation,
+    pub fire: bool,
+    pub reload: bool,
+    /// Server tick of the snapshot the client was showing, hitscan shots are checked against it.
+    pub view_tick: Option<u32>,
+}
+
+impl PlayerInput {
+    /// Limits an input received from a client to what its controls can produce, `None` when
+    /// it carries values that aren't numbers at all.
+    pub fn sanitized(mut self) -> Option<Self> {
+        let view = self.view;
+        let is_finite = [
+            self.movement.x,
+            self.movement.y,
+            view.s,
+            view.bv.xy,
+            view.bv.xz,
+            view.bv.yz,
+        ]
+        .iter()
+        .all(|value| value.is_finite());
+
+        if !is_finite || view.mag() == 0.0 {
+            return None;
+        }
+
+        self.movement = clamp_movement(self.movement);
+        self.view.normalize();
+
+        Some(self)
+    }
+}
+
+/// Keeps each movement component between -1 and 1 and diagonals as fast as straight lines.
+pub fn clamp_movement(movement: Vec2) -> Vec2 {
+    let movement = movement.clamped(Vec2::broadcast(-1.0), Vec2::broadcast(1.0));
+    let length = movement.mag();
+
+    if length > 1.0 {
+        movement / length
+    } else {
+        movement
+    }
+}
+
+/// Server side queue of inputs received from a player's client, keyed by client tick.
+#[derive(Debug, Clone, Default)]
+pub struct InputQueue {
+    inputs: BTreeMap<u32, PlayerInput>,
+    current: Vec<PlayerInput>,
+    /// Inputs that may still be simulated, one more every server tick.
+    budget: u32,
+    /// Client tick minus server tick when the first input arrived. Client ticks count from
+    /// whenever the client started, this maps them onto the server's.
+    tick_offset: Option<i64>,
+    pub last_processed_tick: Option<u32>,
+}
+
+impl InputQueue {
+    /// Inputs are sent redundantly, ticks already queued or processed are ignored, and so are
+    /// ticks too far ahead of `server_tick`. Inputs are sanitized on the way in, clients can send
+    /// anything.
+    pub fn push(&mut self, tick: u32, input: PlayerInput, server_tick: u32) {
+        if matches!(self.last_processed_tick, Some(processed) if tick <= processed) {
+            return;
+        }
+
+        let tick_offset = *self
+            .tick_offset
+            .get_or_insert(tick as i64 - server_tick as i64);
+        if tick as i64 > server_tick as i64 + tick_offset + MAX_INPUT_LEAD_TICKS {
+            return;
+        }
+
+        let input = match input.sanitized() {
+            Some(input) => input,
+            None => return,
+        };
+
+        self.inputs.entry(tick).or_insert(input);
+
+        while self.inputs.len() > MAX_QUEUED_INPUTS {
+            let oldest = *self.inputs.keys().next().expect("queue can't be empty?");
+            self.inputs.remove(&oldest);
+        }
+    }
+
+    /// Moves the inputs to simulate this tick out of the queue, so movement and weapons see
+    /// the same ones. That's one input, or a few more after ticks that had none.
+    pub fn advance(&mut self) {
+        self.current.clear();
+        self.budget = (self.budget + 1).min(1 + MAX_CATCH_UP_INPUTS);
+
+        while self.budget > 0 {
+            let tick = match self.inputs.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+
+            let input = self
+                .inputs
+                .remove(&tick)
+                .expect("queued tick without input?");
+            self.current.push(input);
+            self.last_processed_tick = Some(tick);
+            self.budget -= 1;
+        }
+    }
+
+    /// Inputs of this tick, oldest first.
+    pub fn current(&self) -> &[PlayerInput] {
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::systems::movement::{apply_player_input, MOVEMENT_SPEED_IN_METERS_PER_SECOND};
+    use crate::net::prediction::tests::{walk_forward, DELTA_TIME};
+    use ultraviolet::{Rotor3, Vec3};
+
+    #[test]
+    fn input_queue_ignores_repeated_and_old_inputs() {
+        let mut input_queue = InputQueue::default();
+        // Tags every input with its tick so we can tell them apart afterwards
+        let input = |tick: u32| PlayerInput {
+            view_tick: Some(tick),
+            ..walk_forward()
+        };
+        let current_ticks = |input_queue: &InputQueue| {
+            input_queue
+                .current()
+                .iter()
+                .map(|input| input.view_tick.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        input_queue.push(1, input(1), 100);
+        input_queue.push(2, input(2), 100);
+        input_queue.push(1, input(1), 100);
+
+        input_queue.advance();
+        assert_eq!(current_ticks(&input_queue), vec![1]);
+        assert_eq!(input_queue.last_processed_tick, Some(1));
+
+        // Redundant copies of processed inputs arrive with the next message
+        input_queue.push(1, input(1), 101);
+        input_queue.push(3, input(3), 101);
+        input_queue.advance();
+        assert_eq!(current_ticks(&input_queue), vec![2]);
+
+        input_queue.advance();
+        assert_eq!(current_ticks(&input_queue), vec![3]);
+
+        input_queue.advance();
+        assert!(input_queue.current().is_empty());
+
+        // Input 5 was late, so the tick without one lets 5 and 6 both go through
+        input_queue.push(5, input(5), 104);
+        input_queue.push(6, input(6), 104);
+        input_queue.advance();
+        assert_eq!(current_ticks(&input_queue), vec![5, 6]);
+    }
+
+    #[test]
+    fn input_queue_drops_inputs_from_the_future() {
+        let mut input_queue = InputQueue::default();
+
+        input_queue.push(10, walk_forward(), 500);
+        input_queue.push(10 + MAX_INPUT_LEAD_TICKS as u32 + 1, walk_forward(), 500);
+        input_queue.push(u32::MAX, walk_forward(), 500);
+
+        for _ in 0..4 {
+            input_queue.advance();
+        }
+        assert_eq!(input_queue.last_processed_tick, Some(10));
+    }
+
+    #[test]
+    fn flooding_inputs_moves_no_faster_than_one_input_per_tick() {
+        let mut input_queue = InputQueue::default();
+        let mut position = Vec3::zero();
+        let mut rotation = Rotor3::identity();
+        let server_ticks = 120;
+
+        let mut client_tick = 0;
+        for server_tick in 0..server_ticks {
+            // A cheating client sends ten inputs for every tick that passes
+            for _ in 0..10 {
+                input_queue.push(client_tick, walk_forward(), server_tick);
+                client_tick += 1;
+            }
+
+            input_queue.advance();
+            for player_input in input_queue.current() {
+                apply_player_input(&mut position, &mut rotation, player_input, DELTA_TIME);
+            }
+        }
+
+        let max_distance =
+            MOVEMENT_SPEED_IN_METERS_PER_SECOND * DELTA_TIME * server_ticks as f32 * (1.0 + 1e-6);
+        assert!(position.mag() <= max_distance);
+    }
+
+    #[test]
+    fn input_queue_sanitizes_client_inputs() {
+        let mut input_queue = InputQueue::default();
+        let speed_hack = PlayerInput {
+            movement: Vec2::new(1000.0, 1.0e6),
+            view: Rotor3::identity() * 50.0,
+            ..walk_forward()
+        };
+        let not_a_number = PlayerInput {
+            movement: Vec2::new(f32::NAN, 1.0),
+            ..walk_forward()
+        };
+        input_queue.push(0, speed_hack, 0);
+        input_queue.push(1, not_a_number, 0);
+        input_queue.advance();
+
+        assert_eq!(input_queue.current().len(), 1);
+        let input = input_queue.current()[0];
+        assert!((input.view.mag() - 1.0).abs() < 1e-6);
+
+        let mut position = Vec3::zero();
+        let mut rotation = Rotor3::identity();
+        apply_player_input(&mut position, &mut rotation, &input, DELTA_TIME);
+        assert!(position.mag() <= MOVEMENT_SPEED_IN_METERS_PER_SECOND * DELTA_TIME * (1.0 + 1e-6));
+    }
+}
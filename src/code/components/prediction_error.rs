@@ -0,0 +1,8 @@
+use ultraviolet::Vec3;
+
+/// Offset between where the local player was drawn and where reconciliation moved it.
+/// It is added when rendering and decays, so corrections blend in instead of snapping.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PredictionError {
+    pub offset: Vec3,
+}
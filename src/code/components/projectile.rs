@@ -0,0 +1,11 @@
+use legion::Entity;
+use serde::{Deserialize, Serialize};
+use ultraviolet::Vec3;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Projectile {
+    pub owner: Entity,
+    pub velocity: Vec3,
+    pub damage: f32,
+    pub seconds_remaining: f32,
+}
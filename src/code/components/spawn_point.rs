@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use ultraviolet::{Rotor3, Vec3};
+
+use crate::code::components::team::Team;
+
+/// Place in the level where players (re)spawn, `team` of `None` can be used by everyone.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SpawnPoint {
+    pub position: Vec3,
+    pub rotation: Rotor3,
+    pub team: Option<Team>,
+}
+
+impl SpawnPoint {
+    pub fn allows(&self, team: Option<Team>) -> bool {
+        self.team.is_none() || self.team == team
+    }
+}
@@ -0,0 +1,6 @@
+use crate::collision::TriangleMeshCollider;
+
+pub struct StaticCollider {
+    pub name: String,
+    pub mesh: TriangleMeshCollider,
+}
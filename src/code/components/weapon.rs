@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use ultraviolet::{Rotor3, Vec2};
+
+const FIRE_TIMING_TOLERANCE_IN_SECONDS: f32 = 1e-4;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum FireMode {
+    Hitscan {
+        range: f32,
+    },
+    Projectile {
+        speed: f32,
+        lifetime_in_seconds: f32,
+    },
+}
+
+/// Result of pulling the trigger, applied to the shooter's `Rotation` by the weapon system.
+#[derive(Debug, Copy, Clone)]
+pub struct Shot {
+    /// Local rotation of the shot direction away from where the shooter is looking.
+    pub spread: Rotor3,
+    /// Yaw and pitch kick in radians.
+    pub recoil: Vec2,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "SavedWeapon", into = "SavedWeapon")]
+pub struct Weapon {
+    pub fire_mode: FireMode,
+    pub damage: f32,
+    pub rounds_per_minute: f32,
+    pub magazine_size: u32,
+    pub reload_duration_in_seconds: f32,
+    /// Radius of the spread cone in radians.
+    pub spread_in_rad: f32,
+    /// Yaw and pitch kick in radians for each consecutive shot, the last entry repeats.
+    pub recoil_pattern: Vec<Vec2>,
+    /// Time without firing after which the recoil pattern starts over.
+    pub recoil_reset_in_seconds: f32,
+
+    pub rounds_in_magazine: u32,
+    pub seconds_until_next_shot: f32,
+    pub reload_seconds_remaining: Option<f32>,
+    pub consecutive_shots: usize,
+    pub seconds_since_last_shot: f32,
+    pub shots_fired: u32,
+}
+
+/// What a `Weapon` is saved as. Prefabs only spell out the configuration, a weapon loaded
+/// without its runtime state starts out with a full magazine, ready to fire.
+#[derive(Serialize, Deserialize)]
+struct SavedWeapon {
+    fire_mode: FireMode,
+    damage: f32,
+    rounds_per_minute: f32,
+    magazine_size: u32,
+    reload_duration_in_seconds: f32,
+    spread_in_rad: f32,
+    recoil_pattern: Vec<Vec2>,
+    recoil_reset_in_seconds: f32,
+
+    #[serde(default)]
+    rounds_in_magazine: Option<u32>,
+    #[serde(default)]
+    seconds_until_next_shot: f32,
+    #[serde(default)]
+    reload_seconds_remaining: Option<f32>,
+    #[serde(default)]
+    consecutive_shots: usize,
+    #[serde(default)]
+    seconds_since_last_shot: f32,
+    #[serde(default)]
+    shots_fired: u32,
+}
+
+impl From<SavedWeapon> for Weapon {
+    fn from(saved: SavedWeapon) -> Self {
+        Self {
+            fire_mode: saved.fire_mode,
+            damage: saved.damage,
+            rounds_per_minute: saved.rounds_per_minute,
+            magazine_size: saved.magazine_size,
+            reload_duration_in_seconds: saved.reload_duration_in_seconds,
+            spread_in_rad: saved.spread_in_rad,
+            recoil_pattern: saved.recoil_pattern,
+            recoil_reset_in_seconds: saved.recoil_reset_in_seconds,
+
+            rounds_in_magazine: saved.rounds_in_magazine.unwrap_or(saved.magazine_size),
+            seconds_until_next_shot: saved.seconds_until_next_shot,
+            reload_seconds_remaining: saved.reload_seconds_remaining,
+            consecutive_shots: saved.consecutive_shots,
+            seconds_since_last_shot: saved.seconds_since_last_shot,
+            shots_fired: saved.shots_fired,
+        }
+    }
+}
+
+impl From<Weapon> for SavedWeapon {
+    fn from(weapon: Weapon) -> Self {
+        Self {
+            fire_mode: weapon.fire_mode,
+            damage: weapon.damage,
+            rounds_per_minute: weapon.rounds_per_minute,
+            magazine_size: weapon.magazine_size,
+            reload_duration_in_seconds: weapon.reload_duration_in_seconds,
+            spread_in_rad: weapon.spread_in_rad,
+            recoil_pattern: weapon.recoil_pattern,
+            recoil_reset_in_seconds: weapon.recoil_reset_in_seconds,
+
+            rounds_in_magazine: Some(weapon.rounds_in_magazine),
+            seconds_until_next_shot: weapon.seconds_until_next_shot,
+            reload_seconds_remaining: weapon.reload_seconds_remaining,
+            consecutive_shots: weapon.consecutive_shots,
+            seconds_since_last_shot: weapon.seconds_since_last_shot,
+            shots_fired: weapon.shots_fired,
+        }
+    }
+}
+
+impl Weapon {
+    pub fn new(
+        fire_mode: FireMode,
+        damage: f32,
+        rounds_per_minute: f32,
+        magazine_size: u32,
+        reload_duration_in_seconds: f32,
+    ) -> Self {
+        Self {
+            fire_mode,
+            damage,
+            rounds_per_minute,
+            magazine_size,
+            reload_duration_in_seconds,
+            spread_in_rad: 0.0,
+            recoil_pattern: Vec::new(),
+            recoil_reset_in_seconds: 0.3,
+
+            rounds_in_magazine: magazine_size,
+            seconds_until_next_shot: 0.0,
+            reload_seconds_remaining: None,
+            consecutive_shots: 0,
+            seconds_since_last_shot: 0.0,
+            shots_fired: 0,
+        }
+    }
+
+    pub fn rifle() -> Self {
+        let mut rifle = Weapon::new(FireMode::Hitscan { range: 200.0 }, 25.0, 600.0, 30, 2.0);
+        rifle.spread_in_rad = 0.5f32.to_radians();
+        rifle.recoil_pattern = vec![
+            Vec2::new(0.0, 0.01),
+            Vec2::new(0.002, 0.012),
+            Vec2::new(-0.004, 0.014),
+            Vec2::new(0.006, 0.01),
+            Vec2::new(-0.006, 0.008),
+        ];
+        rifle
+    }
+
+    pub fn rocket_launcher() -> Self {
+        Weapon::new(
+            FireMode::Projectile {
+                speed: 30.0,
+                lifetime_in_seconds: 5.0,
+            },
+            100.0,
+            60.0,
+            4,
+            3.0,
+        )
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reload_seconds_remaining.is_some()
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        // Only the part of this tick past when the next shot was due carries over to the one
+        // after, so fire rates that don't divide the tick rate don't round down, and weapons
+        // that were ready before this tick can't bank shots.
+        self.seconds_until_next_shot = if self.seconds_until_next_shot > 0.0 {
+            self.seconds_until_next_shot - delta_time
+        } else {
+            0.0
+        };
+        self.seconds_since_last_shot += delta_time;
+
+        if self.seconds_since_last_shot >= self.recoil_reset_in_seconds {
+            self.consecutive_shots = 0;
+        }
+
+        if let Some(remaining) = self.reload_seconds_remaining {
+            let remaining = remaining - delta_time;
+            if remaining <= 0.0 {
+                self.reload_seconds_remaining = None;
+                self.rounds_in_magazine = self.magazine_size;
+            } else {
+                self.reload_seconds_remaining = Some(remaining);
+            }
+        }
+    }
+
+    pub fn start_reload(&mut self) {
+        if !self.is_reloading() && self.rounds_in_magazine < self.magazine_size {
+            self.reload_seconds_remaining = Some(self.reload_duration_in_seconds);
+        }
+    }
+
+    pub fn try_fire(&mut self) -> Option<Shot> {
+        // Ticks don't add up to shot intervals exactly in floating point, a shot due a rounding
+        // error from now is due now
+        if self.is_reloading() || self.seconds_until_next_shot > FIRE_TIMING_TOLERANCE_IN_SECONDS {
+            return None;
+        }
+
+        if self.rounds_in_magazine == 0 {
+            self.start_reload();
+            return None;
+        }
+
+        self.rounds_in_magazine -= 1;
+        self.seconds_until_next_shot += 60.0 / self.rounds_per_minute;
+        self.seconds_since_last_shot = 0.0;
+
+        let recoil = self
+            .recoil_pattern
+            .get(self.consecutive_shots)
+            .or_else(|| self.recoil_pattern.last())
+            .copied()
+            .unwrap_or_default();
+
+        let spread_offset = spread_disk_sample(self.shots_fired) * self.spread_in_rad;
+        let spread =
+            Rotor3::from_rotation_xz(spread_offset.x) * Rotor3::from_rotation_yz(spread_offset.y);
+
+        self.consecutive_shots += 1;
+        self.shots_fired = self.shots_fired.wrapping_add(1);
+
+        Some(Shot { spread, recoil })
+    }
+}
+
+/// Uniform sample of the unit disk derived from the shot number, so the same shot
+/// spreads the same way wherever it is simulated.
+fn spread_disk_sample(shot_number: u32) -> Vec2 {
+    let radius = hash_to_unit(shot_number.wrapping_mul(2)).sqrt();
+    let angle = hash_to_unit(shot_number.wrapping_mul(2).wrapping_add(1)) * std::f32::consts::TAU;
+
+    Vec2::new(angle.cos(), angle.sin()) * radius
+}
+
+// PCG hash, mapped to [0, 1)
+fn hash_to_unit(value: u32) -> f32 {
+    let state = value.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    let hash = (word >> 22) ^ word;
+
+    (hash >> 8) as f32 / (1u32 << 24) as f32
+}
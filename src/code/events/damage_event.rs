@@ -0,0 +1,20 @@
+use legion::Entity;
+use ultraviolet::Vec3;
+
+use crate::code::components::hitbox::HitRegion;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DamageType {
+    Bullet,
+    Explosion,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DamageEvent {
+    pub source: Entity,
+    pub target: Entity,
+    pub amount: f32,
+    pub damage_type: DamageType,
+    pub hit_location: Vec3,
+    pub hit_region: HitRegion,
+}
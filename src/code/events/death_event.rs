@@ -0,0 +1,10 @@
+use legion::Entity;
+
+use crate::code::events::damage_event::DamageType;
+
+#[derive(Debug, Copy, Clone)]
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub killer: Entity,
+    pub damage_type: DamageType,
+}
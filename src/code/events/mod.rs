@@ -0,0 +1,3 @@
+pub mod damage_event;
+pub mod death_event;
+pub mod respawn_event;
\ No newline at end of file
@@ -0,0 +1,6 @@
+use legion::Entity;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RespawnEvent {
+    pub entity: Entity,
+}
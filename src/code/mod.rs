@@ -1,2 +1,3 @@
 pub mod components;
+pub mod events;
 pub mod systems;
\ No newline at end of file
@@ -0,0 +1,8 @@
+use legion::system;
+
+use crate::asset_server::AssetServer;
+
+#[system]
+pub fn update_assets(#[resource] asset_server: &mut AssetServer) {
+    asset_server.update();
+}
@@ -0,0 +1,257 @@
+use legion::{system, systems::CommandBuffer, world::SubWorld, EntityStore};
+
+use crate::code::components::{
+    dead::Dead,
+    health::{Armor, Health},
+    hitbox::Hitbox,
+};
+use crate::code::events::{
+    damage_event::DamageEvent, death_event::DeathEvent, respawn_event::RespawnEvent,
+};
+use crate::events::Events;
+use crate::game_clock::GameClock;
+
+#[system]
+#[write_component(Health)]
+#[write_component(Armor)]
+#[read_component(Hitbox)]
+#[read_component(Dead)]
+pub fn apply_damage(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] damage_events: &mut Events<DamageEvent>,
+    #[resource] death_events: &mut Events<DeathEvent>,
+) {
+    for damage_event in damage_events.drain() {
+        let mut entry = match world.entry_mut(damage_event.target) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        // Dead entities and ones killed by an earlier event this tick take no more damage
+        let alive = entry.get_component::<Dead>().is_err()
+            && matches!(entry.get_component::<Health>(), Ok(health) if !health.is_depleted());
+        if !alive {
+            continue;
+        }
+
+        let multiplier = entry.get_component::<Hitbox>().map_or(1.0, |hitbox| {
+            hitbox.damage_multipliers.get(damage_event.hit_region)
+        });
+        let mut damage = damage_event.amount * multiplier;
+
+        if let Ok(armor) = entry.get_component_mut::<Armor>() {
+            damage = armor.absorb(damage);
+        }
+
+        let health = entry
+            .get_component_mut::<Health>()
+            .expect("alive entity without health?");
+        health.current -= damage;
+
+        if health.is_depleted() {
+            commands.add_component(damage_event.target, Dead::default());
+            death_events.send(DeathEvent {
+                entity: damage_event.target,
+                killer: damage_event.source,
+                damage_type: damage_event.damage_type,
+            });
+        }
+    }
+}
+
+#[system(for_each)]
+pub fn update_dead(#[resource] game_clock: &GameClock, dead: &mut Dead) {
+    dead.seconds_since_death += game_clock.fixed_update_step_duration as f32;
+}
+
+#[system]
+#[write_component(Health)]
+#[write_component(Armor)]
+#[read_component(Dead)]
+pub fn respawn(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] respawn_events: &mut Events<RespawnEvent>,
+) {
+    for respawn_event in respawn_events.drain() {
+        let mut entry = match world.entry_mut(respawn_event.entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        if entry.get_component::<Dead>().is_err() {
+            continue;
+        }
+
+        if let Ok(health) = entry.get_component_mut::<Health>() {
+            health.restore();
+        }
+        if let Ok(armor) = entry.get_component_mut::<Armor>() {
+            armor.restore();
+        }
+
+        commands.remove_component::<Dead>(respawn_event.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::hitbox::HitRegion;
+    use crate::code::events::damage_event::DamageType;
+    use legion::{Entity, Resources, Schedule, World};
+    use ultraviolet::Vec3;
+
+    fn setup() -> (World, Resources, Schedule) {
+        let mut resources = Resources::default();
+        resources.insert(GameClock::new(60));
+        resources.insert(Events::<DamageEvent>::default());
+        resources.insert(Events::<DeathEvent>::default());
+        resources.insert(Events::<RespawnEvent>::default());
+
+        let schedule = Schedule::builder()
+            .add_system(apply_damage_system())
+            .add_system(update_dead_system())
+            .add_system(respawn_system())
+            .build();
+
+        (World::default(), resources, schedule)
+    }
+
+    fn damage(
+        resources: &mut Resources,
+        source: Entity,
+        target: Entity,
+        amount: f32,
+        region: HitRegion,
+    ) {
+        resources
+            .get_mut::<Events<DamageEvent>>()
+            .unwrap()
+            .send(DamageEvent {
+                source,
+                target,
+                amount,
+                damage_type: DamageType::Bullet,
+                hit_location: Vec3::zero(),
+                hit_region: region,
+            });
+    }
+
+    fn health(world: &World, entity: Entity) -> f32 {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Health>()
+            .unwrap()
+            .current
+    }
+
+    fn is_dead(world: &World, entity: Entity) -> bool {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Dead>()
+            .is_ok()
+    }
+
+    #[test]
+    fn hitbox_multiplier_scales_damage() {
+        let (mut world, mut resources, mut schedule) = setup();
+        let shooter = world.push((Health::new(100.0),));
+        let target = world.push((Health::new(100.0), Hitbox::humanoid()));
+
+        damage(&mut resources, shooter, target, 20.0, HitRegion::Head);
+        schedule.execute(&mut world, &mut resources);
+
+        assert_eq!(health(&world, target), 60.0);
+    }
+
+    #[test]
+    fn armor_absorbs_until_depleted() {
+        let (mut world, mut resources, mut schedule) = setup();
+        let shooter = world.push((Health::new(100.0),));
+        let target = world.push((Health::new(100.0), Armor::new(10.0, 0.5)));
+
+        damage(&mut resources, shooter, target, 40.0, HitRegion::Body);
+        schedule.execute(&mut world, &mut resources);
+
+        // Armor can only take 10 of the 20 it would absorb
+        assert_eq!(health(&world, target), 70.0);
+        let armor = *world
+            .entry_ref(target)
+            .unwrap()
+            .get_component::<Armor>()
+            .unwrap();
+        assert_eq!(armor.current, 0.0);
+    }
+
+    #[test]
+    fn lethal_damage_kills_once() {
+        let (mut world, mut resources, mut schedule) = setup();
+        let shooter = world.push((Health::new(100.0),));
+        let target = world.push((Health::new(50.0),));
+
+        damage(&mut resources, shooter, target, 60.0, HitRegion::Body);
+        damage(&mut resources, shooter, target, 60.0, HitRegion::Body);
+        schedule.execute(&mut world, &mut resources);
+
+        assert!(is_dead(&world, target));
+        assert_eq!(health(&world, target), -10.0);
+
+        let death_events = resources.get::<Events<DeathEvent>>().unwrap();
+        assert_eq!(death_events.len(), 1);
+        let death_event = death_events.iter().next().unwrap();
+        assert_eq!(death_event.entity, target);
+        assert_eq!(death_event.killer, shooter);
+    }
+
+    #[test]
+    fn dead_entities_ignore_damage_and_count_time() {
+        let (mut world, mut resources, mut schedule) = setup();
+        let shooter = world.push((Health::new(100.0),));
+        let target = world.push((Health::new(50.0),));
+
+        damage(&mut resources, shooter, target, 50.0, HitRegion::Body);
+        schedule.execute(&mut world, &mut resources);
+        damage(&mut resources, shooter, target, 50.0, HitRegion::Body);
+        schedule.execute(&mut world, &mut resources);
+
+        assert_eq!(health(&world, target), 0.0);
+        assert_eq!(resources.get::<Events<DeathEvent>>().unwrap().len(), 1);
+
+        let dead = *world
+            .entry_ref(target)
+            .unwrap()
+            .get_component::<Dead>()
+            .unwrap();
+        assert!(dead.seconds_since_death > 0.0);
+    }
+
+    #[test]
+    fn respawn_restores_health_and_armor() {
+        let (mut world, mut resources, mut schedule) = setup();
+        let shooter = world.push((Health::new(100.0),));
+        let target = world.push((Health::new(50.0), Armor::new(20.0, 0.5)));
+
+        damage(&mut resources, shooter, target, 200.0, HitRegion::Body);
+        schedule.execute(&mut world, &mut resources);
+        assert!(is_dead(&world, target));
+
+        resources
+            .get_mut::<Events<RespawnEvent>>()
+            .unwrap()
+            .send(RespawnEvent { entity: target });
+        schedule.execute(&mut world, &mut resources);
+
+        assert!(!is_dead(&world, target));
+        assert_eq!(health(&world, target), 50.0);
+        let armor = *world
+            .entry_ref(target)
+            .unwrap()
+            .get_component::<Armor>()
+            .unwrap();
+        assert_eq!(armor.current, 20.0);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+
+use legion::{component, system, world::SubWorld, Entity, IntoQuery};
+
+use crate::code::components::{dead::Dead, hitbox::Hitbox, position::Position, rotation::Rotation};
+use crate::code::systems::weapon::{raycast_hitboxes, raycast_level, WorldHit};
+use crate::collision::Ray;
+use crate::game_clock::GameClock;
+
+pub struct LagCompensationSettings {
+    /// Shots from clients further behind than this are checked against the oldest state kept,
+    /// so players with bad connections can't hit targets that long since took cover.
+    pub max_rewind_in_seconds: f32,
+}
+
+impl Default for LagCompensationSettings {
+    fn default() -> Self {
+        Self {
+            max_rewind_in_seconds: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedTransform {
+    pub entity: Entity,
+    pub position: Position,
+    pub rotation: Rotation,
+    pub hitbox: Hitbox,
+}
+
+#[derive(Debug)]
+struct RecordedTick {
+    tick: u32,
+    transforms: Vec<RecordedTransform>,
+}
+
+/// Where every living hitbox was at each of the last few fixed ticks.
+#[derive(Debug, Default)]
+pub struct TransformHistory {
+    ticks: VecDeque<RecordedTick>,
+}
+
+impl TransformHistory {
+    /// Adds the state at `tick` and forgets everything beyond the last `max_ticks` ticks.
+    pub fn record(&mut self, tick: u32, transforms: Vec<RecordedTransform>, max_ticks: usize) {
+        self.ticks.push_back(RecordedTick { tick, transforms });
+
+        while self.ticks.len() > max_ticks.max(1) {
+            self.ticks.pop_front();
+        }
+    }
+
+    pub fn oldest_tick(&self) -> Option<u32> {
+        self.ticks.front().map(|recorded| recorded.tick)
+    }
+
+    /// Transforms as of `tick`, clamped to the recorded window at both ends.
+    pub fn at(&self, tick: u32) -> &[RecordedTransform] {
+        self.ticks
+            .iter()
+            .rev()
+            .find(|recorded| recorded.tick <= tick)
+            .or_else(|| self.ticks.front())
+            .map_or(&[], |recorded| &recorded.transforms)
+    }
+
+    /// Closest hitbox along the ray as they were at `tick`.
+    pub fn raycast(
+        &self,
+        tick: u32,
+        ray: &Ray,
+        max_distance: f32,
+        ignore: Entity,
+    ) -> Option<WorldHit> {
+        let targets = self.at(tick).iter().map(|recorded| {
+            (
+                recorded.entity,
+                recorded.position,
+                recorded.rotation,
+                &recorded.hitbox,
+            )
+        });
+
+        raycast_hitboxes(targets, ray, max_distance, ignore)
+    }
+}
+
+/// `raycast_world` with the other players rewound to `tick`, the level never moves so it is
+/// tested as it is now.
+pub fn raycast_rewound(
+    world: &SubWorld,
+    transform_history: &TransformHistory,
+    tick: u32,
+    ray: &Ray,
+    max_distance: f32,
+    ignore: Entity,
+) -> Option<WorldHit> {
+    let level_hit = raycast_level(world, ray, max_distance);
+    let max_distance = level_hit.map_or(max_distance, |hit| hit.distance);
+
+    transform_history
+        .raycast(tick, ray, max_distance, ignore)
+        .or(level_hit)
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[read_component(Hitbox)]
+#[read_component(Dead)]
+pub fn record_transforms(
+    world: &mut SubWorld,
+    #[resource] game_clock: &GameClock,
+    #[resource] settings: &LagCompensationSettings,
+    #[resource] transform_history: &mut TransformHistory,
+) {
+    let transforms = <(Entity, &Position, Option<&Rotation>, &Hitbox)>::query()
+        .filter(!component::<Dead>())
+        .iter(world)
+        .map(|(entity, position, rotation, hitbox)| RecordedTransform {
+            entity: *entity,
+            position: *position,
+            rotation: rotation.copied().unwrap_or_else(Rotation::identity),
+            hitbox: hitbox.clone(),
+        })
+        .collect();
+
+    let max_ticks = (settings.max_rewind_in_seconds as f64 / game_clock.fixed_update_step_duration)
+        .ceil() as usize
+        + 1;
+
+    transform_history.record(game_clock.fixed_update_tick, transforms, max_ticks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::systems::weapon::HitTarget;
+    use legion::World;
+    use ultraviolet::Vec3;
+
+    fn recorded(entity: Entity, x: f32) -> RecordedTransform {
+        RecordedTransform {
+            entity,
+            position: Vec3::new(x, 0.0, 0.0),
+            rotation: Rotation::identity(),
+            hitbox: Hitbox::humanoid(),
+        }
+    }
+
+    /// Target walks one meter along x per tick, starting at x = 0 on tick 10.
+    fn walking_target() -> (Entity, Entity, TransformHistory) {
+        let mut world = World::default();
+        let shooter = world.push((Position::zero(),));
+        let target = world.push((Position::zero(),));
+
+        let mut transform_history = TransformHistory::default();
+        for tick in 10..20 {
+            transform_history.record(tick, vec![recorded(target, (tick - 10) as f32)], 5);
+        }
+
+        (shooter, target, transform_history)
+    }
+
+    fn hits(hit: Option<WorldHit>, entity: Entity) -> bool {
+        matches!(hit, Some(WorldHit { target: HitTarget::Entity { entity: hit, .. }, .. }) if hit == entity)
+    }
+
+    #[test]
+    fn history_keeps_only_the_rewind_window() {
+        let (_, _, transform_history) = walking_target();
+
+        assert_eq!(transform_history.oldest_tick(), Some(15));
+        assert_eq!(transform_history.at(17)[0].position.x, 7.0);
+        // Too far back clamps to the oldest, ahead of the server clamps to the newest
+        assert_eq!(transform_history.at(3)[0].position.x, 5.0);
+        assert_eq!(transform_history.at(40)[0].position.x, 9.0);
+    }
+
+    #[test]
+    fn rewound_raycast_hits_where_the_target_was() {
+        let (shooter, target, transform_history) = walking_target();
+
+        // Aimed down the z axis at the body of where the target stood on tick 16
+        let ray = Ray::new(Vec3::new(6.0, -0.5, 10.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert!(hits(
+            transform_history.raycast(16, &ray, 100.0, shooter),
+            target
+        ));
+        assert!(!hits(
+            transform_history.raycast(19, &ray, 100.0, shooter),
+            target
+        ));
+        assert!(!hits(
+            transform_history.raycast(16, &ray, 100.0, target),
+            target
+        ));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod asset;
+pub mod health;
+pub mod lag_compensation;
+pub mod movement;
+pub mod network;
+pub mod render;
+pub mod spawn;
+pub mod weapon;
\ No newline at end of file
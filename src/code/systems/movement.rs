@@ -0,0 +1,97 @@
+use legion::{component, system};
+use ultraviolet::{Rotor3, Vec2, Vec3};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::camera::Camera;
+use crate::code::components::{
+    dead::Dead,
+    player_input::{clamp_movement, InputQueue, PlayerInput},
+    position::Position,
+    rotation::Rotation,
+};
+use crate::game_clock::GameClock;
+use crate::input::Input;
+
+pub const MOVEMENT_SPEED_IN_METERS_PER_SECOND: f32 = 5.0;
+
+/// Turns the keyboard state and the mouse movement since the last tick into a `PlayerInput`.
+pub fn sample_player_input(input: &mut Input, rotation: Rotation, delta_time: f32) -> PlayerInput {
+    let mouse_movement = std::mem::take(&mut input.mouse.unconsumed_movement);
+    let view = rotation * Rotor3::from_rotation_xz(mouse_movement.x * delta_time);
+
+    let mut movement = Vec2::default();
+
+    if input.key_held(VirtualKeyCode::W) {
+        movement.y += 1.0;
+    }
+    if input.key_held(VirtualKeyCode::S) {
+        movement.y -= 1.0;
+    }
+    if input.key_held(VirtualKeyCode::A) {
+        movement.x -= 1.0;
+    }
+    if input.key_held(VirtualKeyCode::D) {
+        movement.x += 1.0;
+    }
+
+    PlayerInput {
+        movement: clamp_movement(movement),
+        view,
+        fire: input.mouse_button_held(MouseButton::Left),
+        reload: input.key_held(VirtualKeyCode::R),
+        view_tick: None,
+    }
+}
+
+/// Moves a player by one tick of input. Has to stay deterministic, clients replay it when
+/// reconciling with the server.
+pub fn apply_player_input(
+    position: &mut Position,
+    rotation: &mut Rotation,
+    input: &PlayerInput,
+    delta_time: f32,
+) {
+    *rotation = input.view;
+
+    let right_vector = Vec3::new(1.0, 0.0, 0.0).rotated_by(*rotation);
+    let forward_vector = Vec3::new(0.0, 0.0, -1.0).rotated_by(*rotation);
+
+    *position += (input.movement.x * right_vector + input.movement.y * forward_vector)
+        * MOVEMENT_SPEED_IN_METERS_PER_SECOND
+        * delta_time;
+}
+
+#[system(for_each)]
+#[filter(component::<Camera>() & !component::<Dead>())]
+pub fn move_local_player(
+    #[resource] game_clock: &GameClock,
+    #[resource] input: &mut Input,
+    position: &mut Position,
+    rotation: &mut Rotation,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    let player_input = sample_player_input(input, *rotation, delta_time);
+    apply_player_input(position, rotation, &player_input, delta_time);
+}
+
+/// Simulates the inputs clients sent for their players on the server.
+#[system(for_each)]
+pub fn move_players(
+    #[resource] game_clock: &GameClock,
+    input_queue: &mut InputQueue,
+    position: &mut Position,
+    rotation: &mut Rotation,
+    dead: Option<&Dead>,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    input_queue.advance();
+
+    // Dead players' inputs still count as processed so the client's history moves on
+    if dead.is_none() {
+        for player_input in input_queue.current() {
+            apply_player_input(position, rotation, player_input, delta_time);
+        }
+    }
+}
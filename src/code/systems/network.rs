@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use legion::{
+    component, system, systems::CommandBuffer, world::SubWorld, Entity, EntityStore, IntoQuery,
+};
+use ultraviolet::Vec3;
+
+use crate::camera::Camera;
+use crate::code::components::{
+    dead::Dead,
+    health::{Armor, Health},
+    hitbox::Hitbox,
+    interpolation_buffer::InterpolationBuffer,
+    player::Player,
+    player_input::InputQueue,
+    position::Position,
+    prediction_error::PredictionError,
+    rotation::Rotation,
+    spawn_point::SpawnPoint,
+    team::Team,
+    weapon::Weapon,
+};
+use crate::code::systems::movement::{apply_player_input, sample_player_input};
+use crate::code::systems::spawn::{is_enemy, select_spawn_point};
+use crate::game_clock::GameClock;
+use crate::input::Input;
+use crate::net::client::{Client, ClientEvent};
+use crate::net::interpolation::{InterpolationClock, InterpolationSettings};
+use crate::net::message::{ClientMessage, ServerMessage};
+use crate::net::packet::{ChannelKind, ClientId};
+use crate::net::prediction::{
+    Prediction, INPUT_REDUNDANCY, PREDICTION_ERROR_DECAY_IN_SECONDS, SNAP_DISTANCE,
+};
+use crate::net::replication::{
+    ClientReplication, NetworkId, Replicated, ReplicationRegistry, ServerReplication, Snapshot,
+};
+use crate::net::server::{Server, ServerEvent};
+
+#[system]
+pub fn update_client(
+    #[resource] client: &mut Client,
+    #[resource] replication: &mut ClientReplication,
+    #[resource] prediction: &mut Prediction,
+) {
+    for event in client.update(Instant::now()) {
+        match event {
+            ClientEvent::Connected(client_id) => log::info!("connected as {:?}", client_id),
+            ClientEvent::Disconnected(reason) => log::info!("disconnected: {:?}", reason),
+            ClientEvent::Message(_, payload) => match ServerMessage::decode(&payload) {
+                Some(ServerMessage::Welcome { network_id }) => {
+                    replication.local_network_id = Some(network_id);
+                }
+                Some(ServerMessage::Snapshot {
+                    delta,
+                    last_input_tick,
+                }) => {
+                    if let Some(tick) = replication.receive(&delta) {
+                        prediction.server_input_tick = last_input_tick;
+                        client.send(
+                            ChannelKind::Unreliable,
+                            ClientMessage::SnapshotAck { tick }.encode(),
+                        );
+                    }
+                }
+                None => log::warn!("client received malformed message"),
+            },
+        }
+    }
+}
+
+fn team_for(client_id: ClientId) -> Team {
+    match client_id.0 % 2 {
+        0 => Team::Red,
+        _ => Team::Blue,
+    }
+}
+
+fn spawn_player(
+    world: &SubWorld,
+    commands: &mut CommandBuffer,
+    server: &mut Server,
+    replication: &mut ServerReplication,
+    client_id: ClientId,
+) {
+    let team = team_for(client_id);
+
+    let enemy_positions = <(&Position, Option<&Team>)>::query()
+        .filter(component::<Player>() & !component::<Dead>())
+        .iter(world)
+        .filter(|(_, other_team)| is_enemy(Some(team), other_team.copied()))
+        .map(|(position, _)| *position)
+        .collect::<Vec<_>>();
+    let (position, rotation) = select_spawn_point(
+        <&SpawnPoint>::query().iter(world),
+        Some(team),
+        &enemy_positions,
+    )
+    .map_or((Position::zero(), Rotation::identity()), |spawn_point| {
+        (spawn_point.position, spawn_point.rotation)
+    });
+
+    let network_id = replication.allocate_network_id();
+    let entity = commands.push((
+        position,
+        rotation,
+        Weapon::rifle(),
+        Health::new(100.0),
+        Armor::new(50.0, 0.5),
+        Hitbox::humanoid(),
+        team,
+        Player { client_id },
+    ));
+    commands.add_component(entity, InputQueue::default());
+    commands.add_component(entity, Replicated);
+    commands.add_component(entity, network_id);
+
+    replication.add_client(client_id);
+    server.send(
+        client_id,
+        ChannelKind::Reliable,
+        ServerMessage::Welcome { network_id }.encode(),
+    );
+}
+
+#[system]
+#[read_component(Player)]
+#[write_component(InputQueue)]
+#[read_component(Position)]
+#[read_component(Team)]
+#[read_component(Dead)]
+#[read_component(SpawnPoint)]
+pub fn update_server(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] game_clock: &GameClock,
+    #[resource] server: &mut Server,
+    #[resource] replication: &mut ServerReplication,
+) {
+    for event in server.update(Instant::now()) {
+        match event {
+            ServerEvent::ClientConnected(client_id) => {
+                log::info!("client {:?} connected", client_id);
+                spawn_player(world, commands, server, replication, client_id);
+            }
+            ServerEvent::ClientDisconnected(client_id, reason) => {
+                log::info!("client {:?} disconnected: {:?}", client_id, reason);
+                replication.remove_client(client_id);
+
+                for (entity, player) in <(Entity, &Player)>::query().iter(world) {
+                    if player.client_id == client_id {
+                        commands.remove(*entity);
+                    }
+                }
+            }
+            ServerEvent::Message(client_id, _, payload) => match ClientMessage::decode(&payload) {
+                Some(ClientMessage::SnapshotAck { tick }) => {
+                    replication.acknowledge(client_id, tick);
+                }
+                Some(ClientMessage::Input { inputs }) => {
+                    for (player, input_queue) in
+                        <(&Player, &mut InputQueue)>::query().iter_mut(world)
+                    {
+                        if player.client_id == client_id {
+                            for (tick, input) in inputs.iter() {
+                                input_queue.push(*tick, *input, game_clock.fixed_update_tick);
+                            }
+                        }
+                    }
+                }
+                None => log::warn!("client {:?} sent malformed message", client_id),
+            },
+        }
+    }
+}
+
+/// Gives entities marked `Replicated` after spawning a network id, so they show up in snapshots.
+#[system]
+#[read_component(Replicated)]
+#[read_component(NetworkId)]
+pub fn assign_network_ids(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] replication: &mut ServerReplication,
+) {
+    let mut unassigned =
+        <Entity>::query().filter(component::<Replicated>() & !component::<NetworkId>());
+    for entity in unassigned.iter(world) {
+        commands.add_component(*entity, replication.allocate_network_id());
+    }
+}
+
+#[system]
+#[read_component(NetworkId)]
+#[read_component(Replicated)]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[read_component(Health)]
+#[read_component(Dead)]
+#[read_component(Player)]
+#[read_component(InputQueue)]
+pub fn send_snapshots(
+    world: &mut SubWorld,
+    #[resource] game_clock: &GameClock,
+    #[resource] server: &mut Server,
+    #[resource] replication: &mut ServerReplication,
+    #[resource] registry: &ReplicationRegistry,
+) {
+    let mut snapshot = Snapshot::new(game_clock.fixed_update_tick);
+
+    let mut replicated = <(Entity, &NetworkId)>::query().filter(component::<Replicated>());
+    for (entity, network_id) in replicated.iter(world) {
+        if let Ok(entry) = world.entry_ref(*entity) {
+            snapshot
+                .entities
+                .insert(*network_id, registry.capture(&entry));
+        }
+    }
+
+    let last_input_ticks = <(&Player, &InputQueue)>::query()
+        .iter(world)
+        .map(|(player, input_queue)| (player.client_id, input_queue.last_processed_tick))
+        .collect::<HashMap<_, _>>();
+
+    let client_ids = server.client_ids().collect::<Vec<_>>();
+    for client_id in client_ids {
+        if let Some(delta) = replication.delta_for(client_id, &snapshot) {
+            let message = ServerMessage::Snapshot {
+                delta,
+                last_input_tick: last_input_ticks.get(&client_id).copied().flatten(),
+            };
+            server.send(client_id, ChannelKind::Unreliable, message.encode());
+        }
+    }
+}
+
+/// Writes the newest received snapshot into the client world. The local player is mapped onto
+/// the camera entity and reconciled with its predicted movement, remote entities get the new
+/// transform queued for interpolation.
+#[system]
+#[read_component(Camera)]
+#[write_component(Position)]
+#[write_component(Rotation)]
+#[write_component(PredictionError)]
+#[write_component(InterpolationBuffer)]
+pub fn apply_snapshots(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] game_clock: &GameClock,
+    #[resource] replication: &mut ClientReplication,
+    #[resource] prediction: &mut Prediction,
+    #[resource] registry: &ReplicationRegistry,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    let (snapshot, previous) = match replication.take_pending() {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    if let Some(local_network_id) = replication.local_network_id {
+        if replication.entity(local_network_id).is_none() {
+            let mut local_players = <Entity>::query().filter(component::<Camera>());
+            if let Some(entity) = local_players.iter(world).next() {
+                replication.map_entity(local_network_id, *entity);
+            }
+        }
+    }
+
+    let removed = replication
+        .mapped_network_ids()
+        .filter(|network_id| {
+            !snapshot.entities.contains_key(network_id)
+                && Some(*network_id) != replication.local_network_id
+        })
+        .collect::<Vec<_>>();
+    for network_id in removed {
+        if let Some(entity) = replication.unmap_entity(network_id) {
+            commands.remove(entity);
+        }
+    }
+
+    for (network_id, state) in snapshot.entities.iter() {
+        let is_local = Some(*network_id) == replication.local_network_id;
+        let position = registry.get::<Position>(state);
+        let rotation = registry.get::<Rotation>(state);
+
+        let entity = match replication.entity(*network_id) {
+            Some(entity) => entity,
+            None => {
+                let entity = commands.push((*network_id,));
+                registry.spawn(commands, entity, state);
+                if let (Some(position), Some(rotation)) = (position, rotation) {
+                    let mut interpolation_buffer = InterpolationBuffer::default();
+                    interpolation_buffer.push(snapshot.tick, position, rotation);
+                    commands.add_component(entity, interpolation_buffer);
+                }
+
+                replication.map_entity(*network_id, entity);
+                continue;
+            }
+        };
+
+        let previous_state = previous
+            .as_ref()
+            .and_then(|previous| previous.entities.get(network_id));
+        registry.apply(commands, entity, previous_state, state);
+
+        let mut entry = match world.entry_mut(entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        if is_local {
+            if let Some(authoritative) = position {
+                if let Some(corrected) = prediction.reconcile(authoritative, delta_time) {
+                    let position = entry
+                        .get_component_mut::<Position>()
+                        .expect("local player without position?");
+                    let error = *position - corrected;
+                    *position = corrected;
+
+                    // Teleported by the server, so take its rotation too and don't blend
+                    let snapped = error.mag() > SNAP_DISTANCE;
+                    if snapped {
+                        if let (Ok(rotation), Some(replicated)) =
+                            (entry.get_component_mut::<Rotation>(), rotation)
+                        {
+                            *rotation = replicated;
+                        }
+                    }
+                    if let Ok(prediction_error) = entry.get_component_mut::<PredictionError>() {
+                        prediction_error.offset = if snapped {
+                            Vec3::zero()
+                        } else {
+                            prediction_error.offset + error
+                        };
+                    }
+                }
+            }
+        } else if let (Ok(interpolation_buffer), Some(position), Some(rotation)) = (
+            entry.get_component_mut::<InterpolationBuffer>(),
+            position,
+            rotation,
+        ) {
+            interpolation_buffer.push(snapshot.tick, position, rotation);
+        }
+    }
+}
+
+/// Moves the local player from input right away and remembers the input for reconciliation.
+#[system(for_each)]
+#[filter(component::<Camera>() & !component::<Dead>())]
+pub fn predict_local_player(
+    #[resource] game_clock: &GameClock,
+    #[resource] input: &mut Input,
+    #[resource] client: &mut Client,
+    #[resource] prediction: &mut Prediction,
+    #[resource] interpolation_clock: &InterpolationClock,
+    position: &mut Position,
+    rotation: &mut Rotation,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    // Remote players are drawn at the interpolation clock, so that is what we aimed at
+    let mut player_input = sample_player_input(input, *rotation, delta_time);
+    player_input.view_tick = interpolation_clock
+        .render_tick()
+        .map(|render_tick| render_tick.round() as u32);
+    apply_player_input(position, rotation, &player_input, delta_time);
+
+    if !client.is_connected() {
+        return;
+    }
+
+    prediction.record(game_clock.fixed_update_tick, player_input, *position);
+    client.send(
+        ChannelKind::Unreliable,
+        ClientMessage::Input {
+            inputs: prediction.recent_inputs(INPUT_REDUNDANCY),
+        }
+        .encode(),
+    );
+}
+
+#[system(for_each)]
+pub fn smooth_prediction_error(
+    #[resource] game_clock: &GameClock,
+    prediction_error: &mut PredictionError,
+) {
+    let delta_time = game_clock.last_frame_duration.as_secs_f32();
+
+    prediction_error.offset *= (-delta_time / PREDICTION_ERROR_DECAY_IN_SECONDS).exp();
+}
+
+#[system]
+pub fn update_interpolation_clock(
+    #[resource] game_clock: &GameClock,
+    #[resource] replication: &ClientReplication,
+    #[resource] settings: &InterpolationSettings,
+    #[resource] interpolation_clock: &mut InterpolationClock,
+) {
+    interpolation_clock.advance(
+        replication.latest_tick(),
+        game_clock.last_frame_duration.as_secs_f64(),
+        game_clock.fixed_update_step_duration,
+        settings,
+    );
+}
+
+#[system(for_each)]
+pub fn interpolate_remote_entities(
+    #[resource] game_clock: &GameClock,
+    #[resource] settings: &InterpolationSettings,
+    #[resource] interpolation_clock: &InterpolationClock,
+    interpolation_buffer: &mut InterpolationBuffer,
+    position: &mut Position,
+    rotation: &mut Rotation,
+) {
+    let render_tick = match interpolation_clock.render_tick() {
+        Some(render_tick) => render_tick,
+        None => return,
+    };
+    let max_extrapolation_ticks =
+        settings.max_extrapolation_in_seconds as f64 / game_clock.fixed_update_step_duration;
+
+    if let Some((sampled_position, sampled_rotation)) =
+        interpolation_buffer.sample(render_tick, max_extrapolation_ticks)
+    {
+        *position = sampled_position;
+        *rotation = sampled_rotation;
+    }
+
+    interpolation_buffer.discard_before(render_tick);
+}
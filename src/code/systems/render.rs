@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use image::DynamicImage;
+use legion::{system, world::SubWorld, Entity, EntityStore, IntoQuery};
+use ultraviolet::{Isometry3, Rotor3};
+
+use crate::asset_server::{AssetServer, Handle, LoadState};
+use crate::camera::Camera;
+use crate::code::components::{
+    light::Light, material::Material, model::Model, parent::Parent, position::Position,
+    prediction_error::PredictionError, rotation::Rotation,
+};
+use crate::code::systems::weapon::forward_vector;
+use crate::gltf::GltfScene;
+use crate::renderer::culling::{self, MeshBounds};
+use crate::renderer::scene::{RenderCamera, RenderLight, RenderMesh, RenderScene};
+use crate::renderer::RenderBackend;
+
+/// Models and diffuse textures entities are drawn with, and which version of each the renderer
+/// was given. Assets stay loaded once something was drawn with them.
+#[derive(Default)]
+pub struct RenderAssets {
+    models: HashMap<PathBuf, (Handle<GltfScene>, u32)>,
+    diffuse_textures: HashMap<PathBuf, DiffuseTextureAsset>,
+}
+
+struct DiffuseTextureAsset {
+    image: Handle<DynamicImage>,
+    /// A model drawn with the texture, its material says how the texture is sampled.
+    model: PathBuf,
+    generation: u32,
+}
+
+/// Starts loading the models and diffuse textures of new `Model`s and `Material`s, and hands
+/// them to the renderer once they are loaded and again whenever they are reloaded.
+#[system]
+#[read_component(Model)]
+#[read_component(Material)]
+pub fn update_render_assets<B: RenderBackend + 'static>(
+    world: &SubWorld,
+    #[resource] asset_server: &mut AssetServer,
+    #[resource] render_assets: &mut RenderAssets,
+    #[resource] renderer: &mut B,
+    #[resource] mesh_bounds: &mut MeshBounds,
+) {
+    for (model, material) in <(&Model, Option<&Material>)>::query().iter(world) {
+        render_assets
+            .models
+            .entry(model.path.clone())
+            .or_insert_with(|| (asset_server.load(&model.path), 0));
+
+        if let Some(material) = material {
+            render_assets
+                .diffuse_textures
+                .entry(material.diffuse_texture.clone())
+                .or_insert_with(|| DiffuseTextureAsset {
+                    image: asset_server.load(&material.diffuse_texture),
+                    model: model.path.clone(),
+                    generation: 0,
+                });
+        }
+    }
+
+    for (path, (scene, generation)) in &mut render_assets.models {
+        let scene_generation = asset_server.generation(scene);
+        if scene_generation == *generation {
+            continue;
+        }
+
+        if let Some(scene) = asset_server.get(scene) {
+            mesh_bounds.insert(path.clone(), scene.bounds());
+            if let Err(error) = renderer.load_model(path, &scene.meshes) {
+                log::warn!("failed loading model {}: {:#}", path.display(), error);
+            }
+        }
+        *generation = scene_generation;
+    }
+
+    for (path, diffuse_texture) in &mut render_assets.diffuse_textures {
+        let image_generation = asset_server.generation(&diffuse_texture.image);
+        if image_generation == diffuse_texture.generation {
+            continue;
+        }
+
+        let scene = &render_assets.models[&diffuse_texture.model].0;
+        if asset_server.load_state(scene) == LoadState::Loading {
+            continue;
+        }
+        let sampler = asset_server
+            .get(scene)
+            .and_then(|scene| scene.meshes.first())
+            .map(|mesh| mesh.diffuse_sampler)
+            .unwrap_or_default();
+
+        if let Some(image) = asset_server.get(&diffuse_texture.image) {
+            if let Err(error) = renderer.load_diffuse_texture(path, image, &sampler) {
+                log::warn!(
+                    "failed loading diffuse texture {}: {:#}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        diffuse_texture.generation = image_generation;
+    }
+}
+
+/// Position and rotation in the world, for children of prefabs those are relative to their
+/// `Parent`.
+fn world_transform(world: &SubWorld, entity: Entity) -> Option<Isometry3> {
+    let entry = world.entry_ref(entity).ok()?;
+    let position = *entry.get_component::<Position>().ok()?;
+    let rotation = entry
+        .get_component::<Rotation>()
+        .map_or_else(|_| Rotor3::identity(), |rotation| *rotation);
+    let transform = Isometry3::new(position, rotation);
+
+    match entry.get_component::<Parent>() {
+        Ok(parent) => world_transform(world, parent.entity).map(|parent| parent * transform),
+        Err(_) => Some(transform),
+    }
+}
+
+/// Copies what is drawn this frame out of the world into the `RenderScene` resource.
+#[system]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[read_component(Parent)]
+#[read_component(Camera)]
+#[read_component(PredictionError)]
+#[read_component(Model)]
+#[read_component(Material)]
+#[read_component(Light)]
+pub fn extract_render_scene(world: &SubWorld, #[resource] render_scene: &mut RenderScene) {
+    render_scene.cameras.clear();
+    render_scene.meshes.clear();
+    render_scene.lights.clear();
+
+    let mut cameras = <(&Position, &Rotation, &Camera, Option<&PredictionError>)>::query();
+    for (position, rotation, camera, prediction_error) in cameras.iter(world) {
+        // The camera follows the smoothed position, not the one just corrected by the server
+        let position = match prediction_error {
+            Some(prediction_error) => *position + prediction_error.offset,
+            None => *position,
+        };
+
+        render_scene.cameras.push(RenderCamera {
+            position,
+            view: Isometry3::new(position, *rotation)
+                .inversed()
+                .into_homogeneous_matrix(),
+            projection: camera.projection_matrix,
+            z_near: camera.z_near,
+            z_far: camera.z_far,
+        });
+    }
+
+    let mut meshes = <(Entity, &Model, Option<&Material>)>::query();
+    for (entity, model, material) in meshes.iter(world) {
+        if let Some(transform) = world_transform(world, *entity) {
+            render_scene.meshes.push(RenderMesh {
+                model: model.path.clone(),
+                material: material.cloned(),
+                transform: transform.into_homogeneous_matrix(),
+                visible: true,
+            });
+        }
+    }
+
+    let mut lights = <(Entity, &Light)>::query();
+    for (entity, light) in lights.iter(world) {
+        if let Some(transform) = world_transform(world, *entity) {
+            render_scene.lights.push(RenderLight {
+                kind: light.kind,
+                position: transform.translation,
+                direction: forward_vector(transform.rotation),
+                color: light.color,
+                intensity: light.intensity,
+                shadows: light.shadows,
+            });
+        }
+    }
+}
+
+/// Marks the meshes the camera can't see, so backends skip drawing them.
+#[system]
+pub fn cull_render_scene(
+    #[resource] render_scene: &mut RenderScene,
+    #[resource] mesh_bounds: &MeshBounds,
+) {
+    render_scene.cull_stats = culling::cull(render_scene, mesh_bounds);
+}
+
+/// Hands the extracted scene to whichever backend the schedule was built with.
+#[system]
+pub fn render<B: RenderBackend + 'static>(
+    #[resource] backend: &mut B,
+    #[resource] render_scene: &RenderScene,
+) {
+    backend.render(render_scene);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::light::LightKind;
+    use crate::collision::Aabb;
+    use crate::renderer::culling::CullStats;
+    use crate::renderer::null::NullBackend;
+    use crate::renderer::test_backend::TestBackend;
+    use legion::{Resources, Schedule, World};
+    use std::path::PathBuf;
+    use ultraviolet::{Vec3, Vec4};
+
+    fn run(world: &mut World) -> RenderScene {
+        run_with_bounds(world, MeshBounds::default())
+    }
+
+    fn run_with_bounds(world: &mut World, mesh_bounds: MeshBounds) -> RenderScene {
+        let mut resources = Resources::default();
+        resources.insert(RenderScene::default());
+        resources.insert(mesh_bounds);
+        resources.insert(TestBackend::default());
+
+        let mut schedule = Schedule::builder()
+            .add_system(extract_render_scene_system())
+            .add_system(cull_render_scene_system())
+            .add_system(render_system::<TestBackend>())
+            .build();
+        schedule.execute(world, &mut resources);
+
+        let backend = resources.get::<TestBackend>().unwrap();
+        assert_eq!(backend.frames.len(), 1);
+        backend.frames[0].clone()
+    }
+
+    fn model(path: &str) -> Model {
+        Model {
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).mag() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn extracts_cameras_meshes_and_lights() {
+        let mut world = World::default();
+        world.push((
+            Position::new(0.0, 1.0, 5.0),
+            Rotation::identity(),
+            Camera::new(1.0, 1.0, 0.1, 100.0),
+        ));
+        world.push((
+            Position::new(1.0, 0.0, 0.0),
+            Rotation::identity(),
+            model("crate.gltf"),
+            Material::new(PathBuf::from("crate.png")),
+        ));
+        world.push((
+            Position::new(0.0, 10.0, 0.0),
+            Rotation::from_rotation_yz(-std::f32::consts::FRAC_PI_2),
+            Light {
+                kind: LightKind::Directional,
+                color: Vec3::one(),
+                intensity: 2.0,
+                shadows: None,
+            },
+        ));
+        // Not drawn, it has nowhere to be drawn at
+        world.push((model("floating.gltf"),));
+
+        let scene = run(&mut world);
+
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = scene.cameras[0];
+        assert_near(camera.position, Vec3::new(0.0, 1.0, 5.0));
+        // The camera looks down -z, so the mesh ends up 5 in front of it
+        let mesh_in_view = camera.view * scene.meshes[0].transform * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        assert_near(mesh_in_view.xyz(), Vec3::new(1.0, -1.0, -5.0));
+
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].model, PathBuf::from("crate.gltf"));
+        assert_eq!(
+            scene.meshes[0].material.as_ref().unwrap().diffuse_texture,
+            PathBuf::from("crate.png")
+        );
+
+        assert_eq!(scene.lights.len(), 1);
+        assert_near(scene.lights[0].direction, Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(scene.lights[0].kind, LightKind::Directional);
+        assert_eq!(scene.lights[0].intensity, 2.0);
+    }
+
+    #[test]
+    fn children_are_placed_relative_to_their_parent() {
+        let mut world = World::default();
+        let parent = world.push((
+            Position::new(0.0, 0.0, -10.0),
+            Rotation::from_rotation_xz(std::f32::consts::FRAC_PI_2),
+        ));
+        world.push((
+            Position::new(0.0, 0.0, -1.0),
+            Rotation::identity(),
+            Parent { entity: parent },
+            model("child.gltf"),
+        ));
+
+        let scene = run(&mut world);
+
+        assert!(scene.cameras.is_empty());
+        let origin = scene.meshes[0].transform * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let expected = Vec3::new(0.0, 0.0, -10.0)
+            + Vec3::new(0.0, 0.0, -1.0)
+                .rotated_by(Rotation::from_rotation_xz(std::f32::consts::FRAC_PI_2));
+        assert_near(origin.xyz(), expected);
+    }
+
+    #[test]
+    fn meshes_behind_the_camera_are_culled() {
+        let mut world = World::default();
+        world.push((
+            Position::new(0.0, 0.0, 0.0),
+            Rotation::identity(),
+            Camera::new(1.0, 1.0, 0.1, 100.0),
+        ));
+        for z in &[-5.0, 5.0] {
+            world.push((Position::new(0.0, 0.0, *z), model("crate.gltf")));
+        }
+
+        let mut mesh_bounds = MeshBounds::default();
+        mesh_bounds.insert(
+            PathBuf::from("crate.gltf"),
+            Aabb {
+                min: Vec3::broadcast(-0.5),
+                max: Vec3::broadcast(0.5),
+            },
+        );
+        let scene = run_with_bounds(&mut world, mesh_bounds);
+
+        assert_eq!(
+            scene.cull_stats,
+            CullStats {
+                tested: 2,
+                culled: 1
+            }
+        );
+        let visible = scene
+            .meshes
+            .iter()
+            .filter(|mesh| mesh.visible)
+            .collect::<Vec<_>>();
+        assert_eq!(visible.len(), 1);
+        let origin = visible[0].transform * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        assert_near(origin.xyz(), Vec3::new(0.0, 0.0, -5.0));
+    }
+
+    #[test]
+    fn runs_on_the_null_backend() {
+        let mut world = World::default();
+        world.push((
+            Position::new(0.0, 0.0, 5.0),
+            Rotation::identity(),
+            Camera::new(1.0, 1.0, 0.1, 100.0),
+        ));
+        world.push((
+            Position::zero(),
+            model("./src/assets/render_test_scene.gltf"),
+        ));
+
+        let mut resources = Resources::default();
+        resources.insert(AssetServer::new(1));
+        resources.insert(RenderAssets::default());
+        resources.insert(NullBackend);
+        resources.insert(MeshBounds::default());
+        resources.insert(RenderScene::default());
+        let mut schedule = Schedule::builder()
+            .add_system(update_render_assets_system::<NullBackend>())
+            .add_system(extract_render_scene_system())
+            .add_system(cull_render_scene_system())
+            .add_system(render_system::<NullBackend>())
+            .build();
+        schedule.execute(&mut world, &mut resources);
+
+        let scene = resources.get::<RenderScene>().unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        assert_eq!(scene.meshes.len(), 1);
+    }
+
+    #[test]
+    fn hands_loaded_models_and_textures_to_the_renderer() {
+        let model_path = PathBuf::from("./src/assets/render_test_scene.gltf");
+        let texture_path = PathBuf::from("./src/assets/cube_texture_uv.png");
+        let mut world = World::default();
+        world.push((
+            Position::zero(),
+            model("./src/assets/render_test_scene.gltf"),
+            Material::new(texture_path.clone()),
+        ));
+        world.push((
+            Position::zero(),
+            model("./src/assets/render_test_scene.gltf"),
+        ));
+
+        let mut resources = Resources::default();
+        resources.insert(AssetServer::new(1));
+        resources.insert(RenderAssets::default());
+        resources.insert(TestBackend::default());
+        resources.insert(MeshBounds::default());
+        let mut schedule = Schedule::builder()
+            .add_system(update_render_assets_system::<TestBackend>())
+            .build();
+
+        // Loads are only started, nothing reaches the renderer until they are done
+        schedule.execute(&mut world, &mut resources);
+        {
+            let mut asset_server = resources.get_mut::<AssetServer>().unwrap();
+            let scene = asset_server.load::<GltfScene, _>(&model_path);
+            asset_server.wait(&scene).unwrap();
+            let image = asset_server.load::<DynamicImage, _>(&texture_path);
+            asset_server.wait(&image).unwrap();
+        }
+        schedule.execute(&mut world, &mut resources);
+        schedule.execute(&mut world, &mut resources);
+
+        let backend = resources.get::<TestBackend>().unwrap();
+        assert_eq!(backend.loaded_models, vec![model_path.clone()]);
+        assert_eq!(backend.loaded_diffuse_textures, vec![texture_path]);
+        assert!(resources
+            .get::<MeshBounds>()
+            .unwrap()
+            .get(&model_path)
+            .is_some());
+    }
+}
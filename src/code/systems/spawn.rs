@@ -0,0 +1,281 @@
+use legion::{component, system, world::SubWorld, Entity, EntityStore, IntoQuery};
+
+use crate::code::components::{
+    dead::Dead, health::Health, position::Position, rotation::Rotation, spawn_point::SpawnPoint,
+    team::Team,
+};
+use crate::code::events::respawn_event::RespawnEvent;
+use crate::events::Events;
+
+pub struct SpawnSettings {
+    pub respawn_delay_in_seconds: f32,
+}
+
+impl Default for SpawnSettings {
+    fn default() -> Self {
+        Self {
+            respawn_delay_in_seconds: 3.0,
+        }
+    }
+}
+
+/// Without teams everyone is an enemy of everyone else.
+pub fn is_enemy(team: Option<Team>, other_team: Option<Team>) -> bool {
+    team.is_none() || other_team.is_none() || team != other_team
+}
+
+/// Picks the spawn point allowed for `team` that is furthest away from the closest enemy. When
+/// the level has none for `team` any spawn point will do, players have to appear somewhere.
+pub fn select_spawn_point<'a, I>(
+    spawn_points: I,
+    team: Option<Team>,
+    enemy_positions: &[Position],
+) -> Option<&'a SpawnPoint>
+where
+    I: IntoIterator<Item = &'a SpawnPoint>,
+{
+    let spawn_points = spawn_points.into_iter().collect::<Vec<_>>();
+    let allowed = spawn_points
+        .iter()
+        .copied()
+        .filter(|spawn_point| spawn_point.allows(team))
+        .collect::<Vec<_>>();
+    let candidates = if allowed.is_empty() {
+        spawn_points
+    } else {
+        allowed
+    };
+
+    let mut best: Option<(&SpawnPoint, f32)> = None;
+
+    for spawn_point in candidates {
+        let closest_enemy_distance_sq = enemy_positions
+            .iter()
+            .map(|enemy_position| (*enemy_position - spawn_point.position).mag_sq())
+            .fold(f32::INFINITY, f32::min);
+
+        match best {
+            Some((_, best_distance_sq)) if best_distance_sq >= closest_enemy_distance_sq => {}
+            _ => best = Some((spawn_point, closest_enemy_distance_sq)),
+        }
+    }
+
+    best.map(|(spawn_point, _)| spawn_point)
+}
+
+#[system]
+#[read_component(SpawnPoint)]
+#[read_component(Team)]
+#[read_component(Dead)]
+#[read_component(Health)]
+#[write_component(Position)]
+#[write_component(Rotation)]
+pub fn respawn_dead_players(
+    world: &mut SubWorld,
+    #[resource] spawn_settings: &SpawnSettings,
+    #[resource] respawn_events: &mut Events<RespawnEvent>,
+) {
+    let spawn_points = <&SpawnPoint>::query()
+        .iter(world)
+        .copied()
+        .collect::<Vec<_>>();
+
+    let living_players = <(Entity, &Position, Option<&Team>)>::query()
+        .filter(component::<Health>() & !component::<Dead>())
+        .iter(world)
+        .map(|(entity, position, team)| (*entity, *position, team.copied()))
+        .collect::<Vec<_>>();
+
+    let ready_to_respawn = <(Entity, &Dead, Option<&Team>)>::query()
+        .iter(world)
+        .filter(|(_, dead, _)| dead.seconds_since_death >= spawn_settings.respawn_delay_in_seconds)
+        .map(|(entity, _, team)| (*entity, team.copied()))
+        .collect::<Vec<_>>();
+
+    for (entity, team) in ready_to_respawn {
+        let enemy_positions = living_players
+            .iter()
+            .filter(|(other, _, other_team)| *other != entity && is_enemy(team, *other_team))
+            .map(|(_, position, _)| *position)
+            .collect::<Vec<_>>();
+
+        if let Some(spawn_point) = select_spawn_point(&spawn_points, team, &enemy_positions) {
+            if let Ok(mut entry) = world.entry_mut(entity) {
+                if let Ok(position) = entry.get_component_mut::<Position>() {
+                    *position = spawn_point.position;
+                }
+                if let Ok(rotation) = entry.get_component_mut::<Rotation>() {
+                    *rotation = spawn_point.rotation;
+                }
+            }
+        }
+
+        respawn_events.send(RespawnEvent { entity });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::systems::health::{respawn_system, update_dead_system};
+    use crate::game_clock::GameClock;
+    use legion::{Resources, Schedule, World};
+    use ultraviolet::{Rotor3, Vec3};
+
+    fn spawn_point(x: f32, team: Option<Team>) -> SpawnPoint {
+        SpawnPoint {
+            position: Vec3::new(x, 0.0, 0.0),
+            rotation: Rotor3::identity(),
+            team,
+        }
+    }
+
+    fn x(spawn_point: Option<&SpawnPoint>) -> Option<f32> {
+        spawn_point.map(|spawn_point| spawn_point.position.x)
+    }
+
+    #[test]
+    fn teams_are_enemies_unless_both_are_the_same() {
+        assert!(!is_enemy(Some(Team::Red), Some(Team::Red)));
+        assert!(is_enemy(Some(Team::Red), Some(Team::Blue)));
+        assert!(is_enemy(None, Some(Team::Red)));
+        assert!(is_enemy(None, None));
+    }
+
+    #[test]
+    fn picks_the_spawn_point_furthest_from_the_closest_enemy() {
+        let spawn_points = vec![
+            spawn_point(0.0, None),
+            spawn_point(10.0, None),
+            spawn_point(20.0, None),
+        ];
+
+        // 10 is furthest from the enemy at 1, but right next to the one at 11
+        let enemies = vec![Position::new(1.0, 0.0, 0.0), Position::new(11.0, 0.0, 0.0)];
+        assert_eq!(
+            x(select_spawn_point(&spawn_points, None, &enemies)),
+            Some(20.0)
+        );
+
+        let enemies = vec![Position::new(19.0, 0.0, 0.0)];
+        assert_eq!(
+            x(select_spawn_point(&spawn_points, None, &enemies)),
+            Some(0.0)
+        );
+
+        // Without enemies every spawn point is as good, the first one wins
+        assert_eq!(x(select_spawn_point(&spawn_points, None, &[])), Some(0.0));
+    }
+
+    #[test]
+    fn only_picks_spawn_points_of_the_team() {
+        let spawn_points = vec![
+            spawn_point(0.0, Some(Team::Red)),
+            spawn_point(10.0, Some(Team::Blue)),
+            spawn_point(20.0, None),
+        ];
+        let enemies = vec![Position::new(20.0, 0.0, 0.0)];
+
+        // The shared spawn point at 20 is next to the enemy, the blue one is further away
+        assert_eq!(
+            x(select_spawn_point(
+                &spawn_points,
+                Some(Team::Blue),
+                &enemies
+            )),
+            Some(10.0)
+        );
+        assert_eq!(
+            x(select_spawn_point(&spawn_points, Some(Team::Red), &enemies)),
+            Some(0.0)
+        );
+        // Players without a team only get shared spawn points
+        assert_eq!(
+            x(select_spawn_point(&spawn_points, None, &enemies)),
+            Some(20.0)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_any_spawn_point_when_none_are_for_the_team() {
+        let spawn_points = vec![
+            spawn_point(0.0, Some(Team::Red)),
+            spawn_point(10.0, Some(Team::Red)),
+        ];
+        let enemies = vec![Position::new(1.0, 0.0, 0.0)];
+
+        assert_eq!(
+            x(select_spawn_point(
+                &spawn_points,
+                Some(Team::Blue),
+                &enemies
+            )),
+            Some(10.0)
+        );
+        assert_eq!(x(select_spawn_point(&[], Some(Team::Blue), &enemies)), None);
+    }
+
+    #[test]
+    fn respawns_dead_players_after_the_delay() {
+        let mut world = World::default();
+        let mut resources = Resources::default();
+        resources.insert(GameClock::new(60));
+        resources.insert(SpawnSettings {
+            respawn_delay_in_seconds: 0.49,
+        });
+        resources.insert(Events::<RespawnEvent>::default());
+        let mut schedule = Schedule::builder()
+            .add_system(update_dead_system())
+            .add_system(respawn_dead_players_system())
+            .add_system(respawn_system())
+            .build();
+
+        world.push((spawn_point(0.0, Some(Team::Red)),));
+        world.push((spawn_point(50.0, Some(Team::Red)),));
+        world.push((
+            Position::new(45.0, 0.0, 0.0),
+            Health::new(100.0),
+            Team::Blue,
+        ));
+        let mut health = Health::new(100.0);
+        health.current = 0.0;
+        let dead = world.push((
+            Position::new(30.0, 0.0, 0.0),
+            Rotation::identity(),
+            health,
+            Team::Red,
+            Dead::default(),
+        ));
+        let is_dead = |world: &World| {
+            world
+                .entry_ref(dead)
+                .unwrap()
+                .get_component::<Dead>()
+                .is_ok()
+        };
+
+        // The 30th tick is the first with the player dead for longer than the delay
+        for _ in 0..29 {
+            schedule.execute(&mut world, &mut resources);
+        }
+        assert!(is_dead(&world));
+        assert_eq!(
+            *world
+                .entry_ref(dead)
+                .unwrap()
+                .get_component::<Position>()
+                .unwrap(),
+            Position::new(30.0, 0.0, 0.0)
+        );
+
+        schedule.execute(&mut world, &mut resources);
+        assert!(!is_dead(&world));
+        let entry = world.entry_ref(dead).unwrap();
+        // Away from the blue player at 45
+        assert_eq!(
+            *entry.get_component::<Position>().unwrap(),
+            Position::zero()
+        );
+        assert_eq!(entry.get_component::<Health>().unwrap().current, 100.0);
+    }
+}
@@ -0,0 +1,533 @@
+use legion::{component, system, systems::CommandBuffer, world::SubWorld, Entity, IntoQuery};
+use ultraviolet::{Rotor3, Vec3};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::camera::Camera;
+use crate::code::components::dead::Dead;
+use crate::code::components::{
+    hitbox::{HitRegion, Hitbox},
+    player_input::InputQueue,
+    position::Position,
+    projectile::Projectile,
+    rotation::Rotation,
+    static_collider::StaticCollider,
+    weapon::{FireMode, Shot, Weapon},
+};
+use crate::code::events::damage_event::{DamageEvent, DamageType};
+use crate::code::systems::lag_compensation::{raycast_rewound, TransformHistory};
+use crate::collision::Ray;
+use crate::events::Events;
+use crate::game_clock::GameClock;
+use crate::input::Input;
+
+#[derive(Debug, Copy, Clone)]
+pub enum HitTarget {
+    Level,
+    Entity { entity: Entity, region: HitRegion },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WorldHit {
+    pub distance: f32,
+    pub point: Vec3,
+    pub target: HitTarget,
+}
+
+pub fn forward_vector(rotation: Rotation) -> Vec3 {
+    Vec3::new(0.0, 0.0, -1.0).rotated_by(rotation)
+}
+
+pub fn raycast_level(world: &SubWorld, ray: &Ray, max_distance: f32) -> Option<WorldHit> {
+    <&StaticCollider>::query()
+        .iter(world)
+        .filter_map(|collider| collider.mesh.raycast(ray, max_distance))
+        .min_by(|left, right| {
+            left.distance
+                .partial_cmp(&right.distance)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|hit| WorldHit {
+            distance: hit.distance,
+            point: hit.point,
+            target: HitTarget::Level,
+        })
+}
+
+/// Closest hitbox along the ray. Takes the transforms explicitly so callers can test
+/// against positions other than the current ones.
+pub fn raycast_hitboxes<'a, I>(
+    targets: I,
+    ray: &Ray,
+    max_distance: f32,
+    ignore: Entity,
+) -> Option<WorldHit>
+where
+    I: IntoIterator<Item = (Entity, Position, Rotation, &'a Hitbox)>,
+{
+    targets
+        .into_iter()
+        .filter(|(entity, ..)| *entity != ignore)
+        .filter_map(|(entity, position, rotation, hitbox)| {
+            hitbox
+                .raycast(position, rotation, ray, max_distance)
+                .map(|(distance, region)| WorldHit {
+                    distance,
+                    point: ray.at(distance),
+                    target: HitTarget::Entity { entity, region },
+                })
+        })
+        .min_by(|left, right| {
+            left.distance
+                .partial_cmp(&right.distance)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+}
+
+pub fn raycast_world(
+    world: &SubWorld,
+    ray: &Ray,
+    max_distance: f32,
+    ignore: Entity,
+) -> Option<WorldHit> {
+    let level_hit = raycast_level(world, ray, max_distance);
+    let max_distance = level_hit.map_or(max_distance, |hit| hit.distance);
+
+    let mut targets =
+        <(Entity, &Position, Option<&Rotation>, &Hitbox)>::query().filter(!component::<Dead>());
+    let targets = targets
+        .iter(world)
+        .map(|(entity, position, rotation, hitbox)| {
+            (
+                *entity,
+                *position,
+                rotation.copied().unwrap_or_else(Rotor3::identity),
+                hitbox,
+            )
+        });
+
+    raycast_hitboxes(targets, ray, max_distance, ignore).or(level_hit)
+}
+
+fn send_damage(
+    damage_events: &mut Events<DamageEvent>,
+    source: Entity,
+    amount: f32,
+    damage_type: DamageType,
+    hit: &WorldHit,
+) {
+    if let HitTarget::Entity { entity, region } = hit.target {
+        damage_events.send(DamageEvent {
+            source,
+            target: entity,
+            amount,
+            damage_type,
+            hit_location: hit.point,
+            hit_region: region,
+        });
+    }
+}
+
+struct FiredShot {
+    shooter: Entity,
+    origin: Vec3,
+    direction: Vec3,
+    damage: f32,
+    fire_mode: FireMode,
+    /// Server tick the shooter was seeing, hitscan is checked against it when known.
+    view_tick: Option<u32>,
+}
+
+/// Runs the weapon for one tick of trigger and reload input, aiming along `rotation`.
+fn operate_weapon(
+    shooter: Entity,
+    position: Position,
+    rotation: Rotation,
+    weapon: &mut Weapon,
+    trigger_held: bool,
+    reload_held: bool,
+    delta_time: f32,
+) -> Option<(FiredShot, Shot)> {
+    weapon.update(delta_time);
+
+    if reload_held {
+        weapon.start_reload();
+    }
+
+    if !trigger_held {
+        return None;
+    }
+
+    weapon.try_fire().map(|shot| {
+        let fired_shot = FiredShot {
+            shooter,
+            origin: position,
+            direction: forward_vector(rotation * shot.spread),
+            damage: weapon.damage,
+            fire_mode: weapon.fire_mode,
+            view_tick: None,
+        };
+
+        (fired_shot, shot)
+    })
+}
+
+fn resolve_shots(
+    world: &SubWorld,
+    commands: &mut CommandBuffer,
+    damage_events: &mut Events<DamageEvent>,
+    transform_history: Option<&TransformHistory>,
+    fired_shots: Vec<FiredShot>,
+) {
+    for shot in fired_shots {
+        match shot.fire_mode {
+            FireMode::Hitscan { range } => {
+                let ray = Ray::new(shot.origin, shot.direction);
+                let hit = match (transform_history, shot.view_tick) {
+                    (Some(transform_history), Some(view_tick)) => raycast_rewound(
+                        world,
+                        transform_history,
+                        view_tick,
+                        &ray,
+                        range,
+                        shot.shooter,
+                    ),
+                    _ => raycast_world(world, &ray, range, shot.shooter),
+                };
+
+                if let Some(hit) = hit {
+                    send_damage(
+                        damage_events,
+                        shot.shooter,
+                        shot.damage,
+                        DamageType::Bullet,
+                        &hit,
+                    );
+                }
+            }
+            FireMode::Projectile {
+                speed,
+                lifetime_in_seconds,
+            } => {
+                commands.push((
+                    shot.origin,
+                    Projectile {
+                        owner: shot.shooter,
+                        velocity: shot.direction * speed,
+                        damage: shot.damage,
+                        seconds_remaining: lifetime_in_seconds,
+                    },
+                ));
+            }
+        }
+    }
+}
+
+#[system]
+#[read_component(Position)]
+#[write_component(Rotation)]
+#[write_component(Weapon)]
+#[read_component(Camera)]
+#[read_component(Dead)]
+#[read_component(Hitbox)]
+#[read_component(StaticCollider)]
+pub fn fire_weapons(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] game_clock: &GameClock,
+    #[resource] input: &Input,
+    #[resource] damage_events: &mut Events<DamageEvent>,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+    let trigger_held = input.mouse_button_held(MouseButton::Left);
+    let reload_held = input.key_held(VirtualKeyCode::R);
+
+    let mut fired_shots = Vec::new();
+
+    let mut weapons = <(Entity, &Position, &mut Rotation, &mut Weapon)>::query()
+        .filter(component::<Camera>() & !component::<Dead>());
+    for (entity, position, rotation, weapon) in weapons.iter_mut(world) {
+        let fired = operate_weapon(
+            *entity,
+            *position,
+            *rotation,
+            weapon,
+            trigger_held,
+            reload_held,
+            delta_time,
+        );
+
+        if let Some((fired_shot, shot)) = fired {
+            fired_shots.push(fired_shot);
+
+            *rotation = *rotation
+                * Rotor3::from_rotation_xz(shot.recoil.x)
+                * Rotor3::from_rotation_yz(shot.recoil.y);
+        }
+    }
+
+    resolve_shots(world, commands, damage_events, None, fired_shots);
+}
+
+/// Fires the weapons of client controlled players on the server from the inputs simulated this
+/// tick. Hitscan shots are lag compensated, recoil is already part of the view clients send.
+#[system]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[write_component(Weapon)]
+#[read_component(InputQueue)]
+#[read_component(Dead)]
+#[read_component(Hitbox)]
+#[read_component(StaticCollider)]
+pub fn fire_player_weapons(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] game_clock: &GameClock,
+    #[resource] transform_history: &TransformHistory,
+    #[resource] damage_events: &mut Events<DamageEvent>,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    let mut fired_shots = Vec::new();
+
+    let mut weapons =
+        <(Entity, &Position, &InputQueue, &mut Weapon)>::query().filter(!component::<Dead>());
+    for (entity, position, input_queue, weapon) in weapons.iter_mut(world) {
+        for player_input in input_queue.current() {
+            let fired = operate_weapon(
+                *entity,
+                *position,
+                player_input.view,
+                weapon,
+                player_input.fire,
+                player_input.reload,
+                delta_time,
+            );
+
+            if let Some((fired_shot, _)) = fired {
+                fired_shots.push(FiredShot {
+                    view_tick: player_input.view_tick,
+                    ..fired_shot
+                });
+            }
+        }
+    }
+
+    resolve_shots(
+        world,
+        commands,
+        damage_events,
+        Some(transform_history),
+        fired_shots,
+    );
+}
+
+#[system]
+#[write_component(Position)]
+#[write_component(Projectile)]
+#[read_component(Rotation)]
+#[read_component(Dead)]
+#[read_component(Hitbox)]
+#[read_component(StaticCollider)]
+pub fn update_projectiles(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] game_clock: &GameClock,
+    #[resource] damage_events: &mut Events<DamageEvent>,
+) {
+    let delta_time = game_clock.fixed_update_step_duration as f32;
+
+    let mut moved_projectiles = Vec::new();
+
+    let mut projectiles = <(Entity, &mut Position, &mut Projectile)>::query();
+    for (entity, position, projectile) in projectiles.iter_mut(world) {
+        let start = *position;
+        *position += projectile.velocity * delta_time;
+        projectile.seconds_remaining -= delta_time;
+
+        moved_projectiles.push((*entity, start, *projectile));
+    }
+
+    for (entity, start, projectile) in moved_projectiles {
+        // Sweep the distance travelled this step so fast projectiles can't tunnel through targets.
+        // Projectiles at rest have no direction to sweep along, they only run out of time.
+        let hit = if projectile.velocity.mag_sq() > f32::EPSILON {
+            let step_distance = projectile.velocity.mag() * delta_time;
+            let ray = Ray::new(start, projectile.velocity);
+            raycast_world(world, &ray, step_distance, projectile.owner)
+        } else {
+            None
+        };
+
+        if let Some(hit) = hit {
+            send_damage(
+                damage_events,
+                projectile.owner,
+                projectile.damage,
+                DamageType::Explosion,
+                &hit,
+            );
+            commands.remove(entity);
+        } else if projectile.seconds_remaining <= 0.0 {
+            commands.remove(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::hitbox::HitRegion;
+    use legion::{Resources, Schedule, World};
+    use ultraviolet::Vec2;
+    use winit::event::ElementState;
+
+    const DELTA_TIME: f32 = 1.0 / 60.0;
+
+    /// Runs the weapon for `ticks` fixed updates and returns the shots that came out.
+    fn hold(weapon: &mut Weapon, trigger: bool, reload: bool, ticks: usize) -> Vec<Shot> {
+        let shooter = World::default().push(());
+        (0..ticks)
+            .filter_map(|_| {
+                operate_weapon(
+                    shooter,
+                    Position::zero(),
+                    Rotation::identity(),
+                    weapon,
+                    trigger,
+                    reload,
+                    DELTA_TIME,
+                )
+            })
+            .map(|(_, shot)| shot)
+            .collect()
+    }
+
+    #[test]
+    fn fire_rate_is_limited_across_ticks() {
+        let mut weapon = Weapon::new(FireMode::Hitscan { range: 100.0 }, 10.0, 600.0, 100, 2.0);
+
+        // 600 rounds per minute is a shot every 6 ticks
+        assert_eq!(hold(&mut weapon, true, false, 60).len(), 10);
+        assert_eq!(hold(&mut weapon, true, false, 600).len(), 100 - 10);
+        assert_eq!(weapon.rounds_in_magazine, 0);
+
+        // 700 doesn't divide into ticks, shots 5 and 6 ticks apart average out to it
+        let mut weapon = Weapon::new(FireMode::Hitscan { range: 100.0 }, 10.0, 700.0, 100, 2.0);
+        assert_eq!(hold(&mut weapon, true, false, 6 * 60).len(), 70);
+    }
+
+    #[test]
+    fn empty_magazines_reload_and_refill() {
+        // Fires every tick, and takes 27 ticks to reload
+        let mut weapon = Weapon::new(FireMode::Hitscan { range: 100.0 }, 10.0, 6000.0, 3, 0.44);
+
+        // The fourth tick finds the magazine empty and starts reloading
+        assert_eq!(hold(&mut weapon, true, false, 10).len(), 3);
+        assert!(weapon.is_reloading());
+
+        // Holding the trigger doesn't fire while reloading, it does once the magazine is full
+        assert!(hold(&mut weapon, true, false, 20).is_empty());
+        assert!(weapon.is_reloading());
+        assert_eq!(hold(&mut weapon, true, false, 1).len(), 1);
+        assert!(!weapon.is_reloading());
+        assert_eq!(weapon.rounds_in_magazine, 2);
+
+        // Reloading by hand only happens with rounds missing
+        let mut full = Weapon::new(FireMode::Hitscan { range: 100.0 }, 10.0, 600.0, 3, 0.5);
+        hold(&mut full, false, true, 1);
+        assert!(!full.is_reloading());
+        hold(&mut full, true, false, 1);
+        hold(&mut full, false, true, 1);
+        assert!(full.is_reloading());
+    }
+
+    #[test]
+    fn recoil_pattern_repeats_its_last_kick_and_resets() {
+        let mut weapon = Weapon::new(FireMode::Hitscan { range: 100.0 }, 10.0, 6000.0, 100, 1.0);
+        weapon.recoil_pattern = vec![Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2)];
+        weapon.recoil_reset_in_seconds = 0.25;
+
+        let recoil = |shots: Vec<Shot>| shots.iter().map(|shot| shot.recoil.y).collect::<Vec<_>>();
+        assert_eq!(
+            recoil(hold(&mut weapon, true, false, 3)),
+            vec![0.1, 0.2, 0.2]
+        );
+
+        // Just short of the reset the pattern carries on, past it the pattern starts over
+        hold(&mut weapon, false, false, 13);
+        assert_eq!(recoil(hold(&mut weapon, true, false, 1)), vec![0.2]);
+        hold(&mut weapon, false, false, 16);
+        assert_eq!(recoil(hold(&mut weapon, true, false, 1)), vec![0.1]);
+    }
+
+    #[test]
+    fn hitscan_damages_the_hit_region_once() {
+        let mut world = World::default();
+        let mut resources = Resources::default();
+        resources.insert(GameClock::new(60));
+        resources.insert(Events::<DamageEvent>::default());
+        let mut input = Input::default();
+        input.process_mouse_button(MouseButton::Left, ElementState::Pressed);
+        resources.insert(input);
+
+        let mut rifle = Weapon::rifle();
+        rifle.spread_in_rad = 0.0;
+        let shooter = world.push((
+            Position::zero(),
+            Rotation::identity(),
+            rifle,
+            Camera::new(1.0, 1.0, 0.1, 100.0),
+        ));
+        // Looking down -z at the middle of the body, the head is above and the legs below
+        let target = world.push((
+            Position::new(0.0, 0.5, -10.0),
+            Rotation::identity(),
+            Hitbox::humanoid(),
+        ));
+
+        let mut schedule = Schedule::builder()
+            .add_system(fire_weapons_system())
+            .build();
+        schedule.execute(&mut world, &mut resources);
+
+        let damage_events = resources.get::<Events<DamageEvent>>().unwrap();
+        assert_eq!(damage_events.len(), 1);
+        let damage_event = damage_events.iter().next().unwrap();
+        assert_eq!(damage_event.source, shooter);
+        assert_eq!(damage_event.target, target);
+        assert_eq!(damage_event.hit_region, HitRegion::Body);
+        assert_eq!(damage_event.amount, 25.0);
+        assert_eq!(damage_event.damage_type, DamageType::Bullet);
+        assert!((damage_event.hit_location - Vec3::new(0.0, 0.0, -9.65)).mag() < 1e-4);
+    }
+
+    #[test]
+    fn projectiles_at_rest_only_expire() {
+        let mut world = World::default();
+        let mut resources = Resources::default();
+        resources.insert(GameClock::new(60));
+        resources.insert(Events::<DamageEvent>::default());
+
+        let owner = world.push(());
+        world.push((Position::zero(), Rotation::identity(), Hitbox::humanoid()));
+        let projectile = world.push((
+            Position::zero(),
+            Projectile {
+                owner,
+                velocity: Vec3::zero(),
+                damage: 100.0,
+                seconds_remaining: 0.05,
+            },
+        ));
+
+        let mut schedule = Schedule::builder()
+            .add_system(update_projectiles_system())
+            .build();
+        for _ in 0..3 {
+            schedule.execute(&mut world, &mut resources);
+        }
+
+        assert!(resources.get::<Events<DamageEvent>>().unwrap().is_empty());
+        assert!(world.entry(projectile).is_none());
+    }
+}
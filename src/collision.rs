@@ -0,0 +1,563 @@
+use ultraviolet::{Mat4, Vec3};
+
+// Triangles per BVH leaf, splitting further than this costs more in traversal than it saves
+const BVH_MAX_TRIANGLES_PER_LEAF: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalized(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub triangle_index: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::broadcast(f32::INFINITY),
+            max: Vec3::broadcast(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min_by_component(point);
+        self.max = self.max.max_by_component(point);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min_by_component(other.min),
+            max: self.max.max_by_component(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// The box around the transformed corners, which is bigger than the box itself when
+    /// rotated.
+    pub fn transformed(&self, transform: Mat4) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for corner in self.corners().iter() {
+            aabb.grow(transform.transform_point3(*corner));
+        }
+        aabb
+    }
+
+    pub fn bounding_sphere(&self) -> Sphere {
+        Sphere::new(self.center(), self.extent().mag() * 0.5)
+    }
+
+    /// Slab test, returns the distance along the ray at which the box is entered.
+    pub fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = max_distance;
+
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            if inverse_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// Returns the distance at which the ray enters the sphere, or 0 if it starts inside.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        let to_center = self.center - ray.origin;
+        let projected = to_center.dot(ray.direction);
+        let distance_sq = to_center.mag_sq() - projected * projected;
+        let radius_sq = self.radius * self.radius;
+
+        if distance_sq > radius_sq {
+            return None;
+        }
+
+        let half_chord = (radius_sq - distance_sq).sqrt();
+        let enter = projected - half_chord;
+        let exit = projected + half_chord;
+
+        if exit < 0.0 {
+            None
+        } else {
+            Some(enter.max(0.0))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3) -> Self {
+        Self { a, b, c }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        (self.b - self.a).cross(self.c - self.a).normalized()
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.a + self.b + self.c) / 3.0
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let mut aabb = Aabb::empty();
+        aabb.grow(self.a);
+        aabb.grow(self.b);
+        aabb.grow(self.c);
+        aabb
+    }
+
+    /// Möller–Trumbore intersection, both faces are hit so level geometry is solid from either side.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        let edge_1 = self.b - self.a;
+        let edge_2 = self.c - self.a;
+        let p = ray.direction.cross(edge_2);
+        let determinant = edge_1.dot(p);
+
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let s = ray.origin - self.a;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge_1);
+        let v = ray.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge_2.dot(q) * inverse_determinant;
+        if distance > f32::EPSILON {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BvhNodeKind {
+    Leaf { first: usize, count: usize },
+    Branch { left: usize, right: usize },
+}
+
+#[derive(Debug)]
+struct BvhNode {
+    aabb: Aabb,
+    kind: BvhNodeKind,
+}
+
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangle_indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(triangles: &[Triangle]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            triangle_indices: (0..triangles.len()).collect(),
+        };
+
+        if !triangles.is_empty() {
+            bvh.build_node(triangles, 0, triangles.len());
+        }
+
+        bvh
+    }
+
+    fn build_node(&mut self, triangles: &[Triangle], first: usize, count: usize) -> usize {
+        let indices = &mut self.triangle_indices[first..first + count];
+
+        let aabb = indices.iter().fold(Aabb::empty(), |aabb, &index| {
+            aabb.union(&triangles[index].aabb())
+        });
+
+        let node_index = self.nodes.len();
+
+        if count <= BVH_MAX_TRIANGLES_PER_LEAF {
+            self.nodes.push(BvhNode {
+                aabb,
+                kind: BvhNodeKind::Leaf { first, count },
+            });
+            return node_index;
+        }
+
+        // Median split along the longest axis of the centroid bounds
+        let centroid_aabb = indices.iter().fold(Aabb::empty(), |mut aabb, &index| {
+            aabb.grow(triangles[index].centroid());
+            aabb
+        });
+        let extent = centroid_aabb.extent();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        indices.sort_by(|&left, &right| {
+            triangles[left].centroid()[axis]
+                .partial_cmp(&triangles[right].centroid()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        // Reserve our slot before the children so the root always ends up at index 0
+        self.nodes.push(BvhNode {
+            aabb,
+            kind: BvhNodeKind::Leaf { first, count },
+        });
+
+        let left_count = count / 2;
+        let left = self.build_node(triangles, first, left_count);
+        let right = self.build_node(triangles, first + left_count, count - left_count);
+
+        self.nodes[node_index].kind = BvhNodeKind::Branch { left, right };
+
+        node_index
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map_or_else(Aabb::empty, |root| root.aabb)
+    }
+
+    pub fn raycast(&self, triangles: &[Triangle], ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest: Option<(usize, f32)> = None;
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let closest_distance = closest.map_or(max_distance, |(_, distance)| distance);
+
+            if node.aabb.intersect_ray(ray, closest_distance).is_none() {
+                continue;
+            }
+
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for &triangle_index in &self.triangle_indices[first..first + count] {
+                        if let Some(distance) = triangles[triangle_index].intersect_ray(ray) {
+                            let closest_distance =
+                                closest.map_or(max_distance, |(_, distance)| distance);
+                            if distance < closest_distance {
+                                closest = Some((triangle_index, distance));
+                            }
+                        }
+                    }
+                }
+                BvhNodeKind::Branch { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        closest.map(|(triangle_index, distance)| RayHit {
+            distance,
+            point: ray.at(distance),
+            normal: triangles[triangle_index].normal(),
+            triangle_index,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TriangleMeshCollider {
+    triangles: Vec<Triangle>,
+    bvh: Bvh,
+}
+
+impl TriangleMeshCollider {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let bvh = Bvh::build(&triangles);
+
+        Self { triangles, bvh }
+    }
+
+    /// Builds the collider in world space by applying `transform` to every position.
+    pub fn from_indexed(positions: &[Vec3], indices: &[u32], transform: Mat4) -> Self {
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                Triangle::new(
+                    transform.transform_point3(positions[triangle[0] as usize]),
+                    transform.transform_point3(positions[triangle[1] as usize]),
+                    transform.transform_point3(positions[triangle[2] as usize]),
+                )
+            })
+            .collect();
+
+        Self::new(triangles)
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.bvh.aabb()
+    }
+
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        self.bvh.raycast(&self.triangles, ray, max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_point(rng: &mut StdRng, range: f32) -> Vec3 {
+        Vec3::new(
+            rng.gen_range(-range, range),
+            rng.gen_range(-range, range),
+            rng.gen_range(-range, range),
+        )
+    }
+
+    /// A floor triangle at y = 0 with its right angle at the origin.
+    fn floor_triangle() -> Triangle {
+        Triangle::new(
+            Vec3::zero(),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        )
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Vec3::broadcast(-1.0),
+            max: Vec3::broadcast(1.0),
+        }
+    }
+
+    #[test]
+    fn bvh_hits_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let triangles = (0..1000)
+            .map(|_| {
+                let center = random_point(&mut rng, 20.0);
+                Triangle::new(
+                    center + random_point(&mut rng, 2.0),
+                    center + random_point(&mut rng, 2.0),
+                    center + random_point(&mut rng, 2.0),
+                )
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&triangles);
+
+        let mut hits = 0;
+        for _ in 0..1000 {
+            // Aimed into the soup, so most rays pass through a few leaves
+            let origin = random_point(&mut rng, 25.0);
+            let ray = Ray::new(origin, random_point(&mut rng, 20.0) - origin);
+            let max_distance = rng.gen_range(5.0, 60.0);
+
+            let brute_force = triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(index, triangle)| {
+                    triangle
+                        .intersect_ray(&ray)
+                        .map(|distance| (index, distance))
+                })
+                .filter(|(_, distance)| *distance < max_distance)
+                .min_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap());
+            let hit = bvh.raycast(&triangles, &ray, max_distance);
+
+            assert_eq!(
+                hit.map(|hit| (hit.triangle_index, hit.distance)),
+                brute_force
+            );
+            hits += hit.is_some() as usize;
+        }
+
+        // Make sure the soup is dense enough to test something
+        assert!(hits > 250, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn empty_bvhs_hit_nothing() {
+        let bvh = Bvh::build(&[]);
+
+        let ray = Ray::new(Vec3::zero(), Vec3::unit_x());
+        assert!(bvh.raycast(&[], &ray, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn triangles_are_hit_from_both_sides() {
+        let triangle = floor_triangle();
+
+        let from_above = Ray::new(Vec3::new(0.25, 2.0, 0.25), -Vec3::unit_y());
+        let from_below = Ray::new(Vec3::new(0.25, -3.0, 0.25), Vec3::unit_y());
+        assert_eq!(triangle.intersect_ray(&from_above), Some(2.0));
+        assert_eq!(triangle.intersect_ray(&from_below), Some(3.0));
+
+        // Pointing away, and missing past the hypotenuse
+        let away = Ray::new(Vec3::new(0.25, 2.0, 0.25), Vec3::unit_y());
+        let past = Ray::new(Vec3::new(0.75, 2.0, 0.75), -Vec3::unit_y());
+        assert_eq!(triangle.intersect_ray(&away), None);
+        assert_eq!(triangle.intersect_ray(&past), None);
+    }
+
+    #[test]
+    fn grazing_and_parallel_rays() {
+        let triangle = floor_triangle();
+
+        // Through an edge and a corner still counts, hitscan shouldn't slip through seams
+        let edge = Ray::new(Vec3::new(0.5, 1.0, 0.0), -Vec3::unit_y());
+        let corner = Ray::new(Vec3::new(1.0, 1.0, 0.0), -Vec3::unit_y());
+        assert_eq!(triangle.intersect_ray(&edge), Some(1.0));
+        assert_eq!(triangle.intersect_ray(&corner), Some(1.0));
+
+        // In the triangle's plane, and parallel above it
+        let in_plane = Ray::new(Vec3::new(-1.0, 0.0, 0.25), Vec3::unit_x());
+        let above = Ray::new(Vec3::new(-1.0, 0.5, 0.25), Vec3::unit_x());
+        assert_eq!(triangle.intersect_ray(&in_plane), None);
+        assert_eq!(triangle.intersect_ray(&above), None);
+
+        // Parallel to a box's faces, inside and outside of its slab
+        let aabb = unit_box();
+        let through = Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::unit_x());
+        let beside = Ray::new(Vec3::new(-5.0, 1.5, 0.5), Vec3::unit_x());
+        let along_face = Ray::new(Vec3::new(-5.0, 1.0, 0.5), Vec3::unit_x());
+        assert_eq!(aabb.intersect_ray(&through, f32::INFINITY), Some(4.0));
+        assert_eq!(aabb.intersect_ray(&beside, f32::INFINITY), None);
+        assert_eq!(aabb.intersect_ray(&along_face, f32::INFINITY), Some(4.0));
+    }
+
+    #[test]
+    fn rays_starting_inside_a_box_hit_it_immediately() {
+        let aabb = unit_box();
+
+        for direction in &[Vec3::unit_x(), -Vec3::unit_y(), Vec3::new(1.0, 2.0, -3.0)] {
+            let ray = Ray::new(Vec3::new(0.25, -0.5, 0.0), *direction);
+            assert_eq!(aabb.intersect_ray(&ray, f32::INFINITY), Some(0.0));
+        }
+
+        let behind = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::unit_z());
+        assert_eq!(aabb.intersect_ray(&behind, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn hits_past_max_distance_are_ignored() {
+        let aabb = unit_box();
+        let ray = Ray::new(Vec3::new(-6.0, 0.0, 0.0), Vec3::unit_x());
+        assert_eq!(aabb.intersect_ray(&ray, 4.0), None);
+        assert_eq!(aabb.intersect_ray(&ray, 6.0), Some(5.0));
+
+        // Two floors, the nearer one out of reach
+        let triangles = vec![
+            Triangle::new(
+                Vec3::new(-1.0, 0.0, -1.0),
+                Vec3::new(1.0, 0.0, -1.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ),
+            Triangle::new(
+                Vec3::new(-1.0, -2.0, -1.0),
+                Vec3::new(1.0, -2.0, -1.0),
+                Vec3::new(0.0, -2.0, 1.0),
+            ),
+        ];
+        let collider = TriangleMeshCollider::new(triangles);
+        let down = Ray::new(Vec3::new(0.0, 3.0, 0.0), -Vec3::unit_y());
+
+        let hit = collider.raycast(&down, 10.0).unwrap();
+        assert_eq!((hit.triangle_index, hit.distance), (0, 3.0));
+        assert_eq!(hit.point, Vec3::zero());
+        assert!(collider.raycast(&down, 2.5).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+/// Queue of events of a single type, stored as a resource.
+///
+/// Systems that produce events `send` them, the consuming system `drain`s the queue
+/// so every event is handled exactly once.
+pub struct Events<T> {
+    events: Vec<T>,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+impl<T> Events<T> {
+    pub fn send(&mut self, event: T) {
+        self.events.push(event);
+    }
+
+    pub fn drain(&mut self) -> std::vec::Drain<'_, T> {
+        self.events.drain(..)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the polling fallback rescans the watched directories.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reports files that were written under a set of directories. Uses inotify on Linux and
+/// falls back to comparing modification times when that isn't available.
+pub struct FileWatcher {
+    backend: Backend,
+}
+
+enum Backend {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::InotifyWatcher),
+    Polling(PollingWatcher),
+}
+
+impl FileWatcher {
+    /// Watches every file under `directories`, including ones created later.
+    pub fn new(directories: &[PathBuf]) -> Self {
+        #[cfg(target_os = "linux")]
+        match inotify::InotifyWatcher::new(directories) {
+            Ok(watcher) => {
+                return Self {
+                    backend: Backend::Inotify(watcher),
+                }
+            }
+            Err(error) => log::warn!("inotify unavailable, polling for file changes: {}", error),
+        }
+
+        Self::polling(directories)
+    }
+
+    pub fn polling(directories: &[PathBuf]) -> Self {
+        Self {
+            backend: Backend::Polling(PollingWatcher::new(directories)),
+        }
+    }
+
+    /// Files changed since the last call, each listed once.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let changed = match &mut self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Inotify(watcher) => watcher.changed_files(),
+            Backend::Polling(watcher) => watcher.changed_files(),
+        };
+
+        let mut seen = HashSet::new();
+        changed
+            .into_iter()
+            .filter(|path| seen.insert(path.clone()))
+            .collect()
+    }
+}
+
+fn visit_files(directory: &Path, visit: &mut dyn FnMut(&Path)) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            visit_files(&path, visit);
+        } else {
+            visit(&path);
+        }
+    }
+}
+
+struct PollingWatcher {
+    directories: Vec<PathBuf>,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl PollingWatcher {
+    fn new(directories: &[PathBuf]) -> Self {
+        let mut watcher = Self {
+            directories: directories.to_vec(),
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.scan();
+        watcher
+    }
+
+    fn changed_files(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        self.scan()
+    }
+
+    /// Records modification times and returns the files that are new or were modified.
+    fn scan(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        for directory in &self.directories {
+            let modified = &mut self.modified;
+            visit_files(directory, &mut |path| {
+                let time = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                    Ok(time) => time,
+                    Err(_) => return,
+                };
+
+                if modified.insert(path.to_path_buf(), time) != Some(time) {
+                    changed.push(path.to_path_buf());
+                }
+            });
+        }
+
+        changed
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    /// Editors often save by writing a temporary file and renaming it over the original.
+    const WATCHED_EVENTS: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+    const EVENT_HEADER_SIZE: usize = mem::size_of::<libc::inotify_event>();
+
+    pub struct InotifyWatcher {
+        fd: libc::c_int,
+        directories: HashMap<libc::c_int, PathBuf>,
+    }
+
+    impl InotifyWatcher {
+        pub fn new(directories: &[PathBuf]) -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut watcher = Self {
+                fd,
+                directories: HashMap::new(),
+            };
+            for directory in directories {
+                watcher.watch_recursive(directory)?;
+            }
+
+            Ok(watcher)
+        }
+
+        /// inotify doesn't watch subdirectories by itself.
+        fn watch_recursive(&mut self, directory: &Path) -> io::Result<()> {
+            let path = CString::new(directory.as_os_str().as_bytes())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), WATCHED_EVENTS) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.directories.insert(wd, directory.to_path_buf());
+
+            for entry in std::fs::read_dir(directory)?.flatten() {
+                if entry.path().is_dir() {
+                    self.watch_recursive(&entry.path())?;
+                }
+            }
+
+            Ok(())
+        }
+
+        pub fn changed_files(&mut self) -> Vec<PathBuf> {
+            let mut changed = Vec::new();
+            let mut buffer = [0u8; 4096];
+
+            loop {
+                let read = unsafe {
+                    libc::read(
+                        self.fd,
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                    )
+                };
+                // Nothing left to read returns EAGAIN since the descriptor is non-blocking
+                if read <= 0 {
+                    break;
+                }
+
+                let mut offset = 0;
+                while offset + EVENT_HEADER_SIZE <= read as usize {
+                    let event = unsafe {
+                        (buffer.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+                    };
+                    let name_start = offset + EVENT_HEADER_SIZE;
+                    let name = &buffer[name_start..name_start + event.len as usize];
+                    let name_length = name
+                        .iter()
+                        .position(|&byte| byte == 0)
+                        .unwrap_or(name.len());
+                    offset = name_start + event.len as usize;
+
+                    let directory = match self.directories.get(&event.wd) {
+                        Some(directory) => directory,
+                        None => continue,
+                    };
+                    let path = directory.join(OsStr::from_bytes(&name[..name_length]));
+
+                    if event.mask & libc::IN_ISDIR != 0 {
+                        if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                            let _ = self.watch_recursive(&path);
+                        }
+                    } else if event.mask & libc::IN_CREATE == 0 {
+                        // Created files are reported once they are written and closed
+                        changed.push(path);
+                    }
+                }
+            }
+
+            changed
+        }
+    }
+
+    impl Drop for InotifyWatcher {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_directory::TestDirectory;
+
+    fn test_directory(name: &str) -> TestDirectory {
+        let directory = TestDirectory::new(&format!("file_watcher_{}", name));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        directory
+    }
+
+    #[test]
+    fn polling_reports_modified_files() {
+        let directory = test_directory("polling");
+        let file = directory.join("nested").join("shader.frag");
+        fs::write(&file, "old").unwrap();
+
+        let mut watcher = PollingWatcher::new(&[directory.path().to_path_buf()]);
+        assert!(watcher.scan().is_empty());
+
+        // Modification times can be as coarse as a second on some file systems
+        let later = SystemTime::now() + Duration::from_secs(2);
+        watcher.modified.insert(file.clone(), later);
+        assert_eq!(watcher.scan(), vec![file]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inotify_reports_written_files() {
+        let directory = test_directory("inotify");
+        let mut watcher = FileWatcher::new(&[directory.path().to_path_buf()]);
+        let file = directory.join("nested").join("texture.png");
+
+        fs::write(&file, "new").unwrap();
+
+        assert_eq!(watcher.changed_files(), vec![file]);
+        assert!(watcher.changed_files().is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct GameClock {
+    pub game_start_instant: Instant,
+    pub current_frame_instant: Instant,
+    pub new_frame_instant: Instant,
+
+    pub last_frame_duration: Duration,
+
+    pub fixed_update_step_duration: f64,
+    /// Number of fixed updates run so far, network snapshots are stamped with it.
+    pub fixed_update_tick: u32,
+}
+
+impl GameClock {
+    pub fn new(fixed_update_steps_per_second: usize) -> Self {
+        Self {
+            game_start_instant: Instant::now(),
+            current_frame_instant: Instant::now(),
+            new_frame_instant: Instant::now(),
+
+            last_frame_duration: Duration::default(),
+
+            fixed_update_step_duration: 1.0 / fixed_update_steps_per_second as f64,
+            fixed_update_tick: 0,
+        }
+    }
+}
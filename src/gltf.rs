@@ -1,6 +1,27 @@
+use std::collections::HashSet;
 use std::path::Path;
 
-use gltf::{buffer::Data as BufferData, image::Data as ImageData, Document, Semantic};
+use anyhow::*;
+use gltf::khr_lights_punctual::Kind;
+use gltf::{
+    buffer::Data as BufferData, image::Data as ImageData, Accessor, Document, Node, Semantic,
+};
+use serde::Deserialize;
+use ultraviolet::{Mat4, Rotor3, Vec3};
+
+use crate::code::components::{
+    light::{Light, LightKind, ShadowSettings},
+    spawn_point::SpawnPoint,
+    static_collider::StaticCollider,
+    team::Team,
+};
+use crate::collision::{Aabb, TriangleMeshCollider};
+use crate::texture::SamplerSettings;
+
+// Blender object name suffixes, matching the convention other engines use for authored collision
+const COLLISION_NAME_SUFFIX: &str = "-col";
+const COLLISION_ONLY_NAME_SUFFIX: &str = "-colonly";
+const SPAWN_POINT_NAME_PREFIX: &str = "spawn_point";
 
 #[derive(Default)]
 pub struct Mesh {
@@ -9,26 +30,142 @@ pub struct Mesh {
 
     pub normals: Vec<u8>,
     pub texture_coordinates: Vec<u8>,
+
+    /// Of the positions, in model space.
+    pub bounds: Aabb,
+    /// How the base color texture of the primitive's material is sampled.
+    pub diffuse_sampler: SamplerSettings,
+}
+
+#[derive(Default)]
+pub struct GltfLoadOptions {
+    pub build_colliders: bool,
+}
+
+/// A `KHR_lights_punctual` light and where its node is.
+#[derive(Debug, Copy, Clone)]
+pub struct GltfLight {
+    pub light: Light,
+    pub position: Vec3,
+    pub rotation: Rotor3,
+}
+
+#[derive(Default)]
+pub struct GltfScene {
+    pub meshes: Vec<Mesh>,
+    pub colliders: Vec<StaticCollider>,
+    pub spawn_points: Vec<SpawnPoint>,
+    pub lights: Vec<GltfLight>,
+}
+
+impl GltfScene {
+    /// Bounds of all meshes together, in model space.
+    pub fn bounds(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .fold(Aabb::empty(), |bounds, mesh| bounds.union(&mesh.bounds))
+    }
+}
+
+/// Custom properties set on an object in Blender end up in the node's `extras`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NodeExtras {
+    collision: bool,
+    collision_only: bool,
+    spawn_point: bool,
+    team: Option<String>,
+}
+
+impl NodeExtras {
+    fn of(node: &Node) -> Self {
+        node.extras()
+            .as_ref()
+            .and_then(|raw| serde_json::from_str::<NodeExtras>(raw.get()).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum NodeCollision {
+    None,
+    VisualAndCollision,
+    CollisionOnly,
+}
+
+impl NodeCollision {
+    fn of(node: &Node) -> Self {
+        let extras = NodeExtras::of(node);
+        let name = node.name().unwrap_or("");
+
+        if extras.collision_only || name.ends_with(COLLISION_ONLY_NAME_SUFFIX) {
+            NodeCollision::CollisionOnly
+        } else if extras.collision || name.ends_with(COLLISION_NAME_SUFFIX) {
+            NodeCollision::VisualAndCollision
+        } else {
+            NodeCollision::None
+        }
+    }
 }
 
 pub struct GltfLoader {}
 
 impl GltfLoader {
-    pub fn load<P: AsRef<Path>>(path: P) -> Vec<Mesh> {
-        let (schema, buffers, _): (Document, Vec<BufferData>, Vec<ImageData>) =
-            gltf::import(path).unwrap();
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Mesh>> {
+        Ok(GltfLoader::load_with_options(path, &GltfLoadOptions::default())?.meshes)
+    }
+
+    pub fn load_with_options<P: AsRef<Path>>(
+        path: P,
+        options: &GltfLoadOptions,
+    ) -> Result<GltfScene> {
+        GltfLoader::load_imported(gltf::import(path)?, options)
+    }
 
-        let mut meshes = Vec::new();
+    fn load_imported(
+        (schema, buffers, _): (Document, Vec<BufferData>, Vec<ImageData>),
+        options: &GltfLoadOptions,
+    ) -> Result<GltfScene> {
+        let mut scene = GltfScene::default();
+
+        // Meshes referenced only by collision-only nodes are never drawn
+        let mut visible_meshes = HashSet::new();
+        let mut collision_only_meshes = HashSet::new();
+        for node in schema.nodes() {
+            if let Some(mesh) = node.mesh() {
+                if NodeCollision::of(&node) == NodeCollision::CollisionOnly {
+                    collision_only_meshes.insert(mesh.index());
+                } else {
+                    visible_meshes.insert(mesh.index());
+                }
+            }
+        }
 
         for mesh in schema.meshes() {
-            println!("mesh: {}", mesh.name().unwrap_or("none"));
+            if collision_only_meshes.contains(&mesh.index())
+                && !visible_meshes.contains(&mesh.index())
+            {
+                log::debug!("mesh: {} is collision only", mesh.name().unwrap_or("none"));
+                continue;
+            }
+
+            log::debug!("mesh: {}", mesh.name().unwrap_or("none"));
             for primitive in mesh.primitives() {
                 let mut mesh = Mesh::default();
 
-                println!("new primitive");
+                log::debug!("new primitive");
+
+                if let Some(base_color) = primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                {
+                    mesh.diffuse_sampler =
+                        SamplerSettings::from_gltf(&base_color.texture().sampler());
+                }
 
                 for (attribute_type, accessor) in primitive.attributes() {
-                    println!(
+                    log::debug!(
                         "\tattribute: {:?} accessor component size: {:?}",
                         attribute_type,
                         accessor.size()
@@ -38,6 +175,7 @@ impl GltfLoader {
                         Semantic::Positions => {
                             mesh.positions =
                                 GltfLoader::get_accessor_data(&schema, accessor.index(), &buffers);
+                            mesh.bounds = GltfLoader::position_bounds(&accessor, &mesh.positions);
                         }
                         Semantic::Normals => {
                             mesh.normals =
@@ -51,10 +189,11 @@ impl GltfLoader {
                         }
                         Semantic::Joints(_) => {}
                         Semantic::Weights(_) => {}
+                        Semantic::Extras(_) => {}
                     }
                 }
 
-                println!(
+                log::debug!(
                     "\tindices component size: {:?}",
                     primitive.indices().unwrap().size()
                 );
@@ -68,11 +207,198 @@ impl GltfLoader {
                     &buffers,
                 );
 
-                meshes.push(mesh);
+                scene.meshes.push(mesh);
+            }
+        }
+
+        for gltf_scene in schema.scenes() {
+            for node in gltf_scene.nodes() {
+                GltfLoader::collect_nodes(&node, Mat4::identity(), &buffers, options, &mut scene);
+            }
+        }
+
+        Ok(scene)
+    }
+
+    fn collect_nodes(
+        node: &Node,
+        parent_transform: Mat4,
+        buffers: &[BufferData],
+        options: &GltfLoadOptions,
+        scene: &mut GltfScene,
+    ) {
+        let transform = parent_transform * Mat4::from(node.transform().matrix());
+
+        if options.build_colliders {
+            GltfLoader::collect_collider(node, transform, buffers, &mut scene.colliders);
+        }
+
+        GltfLoader::collect_spawn_point(node, transform, &mut scene.spawn_points);
+        GltfLoader::collect_light(node, transform, &mut scene.lights);
+
+        for child in node.children() {
+            GltfLoader::collect_nodes(&child, transform, buffers, options, scene);
+        }
+    }
+
+    fn collect_spawn_point(node: &Node, transform: Mat4, spawn_points: &mut Vec<SpawnPoint>) {
+        let extras = NodeExtras::of(node);
+        let name = node.name().unwrap_or("");
+
+        if !extras.spawn_point && !name.starts_with(SPAWN_POINT_NAME_PREFIX) {
+            return;
+        }
+
+        let team = extras.team.as_deref().and_then(|team| {
+            let parsed = Team::from_name(team);
+            if parsed.is_none() {
+                log::warn!("spawn point: {} has unknown team {}", name, team);
+            }
+            parsed
+        });
+
+        // Players only ever yaw, so keep just the heading of the node
+        let forward = transform.transform_vec3(Vec3::new(0.0, 0.0, -1.0));
+        let flat_forward = Vec3::new(forward.x, 0.0, forward.z);
+        let rotation = if flat_forward.mag_sq() <= f32::EPSILON {
+            Rotor3::identity()
+        } else if (flat_forward.normalized() - Vec3::unit_z()).mag_sq() > f32::EPSILON {
+            Rotor3::from_rotation_between(Vec3::new(0.0, 0.0, -1.0), flat_forward.normalized())
+        } else {
+            // Straight backwards, rotation_between can't pick an axis
+            Rotor3::from_rotation_xz(std::f32::consts::PI)
+        };
+
+        let spawn_point = SpawnPoint {
+            position: transform.transform_point3(Vec3::zero()),
+            rotation,
+            team,
+        };
+
+        log::debug!("spawn point: {} {:?}", name, spawn_point);
+
+        spawn_points.push(spawn_point);
+    }
+
+    fn collect_light(node: &Node, transform: Mat4, lights: &mut Vec<GltfLight>) {
+        let light = match node.light() {
+            Some(light) => light,
+            None => return,
+        };
+
+        let kind = match light.kind() {
+            Kind::Directional => LightKind::Directional,
+            Kind::Point => LightKind::Point {
+                range: light.range(),
+            },
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                range: light.range(),
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        };
+
+        // Lights shine down their node's -z, scale and roll don't matter
+        let direction = transform
+            .transform_vec3(Vec3::new(0.0, 0.0, -1.0))
+            .normalized();
+        let rotation = if (direction - Vec3::unit_z()).mag_sq() > f32::EPSILON {
+            Rotor3::from_rotation_between(Vec3::new(0.0, 0.0, -1.0), direction)
+        } else {
+            // Straight backwards, rotation_between can't pick an axis
+            Rotor3::from_rotation_xz(std::f32::consts::PI)
+        };
+
+        let gltf_light = GltfLight {
+            light: Light {
+                kind,
+                color: Vec3::from(light.color()),
+                intensity: light.intensity(),
+                // glTF has no say in shadows, every light that can cast them does
+                shadows: match kind {
+                    LightKind::Point { .. } => None,
+                    _ => Some(ShadowSettings::default()),
+                },
+            },
+            position: transform.transform_point3(Vec3::zero()),
+            rotation,
+        };
+
+        log::debug!("light: {} {:?}", node.name().unwrap_or("none"), gltf_light);
+
+        lights.push(gltf_light);
+    }
+
+    fn collect_collider(
+        node: &Node,
+        transform: Mat4,
+        buffers: &[BufferData],
+        colliders: &mut Vec<StaticCollider>,
+    ) {
+        let collision = NodeCollision::of(node);
+
+        if let Some(mesh) = node.mesh().filter(|_| collision != NodeCollision::None) {
+            let mut positions = Vec::new();
+            let mut indices = Vec::new();
+
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let index_offset = positions.len() as u32;
+
+                positions.extend(
+                    reader
+                        .read_positions()
+                        .expect("Collision primitive doesn't have positions!")
+                        .map(Vec3::from),
+                );
+                indices.extend(
+                    reader
+                        .read_indices()
+                        .expect("Collision primitive doesn't have index buffer!")
+                        .into_u32()
+                        .map(|index| index + index_offset),
+                );
             }
+
+            let name = node.name().unwrap_or("none").to_string();
+            let collider = TriangleMeshCollider::from_indexed(&positions, &indices, transform);
+
+            log::debug!(
+                "collider: {} triangles: {}",
+                name,
+                collider.triangles().len()
+            );
+
+            colliders.push(StaticCollider {
+                name,
+                mesh: collider,
+            });
         }
+    }
+
+    /// glTF requires position accessors to have their min and max, bounds are only computed
+    /// from the positions for files that leave them out anyway.
+    fn position_bounds(accessor: &Accessor, positions: &[u8]) -> Aabb {
+        let corner = |value: Option<serde_json::Value>| {
+            value.and_then(|value| serde_json::from_value::<[f32; 3]>(value).ok())
+        };
 
-        meshes
+        match (corner(accessor.min()), corner(accessor.max())) {
+            (Some(min), Some(max)) => Aabb {
+                min: Vec3::from(min),
+                max: Vec3::from(max),
+            },
+            _ => {
+                let mut bounds = Aabb::empty();
+                for position in bytemuck::cast_slice::<u8, [f32; 3]>(positions) {
+                    bounds.grow(Vec3::from(*position));
+                }
+                bounds
+            }
+        }
     }
 
     fn get_accessor_data(
@@ -103,3 +429,192 @@ impl GltfLoader {
         accessor_data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::systems::weapon::forward_vector;
+
+    #[test]
+    fn imports_punctual_lights() {
+        let scene = GltfLoader::load_with_options(
+            "./src/assets/render_test_scene.gltf",
+            &GltfLoadOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(scene.lights.len(), 1);
+        let sun = scene.lights[0];
+        assert_eq!(sun.light.kind, LightKind::Directional);
+        assert_eq!(sun.light.intensity, 3.0);
+        assert_eq!(sun.light.color, Vec3::new(1.0, 0.95, 0.9));
+        assert_eq!(sun.light.shadows, Some(ShadowSettings::default()));
+        // Rotated 60 degrees down around x
+        let direction = forward_vector(sun.rotation);
+        assert!((direction - Vec3::new(0.0, -0.866_025_4, -0.5)).mag() < 1e-4);
+    }
+
+    #[test]
+    fn meshes_have_the_bounds_of_their_positions() {
+        let scene = GltfLoader::load_with_options(
+            "./src/assets/render_test_scene.gltf",
+            &GltfLoadOptions::default(),
+        )
+        .unwrap();
+
+        let bounds = scene.meshes[0].bounds;
+        assert_eq!(bounds.min, Vec3::broadcast(-1.0));
+        assert_eq!(bounds.max, Vec3::broadcast(1.0));
+
+        // The same as going through the positions, which is what files without min and max get
+        let mut computed = Aabb::empty();
+        for position in bytemuck::cast_slice::<u8, [f32; 3]>(&scene.meshes[0].positions) {
+            computed.grow(Vec3::from(*position));
+        }
+        assert_eq!(computed.min, bounds.min);
+        assert_eq!(computed.max, bounds.max);
+        assert_eq!(scene.bounds().max, bounds.max);
+    }
+
+    /// One triangle used by nodes marked for collision in every way Blender exports can.
+    const COLLISION_TEST_SCENE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 1, 2, 3, 4, 5] }],
+        "nodes": [
+            { "name": "Floor-col", "mesh": 0, "translation": [0, 5, 0] },
+            { "name": "Trigger-colonly", "mesh": 1 },
+            { "name": "Crate", "mesh": 0, "extras": { "collision": true } },
+            { "name": "Blocker", "mesh": 1, "extras": { "collision_only": true } },
+            { "name": "Decoration", "mesh": 0 },
+            { "name": "Broken", "mesh": 0, "extras": { "collision": "yes" } }
+        ],
+        "meshes": [
+            { "name": "visible", "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] },
+            { "name": "hidden", "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }
+        ],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 0, 1]
+            },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "buffers": [{
+            "byteLength": 44,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAAAAAAIA/AAABAAIAAAA="
+        }]
+    }"#;
+
+    fn import_collision_test_scene() -> (Document, Vec<BufferData>, Vec<ImageData>) {
+        let document = gltf::Gltf::from_slice(COLLISION_TEST_SCENE.as_bytes())
+            .unwrap()
+            .document;
+        // `import_slice` refuses data URIs, so this is the buffer's data decoded by hand
+        let mut data =
+            bytemuck::cast_slice::<f32, u8>(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0])
+                .to_vec();
+        data.extend(bytemuck::cast_slice::<u16, u8>(&[0, 1, 2, 0]));
+        let buffers = vec![BufferData(data)];
+        (document, buffers, Vec::new())
+    }
+
+    fn collision_test_scene(build_colliders: bool) -> GltfScene {
+        GltfLoader::load_imported(
+            import_collision_test_scene(),
+            &GltfLoadOptions { build_colliders },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn collision_comes_from_name_suffixes_and_extras() {
+        let (document, _, _) = import_collision_test_scene();
+
+        let collisions = document
+            .nodes()
+            .map(|node| NodeCollision::of(&node))
+            .collect::<Vec<_>>();
+
+        // Extras that don't parse are ignored rather than failing the level
+        assert_eq!(
+            collisions,
+            vec![
+                NodeCollision::VisualAndCollision,
+                NodeCollision::CollisionOnly,
+                NodeCollision::VisualAndCollision,
+                NodeCollision::CollisionOnly,
+                NodeCollision::None,
+                NodeCollision::None,
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_colliders_in_world_space_and_hides_collision_only_meshes() {
+        let scene = collision_test_scene(true);
+
+        let names = scene
+            .colliders
+            .iter()
+            .map(|collider| collider.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["Floor-col", "Trigger-colonly", "Crate", "Blocker"]
+        );
+        let floor = scene.colliders[0].mesh.aabb();
+        assert_eq!(floor.min, Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(floor.max, Vec3::new(1.0, 5.0, 1.0));
+
+        // Only the mesh some node draws is loaded for rendering
+        assert_eq!(scene.meshes.len(), 1);
+        assert!(collision_test_scene(false).colliders.is_empty());
+    }
+
+    #[test]
+    fn imports_spawn_points_by_name_or_extras() {
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "scene": 0,
+                "scenes": [{ "nodes": [0, 1, 2, 3, 4] }],
+                "nodes": [
+                    { "name": "spawn_point_a", "translation": [1, 2, 3] },
+                    {
+                        "name": "Start",
+                        "rotation": [0, 0.70710677, 0, 0.70710677],
+                        "extras": { "spawn_point": true, "team": "Blue" }
+                    },
+                    { "name": "spawn_point_b", "extras": { "team": "green" } },
+                    { "name": "spawn_point_backwards", "rotation": [0, 1, 0, 0] },
+                    { "name": "Crate", "extras": { "team": "red" } }
+                ]
+            }"#,
+        )
+        .unwrap()
+        .document;
+        let scene = GltfLoader::load_imported(
+            (document, Vec::new(), Vec::new()),
+            &GltfLoadOptions::default(),
+        )
+        .unwrap();
+        let spawn_points = &scene.spawn_points;
+
+        assert_eq!(spawn_points.len(), 4);
+        assert_eq!(spawn_points[0].position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(spawn_points[0].team, None);
+        assert_eq!(spawn_points[1].team, Some(Team::Blue));
+        // Turned 90 degrees left, looking down -x
+        let forward = forward_vector(spawn_points[1].rotation);
+        assert!((forward - Vec3::new(-1.0, 0.0, 0.0)).mag() < 1e-4);
+        // Unknown teams leave the spawn point to everyone
+        assert_eq!(spawn_points[2].team, None);
+        let forward = forward_vector(spawn_points[3].rotation);
+        assert!((forward - Vec3::unit_z()).mag() < 1e-4, "{:?}", forward);
+    }
+}
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use ultraviolet::Vec2;
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode};
 
 #[derive(Default)]
 pub struct Mouse {
     pub acceleration: Vec2,
     pub position: Vec2,
     pub old_position: Vec2,
+    /// Movement accumulated over frames until a fixed update consumes it.
+    pub unconsumed_movement: Vec2,
+    pub held_buttons: HashSet<MouseButton>,
 }
 
 #[derive(Default)]
@@ -52,6 +55,17 @@ impl Input {
         };
     }
 
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => self.mouse.held_buttons.insert(button),
+            ElementState::Released => self.mouse.held_buttons.remove(&button),
+        };
+    }
+
+    pub fn mouse_button_held(&self, button: MouseButton) -> bool {
+        self.mouse.held_buttons.contains(&button)
+    }
+
     pub fn key_pressed(&self, key_code: VirtualKeyCode) -> bool {
         false
     }
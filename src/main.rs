@@ -1,21 +1,74 @@
+mod asset_server;
 mod camera;
 mod code;
+mod collision;
+mod events;
+mod file_watcher;
+mod game_clock;
 mod gltf;
 mod input;
+mod net;
+mod prefab;
 mod renderer;
+mod serialization;
+mod shader;
+#[cfg(test)]
+mod test_directory;
 mod texture;
 
-use code::components::{position::Position, rotation::Rotation};
+use code::components::{
+    material::Material, model::Model, position::Position, prediction_error::PredictionError,
+    rotation::Rotation, spawn_point::SpawnPoint, static_collider::StaticCollider, team::Team,
+};
+use code::events::{
+    damage_event::DamageEvent, death_event::DeathEvent, respawn_event::RespawnEvent,
+};
+use code::systems::asset::update_assets_system;
+use code::systems::health::{apply_damage_system, respawn_system, update_dead_system};
+use code::systems::lag_compensation::{
+    record_transforms_system, LagCompensationSettings, TransformHistory,
+};
+use code::systems::movement::{move_local_player_system, move_players_system};
+use code::systems::network::{
+    apply_snapshots_system, assign_network_ids_system, interpolate_remote_entities_system,
+    predict_local_player_system, send_snapshots_system, smooth_prediction_error_system,
+    update_client_system, update_interpolation_clock_system, update_server_system,
+};
+use code::systems::render::{
+    cull_render_scene_system, extract_render_scene_system, render_system,
+    update_render_assets_system, RenderAssets,
+};
+use code::systems::spawn::{respawn_dead_players_system, select_spawn_point, SpawnSettings};
+use code::systems::weapon::{
+    fire_player_weapons_system, fire_weapons_system, update_projectiles_system,
+};
+
+use crate::asset_server::AssetServer;
+use crate::events::Events;
+use crate::file_watcher::FileWatcher;
+use crate::game_clock::GameClock;
+use crate::gltf::{GltfLoadOptions, GltfLoader};
+use crate::net::client::Client;
+use crate::net::interpolation::{InterpolationClock, InterpolationSettings};
+use crate::net::prediction::Prediction;
+use crate::net::replication::{replication_registry, ClientReplication, ServerReplication};
+use crate::net::server::Server;
+use crate::net::transport::Transport;
+use crate::net::udp::UdpTransport;
+use crate::net::{NetConfig, NetworkMode};
+use crate::prefab::{spawn_prefab, Prefab};
+use crate::serialization::{load_world_from_file, save_world_to_file};
 
-use camera::Camera;
 use futures::executor::block_on;
+use renderer::culling::MeshBounds;
+use renderer::scene::RenderScene;
 use renderer::State;
-use std::time::{Duration, Instant};
-use ultraviolet::{Rotor3, Similarity3, Vec2, Vec3};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use input::Input;
-use legion::component;
-use legion::{system, Resources, Schedule, World};
+use legion::{any, component, system, Entity, IntoQuery, Resources, Schedule, World};
 
 use winit::{
     event::*,
@@ -28,6 +81,7 @@ fn update_mouse(#[resource] input: &mut Input) {
     input.mouse.acceleration.x = input.mouse.position.x - input.mouse.old_position.x;
     input.mouse.acceleration.y = input.mouse.position.y - input.mouse.old_position.y;
     input.mouse.old_position = input.mouse.position;
+    input.mouse.unconsumed_movement += input.mouse.acceleration;
 
     // println!(
     //     "mouse acceleration x: {}, y: {}",
@@ -35,66 +89,6 @@ fn update_mouse(#[resource] input: &mut Input) {
     // );
 }
 
-#[system(for_each)]
-#[filter(component::<Camera>())]
-fn move_camera(
-    #[resource] game_clock: &GameClock,
-    #[resource] input: &Input,
-    position: &mut Position,
-    rotation: &mut Rotation,
-) {
-    let delta_time = game_clock.last_frame_duration.as_secs_f32();
-    let movement_speed_in_meters_per_second = 5.0;
-
-    let horizontal_rotor = Rotor3::from_rotation_xz(input.mouse.acceleration.x * delta_time);
-    *rotation = *rotation * horizontal_rotor;
-
-    let right_vector = Vec3::new(1.0, 0.0, 0.0).rotated_by(*rotation);
-    let forward_vector = Vec3::new(0.0, 0.0, 1.0).rotated_by(*rotation);
-
-    let mut movement = Vec2::default();
-
-    if input.key_held(VirtualKeyCode::W) {
-        movement.y -= 1.0;
-    }
-    if input.key_held(VirtualKeyCode::S) {
-        movement.y += 1.0;
-    }
-    if input.key_held(VirtualKeyCode::A) {
-        movement.x -= 1.0;
-    }
-    if input.key_held(VirtualKeyCode::D) {
-        movement.x += 1.0;
-    }
-
-    *position += (movement.x * right_vector + movement.y * forward_vector)
-        * movement_speed_in_meters_per_second
-        * delta_time;
-
-    // let forward = camera.target - camera.eye;
-    // let forward_norm = forward.normalized();
-    // let forward_mag = forward.mag();
-    // let speed = 0.2;
-
-    // if input.key_held(VirtualKeyCode::W) && forward_mag > speed {
-    //     camera.eye += forward_norm * speed;
-    // }
-    // if input.key_held(VirtualKeyCode::S) {
-    //     camera.eye -= forward_norm * speed;
-    // }
-
-    // let right = forward_norm.cross(camera.up);
-    // let forward = camera.target - camera.eye;
-    // let forward_mag = forward.mag();
-
-    // if input.key_held(VirtualKeyCode::D) {
-    //     camera.eye = camera.target - (forward + right * speed).normalized() * forward_mag;
-    // }
-    // if input.key_held(VirtualKeyCode::A) {
-    //     camera.eye = camera.target - (forward - right * speed).normalized() * forward_mag;
-    // }
-}
-
 #[system]
 fn update_print(#[resource] game_clock: &GameClock) {
     println!(
@@ -108,58 +102,233 @@ fn fixed_update_print() {
     println!("fixed update");
 }
 
-#[derive(Debug)]
-struct GameClock {
-    game_start_instant: Instant,
-    current_frame_instant: Instant,
-    new_frame_instant: Instant,
+#[system]
+fn advance_fixed_update_tick(#[resource] game_clock: &mut GameClock) {
+    game_clock.fixed_update_tick += 1;
+}
 
-    last_frame_duration: Duration,
+#[system]
+fn print_death_events(#[resource] death_events: &mut Events<DeathEvent>) {
+    for death_event in death_events.drain() {
+        println!("death: {:?}", death_event);
+    }
+}
 
-    fixed_update_step_duration: f64,
+#[system]
+fn hot_reload(
+    #[resource] file_watcher: &mut FileWatcher,
+    #[resource] asset_server: &mut AssetServer,
+    #[resource] renderer: &mut State,
+) {
+    for path in file_watcher.changed_files() {
+        if !renderer.reload_shader(&path) && asset_server.reload(&path) {
+            log::info!("reloading asset {}", path.display());
+        }
+    }
 }
 
-impl GameClock {
-    pub fn new(fixed_update_steps_per_second: usize) -> Self {
-        Self {
-            game_start_instant: Instant::now(),
-            current_frame_instant: Instant::now(),
-            new_frame_instant: Instant::now(),
+/// Pushes the level's colliders and spawn points into `world` and returns the spawn points.
+fn load_level(world: &mut World) -> Vec<SpawnPoint> {
+    let level = GltfLoader::load_with_options(
+        "./src/assets/render_test_scene.gltf",
+        &GltfLoadOptions {
+            build_colliders: true,
+        },
+    )
+    .expect("failed loading level?");
+
+    for collider in level.colliders {
+        world.push((collider,));
+    }
+
+    for spawn_point in level.spawn_points.iter() {
+        world.push((*spawn_point,));
+    }
 
-            last_frame_duration: Duration::default(),
+    world.push((
+        Position::zero(),
+        Rotation::identity(),
+        Model {
+            path: PathBuf::from("./src/assets/render_test_scene.gltf"),
+        },
+        Material::new(PathBuf::from("./src/assets/cube_texture_uv.png")),
+    ));
+
+    for light in level.lights {
+        world.push((light.position, light.rotation, light.light));
+    }
+
+    level.spawn_points
+}
 
-            fixed_update_step_duration: 1.0 / fixed_update_steps_per_second as f64,
+fn run_dedicated_server(address: SocketAddr) {
+    let transport = UdpTransport::bind(address).expect("failed binding server socket?");
+    log::info!("server listening on {}", transport.local_addr());
+
+    let mut world = World::default();
+    let mut resources = Resources::default();
+    resources.insert(GameClock::new(60));
+    resources.insert(Server::new(Box::new(transport), NetConfig::default()));
+    resources.insert(ServerReplication::default());
+    resources.insert(replication_registry());
+    resources.insert(Events::<DamageEvent>::default());
+    resources.insert(Events::<DeathEvent>::default());
+    resources.insert(Events::<RespawnEvent>::default());
+    resources.insert(SpawnSettings::default());
+    resources.insert(LagCompensationSettings::default());
+    resources.insert(TransformHistory::default());
+
+    load_level(&mut world);
+
+    let mut fixed_update_schedule = Schedule::builder()
+        .add_system(update_server_system())
+        .add_system(assign_network_ids_system())
+        .add_system(move_players_system())
+        .add_system(fire_player_weapons_system())
+        .add_system(update_projectiles_system())
+        .add_system(apply_damage_system())
+        .add_system(update_dead_system())
+        .add_system(respawn_dead_players_system())
+        .add_system(respawn_system())
+        .add_system(print_death_events_system())
+        .add_system(send_snapshots_system())
+        .add_system(record_transforms_system())
+        .add_system(advance_fixed_update_tick_system())
+        .build();
+
+    let fixed_update_step_duration = Duration::from_secs_f64(
+        resources
+            .get::<GameClock>()
+            .expect("failed getting game clock resource?")
+            .fixed_update_step_duration,
+    );
+    let mut next_tick_instant = Instant::now();
+
+    // Without a window there is no event loop to drive us, so tick at the fixed rate ourselves
+    loop {
+        fixed_update_schedule.execute(&mut world, &mut resources);
+
+        next_tick_instant += fixed_update_step_duration;
+        if let Some(remaining) = next_tick_instant.checked_duration_since(Instant::now()) {
+            std::thread::sleep(remaining);
         }
     }
 }
 
 fn main() {
-    env_logger::init();
+    // Our own status messages are shown unless RUST_LOG says otherwise, dependencies only warn
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,game=info"))
+        .init();
+
+    let network_mode =
+        NetworkMode::from_args(std::env::args().skip(1)).expect("invalid command line arguments");
+
+    if let NetworkMode::Server(address) = network_mode {
+        run_dedicated_server(address);
+        return;
+    }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
     let mut world = World::default();
     let mut resources = Resources::default();
+
     resources.insert(block_on(renderer::State::new(&window)));
+    resources.insert(AssetServer::default());
+    resources.insert(RenderAssets::default());
+    resources.insert(MeshBounds::default());
+    resources.insert(RenderScene::default());
     resources.insert(GameClock::new(60));
     resources.insert(Input::default());
+    resources.insert(Events::<DamageEvent>::default());
+    resources.insert(Events::<DeathEvent>::default());
+    resources.insert(Events::<RespawnEvent>::default());
+    resources.insert(SpawnSettings::default());
+
+    let spawn_points = load_level(&mut world);
+
+    let player_team = Team::Red;
+    let (spawn_position, spawn_rotation) =
+        select_spawn_point(&spawn_points, Some(player_team), &[]).map_or(
+            (
+                Position::new(0.0, 0.0, 10.0),
+                Rotation::from_euler_angles(0.0, 0.0, 0.0).normalized(),
+            ),
+            |spawn_point| (spawn_point.position, spawn_point.rotation),
+        );
+
+    let player_prefab =
+        Prefab::load("./src/assets/prefabs/player.json").expect("failed loading player prefab?");
+    let player = spawn_prefab(&mut world, &player_prefab, spawn_position, spawn_rotation)
+        .expect("failed spawning player?");
+    world
+        .entry(player)
+        .expect("failed getting player entry?")
+        .add_component(player_team);
+
+    let mut update_schedule_builder = Schedule::builder();
+    let mut fixed_update_schedule_builder = Schedule::builder();
+
+    if let NetworkMode::Client(server_address) = network_mode {
+        let transport = UdpTransport::bind(SocketAddr::from(([0, 0, 0, 0], 0)))
+            .expect("failed binding client socket?");
+        resources.insert(Client::connect(
+            Box::new(transport),
+            server_address,
+            NetConfig::default(),
+            Instant::now(),
+        ));
+        resources.insert(ClientReplication::default());
+        resources.insert(replication_registry());
+        resources.insert(Prediction::default());
+        resources.insert(InterpolationSettings::default());
+        resources.insert(InterpolationClock::default());
+        world
+            .entry(player)
+            .expect("failed getting player entry?")
+            .add_component(PredictionError::default());
+
+        update_schedule_builder
+            .add_system(update_interpolation_clock_system())
+            .add_system(interpolate_remote_entities_system())
+            .add_system(smooth_prediction_error_system());
+        fixed_update_schedule_builder
+            .add_system(update_client_system())
+            .add_system(apply_snapshots_system())
+            .add_system(predict_local_player_system());
+    } else {
+        // Clients get damage, deaths and respawns from the server's snapshots
+        fixed_update_schedule_builder
+            .add_system(move_local_player_system())
+            .add_system(fire_weapons_system())
+            .add_system(update_projectiles_system())
+            .add_system(apply_damage_system())
+            .add_system(update_dead_system())
+            .add_system(respawn_dead_players_system())
+            .add_system(respawn_system());
+    }
 
-    world.push((
-        Position::new(0.0, 0.0, 10.0),
-        Rotation::from_euler_angles(0.0, 0.0, 0.0).normalized(),
-        Camera::new(16.0 / 9.0, 45.0f32.to_radians(), 0.1, 100.0),
-    ));
+    // Release builds get their assets from a fixed location, nobody is editing them
+    if cfg!(debug_assertions) {
+        resources.insert(FileWatcher::new(&[PathBuf::from("./src/assets")]));
+        update_schedule_builder.add_system(hot_reload_system());
+    }
 
-    let mut update_schedule = Schedule::builder()
+    let mut update_schedule = update_schedule_builder
         // .add_system(update_print_system())
+        .add_system(update_assets_system())
+        .add_system(update_render_assets_system::<State>())
         .add_system(update_mouse_system())
-        .add_system(move_camera_system())
-        .add_system(render_system())
+        .add_system(extract_render_scene_system())
+        .add_system(cull_render_scene_system())
+        .add_system(render_system::<State>())
         .build();
 
-    let mut fixed_update_schedule = Schedule::builder()
+    let mut fixed_update_schedule = fixed_update_schedule_builder
         // .add_system(fixed_update_print_system())
+        .add_system(print_death_events_system())
+        .add_system(advance_fixed_update_tick_system())
         .build();
 
     let mut fixed_update_time_accumulator = 0.0;
@@ -181,6 +350,38 @@ fn main() {
                     if input_manager.key_held(VirtualKeyCode::Escape) {
                         *control_flow = ControlFlow::Exit
                     }
+
+                    if input.state == ElementState::Pressed
+                        && input.virtual_keycode == Some(VirtualKeyCode::F12)
+                    {
+                        resources
+                            .get_mut::<State>()
+                            .expect("failed getting renderer resource?")
+                            .request_screenshot(screenshot_path());
+                    }
+
+                    // Only offline games are saved, clients get their world from the server
+                    if input.state == ElementState::Pressed && network_mode == NetworkMode::Offline
+                    {
+                        match input.virtual_keycode {
+                            Some(VirtualKeyCode::F5) => match quicksave(&world) {
+                                Ok(()) => log::info!("saved {}", QUICKSAVE_PATH),
+                                Err(error) => log::error!("failed saving: {:#}", error),
+                            },
+                            Some(VirtualKeyCode::F9) => match quickload(&mut world) {
+                                Ok(()) => log::info!("loaded {}", QUICKSAVE_PATH),
+                                Err(error) => log::error!("failed loading: {:#}", error),
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let mut input_manager = resources
+                        .get_mut::<Input>()
+                        .expect("failed getting input resource?");
+
+                    input_manager.process_mouse_button(*button, *state);
                 }
                 WindowEvent::CursorMoved { position, .. } => {
                     let mut input_manager = resources
@@ -235,17 +436,44 @@ fn main() {
     });
 }
 
-#[system(for_each)]
-fn render(
-    #[resource] renderer: &mut State,
-    position: &Position,
-    rotation: &mut Rotation,
-    camera: &mut Camera,
-) {
-    let camera_matrix = Similarity3::new(position.clone(), rotation.clone(), 1.0)
-        .into_homogeneous_matrix()
-        .inversed();
-    let projection_matrix = camera.projection_matrix;
+const QUICKSAVE_PATH: &str = "./saves/quicksave.bin";
+
+/// Saves everything but the level geometry, which comes from the level file.
+fn quicksave(world: &World) -> anyhow::Result<()> {
+    save_world_to_file(
+        world,
+        !component::<StaticCollider>(),
+        Path::new(QUICKSAVE_PATH),
+    )
+}
+
+/// Replaces everything `quicksave` saved with the quicksave. The old entities are only removed
+/// once it loaded, a broken save leaves the game as it was.
+fn quickload(world: &mut World) -> anyhow::Result<()> {
+    let mut loaded = World::default();
+    load_world_from_file(&mut loaded, Path::new(QUICKSAVE_PATH))?;
+
+    let saved = <Entity>::query()
+        .filter(!component::<StaticCollider>())
+        .iter(world)
+        .copied()
+        .collect::<Vec<_>>();
+    for entity in saved {
+        world.remove(entity);
+    }
+    world.move_from(&mut loaded, &any());
+
+    Ok(())
+}
 
-    renderer.render(projection_matrix * camera_matrix)
+/// A new file in `./screenshots` named after the current time.
+fn screenshot_path() -> PathBuf {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from(format!(
+        "./screenshots/screenshot_{}_{:03}.png",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    ))
 }
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::net::packet::ReliableMessage;
+
+// Keeps a data packet comfortably below a typical MTU
+const MAX_RELIABLE_BYTES_PER_PACKET: usize = 1024;
+
+struct PendingMessage {
+    sequence: u32,
+    payload: Vec<u8>,
+    last_sent: Option<Instant>,
+}
+
+/// Ordered reliable delivery on top of unreliable datagrams using cumulative acks.
+#[derive(Default)]
+pub struct ReliableChannel {
+    next_send_sequence: u32,
+    unacked: VecDeque<PendingMessage>,
+
+    next_receive_sequence: u32,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+}
+
+impl ReliableChannel {
+    pub fn send(&mut self, payload: Vec<u8>) {
+        self.unacked.push_back(PendingMessage {
+            sequence: self.next_send_sequence,
+            payload,
+            last_sent: None,
+        });
+        self.next_send_sequence = self.next_send_sequence.wrapping_add(1);
+    }
+
+    /// Messages that were never sent or weren't acknowledged within `resend_interval`.
+    pub fn messages_to_send(
+        &mut self,
+        now: Instant,
+        resend_interval: Duration,
+    ) -> Vec<ReliableMessage> {
+        let mut messages = Vec::new();
+        let mut bytes = 0;
+
+        for pending in self.unacked.iter_mut() {
+            let due = match pending.last_sent {
+                Some(last_sent) => now.duration_since(last_sent) >= resend_interval,
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+
+            if !messages.is_empty() && bytes + pending.payload.len() > MAX_RELIABLE_BYTES_PER_PACKET
+            {
+                break;
+            }
+
+            bytes += pending.payload.len();
+            pending.last_sent = Some(now);
+            messages.push(ReliableMessage {
+                sequence: pending.sequence,
+                payload: pending.payload.clone(),
+            });
+        }
+
+        messages
+    }
+
+    pub fn process_ack(&mut self, ack: u32) {
+        while let Some(pending) = self.unacked.front() {
+            if sequence_less_than(pending.sequence, ack) {
+                self.unacked.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Buffers `message` and appends every message that is now in order to `delivered`.
+    pub fn receive(&mut self, message: ReliableMessage, delivered: &mut Vec<Vec<u8>>) {
+        if sequence_less_than(message.sequence, self.next_receive_sequence) {
+            // Already delivered, our ack must have been lost
+            return;
+        }
+
+        self.out_of_order.insert(message.sequence, message.payload);
+
+        while let Some(payload) = self.out_of_order.remove(&self.next_receive_sequence) {
+            delivered.push(payload);
+            self.next_receive_sequence = self.next_receive_sequence.wrapping_add(1);
+        }
+    }
+
+    pub fn ack(&self) -> u32 {
+        self.next_receive_sequence
+    }
+
+    pub fn unacked_count(&self) -> usize {
+        self.unacked.len()
+    }
+}
+
+/// Wrapping comparison so sequences keep working after overflowing.
+fn sequence_less_than(left: u32, right: u32) -> bool {
+    left != right && right.wrapping_sub(left) < u32::MAX / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reliable_channel_orders_and_deduplicates() {
+        let mut channel = ReliableChannel::default();
+        let mut delivered = Vec::new();
+        let message = |sequence: u32| ReliableMessage {
+            sequence,
+            payload: vec![sequence as u8],
+        };
+
+        channel.receive(message(1), &mut delivered);
+        assert!(delivered.is_empty());
+
+        channel.receive(message(0), &mut delivered);
+        channel.receive(message(0), &mut delivered);
+        channel.receive(message(2), &mut delivered);
+
+        assert_eq!(delivered, vec![vec![0], vec![1], vec![2]]);
+        assert_eq!(channel.ack(), 3);
+    }
+
+    #[test]
+    fn reliable_channel_resends_until_acked() {
+        let mut channel = ReliableChannel::default();
+        let now = Instant::now();
+        let resend_interval = Duration::from_millis(100);
+        channel.send(b"important".to_vec());
+
+        assert_eq!(channel.messages_to_send(now, resend_interval).len(), 1);
+        assert!(channel.messages_to_send(now, resend_interval).is_empty());
+        assert_eq!(
+            channel
+                .messages_to_send(now + resend_interval, resend_interval)
+                .len(),
+            1
+        );
+
+        channel.process_ack(1);
+        assert_eq!(channel.unacked_count(), 0);
+        assert!(channel
+            .messages_to_send(now + resend_interval * 2, resend_interval)
+            .is_empty());
+    }
+}
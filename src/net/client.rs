@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::connection::Connection;
+use crate::net::packet::{ChannelKind, ClientId, Packet, RejectReason};
+use crate::net::transport::Transport;
+use crate::net::NetConfig;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    TimedOut,
+    Rejected(RejectReason),
+    Closed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClientState {
+    Connecting {
+        started: Instant,
+        last_request: Option<Instant>,
+    },
+    Connected {
+        client_id: ClientId,
+    },
+    Disconnected(DisconnectReason),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Connected(ClientId),
+    Disconnected(DisconnectReason),
+    Message(ChannelKind, Vec<u8>),
+}
+
+pub struct Client {
+    transport: Box<dyn Transport>,
+    config: NetConfig,
+    state: ClientState,
+    connection: Connection,
+}
+
+impl Client {
+    pub fn connect(
+        transport: Box<dyn Transport>,
+        server_address: SocketAddr,
+        config: NetConfig,
+        now: Instant,
+    ) -> Self {
+        Self {
+            transport,
+            config,
+            state: ClientState::Connecting {
+                started: now,
+                last_request: None,
+            },
+            connection: Connection::new(server_address, now),
+        }
+    }
+
+    pub fn state(&self) -> ClientState {
+        self.state
+    }
+
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self.state {
+            ClientState::Connected { client_id } => Some(client_id),
+            _ => None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client_id().is_some()
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+
+    /// Messages sent while still connecting go out once the handshake completes.
+    pub fn send(&mut self, channel: ChannelKind, payload: Vec<u8>) {
+        self.connection.send(channel, payload);
+    }
+
+    pub fn disconnect(&mut self) {
+        if self.is_connected() {
+            self.send_packet(&Packet::Disconnect);
+        }
+        self.state = ClientState::Disconnected(DisconnectReason::Closed);
+    }
+
+    pub fn update(&mut self, now: Instant) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+
+        if let ClientState::Disconnected(_) = self.state {
+            return events;
+        }
+
+        loop {
+            let (from, bytes) = match self.transport.recv() {
+                Ok(Some(datagram)) => datagram,
+                Ok(None) => break,
+                Err(error) => {
+                    log::warn!("client receive failed: {}", error);
+                    break;
+                }
+            };
+
+            if from != self.connection.remote {
+                continue;
+            }
+
+            if let Some(packet) = Packet::decode(&bytes) {
+                self.process_packet(packet, now, &mut events);
+            }
+        }
+
+        while let Some((channel, payload)) = self.connection.receive() {
+            events.push(ClientEvent::Message(channel, payload));
+        }
+
+        match self.state {
+            ClientState::Connecting {
+                started,
+                last_request,
+            } => {
+                let request_due = match last_request {
+                    Some(last_request) => {
+                        now.duration_since(last_request) >= self.config.connect_retry_interval
+                    }
+                    None => true,
+                };
+
+                if now.duration_since(started) >= self.config.timeout {
+                    self.disconnect_with(DisconnectReason::TimedOut, &mut events);
+                } else if request_due {
+                    self.send_packet(&Packet::ConnectRequest);
+                    self.state = ClientState::Connecting {
+                        started,
+                        last_request: Some(now),
+                    };
+                }
+            }
+            ClientState::Connected { .. } => {
+                if self.connection.is_timed_out(now, &self.config) {
+                    self.disconnect_with(DisconnectReason::TimedOut, &mut events);
+                } else {
+                    while let Some(packet) = self.connection.poll_packet(now, &self.config) {
+                        self.send_packet(&packet);
+                    }
+                }
+            }
+            ClientState::Disconnected(_) => {}
+        }
+
+        events
+    }
+
+    fn process_packet(&mut self, packet: Packet, now: Instant, events: &mut Vec<ClientEvent>) {
+        match (packet, self.state) {
+            (Packet::ConnectAccepted { client_id }, ClientState::Connecting { .. }) => {
+                self.connection.mark_received(now);
+                self.state = ClientState::Connected { client_id };
+                events.push(ClientEvent::Connected(client_id));
+            }
+            (Packet::ConnectRejected { reason }, ClientState::Connecting { .. }) => {
+                self.disconnect_with(DisconnectReason::Rejected(reason), events);
+            }
+            (Packet::Disconnect, ClientState::Connected { .. }) => {
+                self.disconnect_with(DisconnectReason::Closed, events);
+            }
+            (
+                Packet::Data {
+                    reliable_ack,
+                    reliable,
+                    unreliable,
+                },
+                ClientState::Connected { .. },
+            ) => {
+                self.connection
+                    .process_data(now, reliable_ack, reliable, unreliable);
+            }
+            _ => {}
+        }
+    }
+
+    fn disconnect_with(&mut self, reason: DisconnectReason, events: &mut Vec<ClientEvent>) {
+        self.state = ClientState::Disconnected(reason);
+        events.push(ClientEvent::Disconnected(reason));
+    }
+
+    fn send_packet(&mut self, packet: &Packet) {
+        if let Err(error) = self
+            .transport
+            .send(self.connection.remote, &packet.encode())
+        {
+            log::warn!("client send failed: {}", error);
+        }
+    }
+}
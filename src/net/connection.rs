@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::channel::ReliableChannel;
+use crate::net::packet::{ChannelKind, Packet, ReliableMessage};
+use crate::net::NetConfig;
+
+// Fits the MTU of links that tunnel or use IPv6, larger datagrams are fragmented by IP and get
+// lost whenever any fragment is
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+/// State of one end of an established client-server link.
+pub struct Connection {
+    pub remote: SocketAddr,
+
+    reliable: ReliableChannel,
+    outgoing_unreliable: VecDeque<Vec<u8>>,
+    received: VecDeque<(ChannelKind, Vec<u8>)>,
+    ack_pending: bool,
+
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Connection {
+    pub fn new(remote: SocketAddr, now: Instant) -> Self {
+        Self {
+            remote,
+            reliable: ReliableChannel::default(),
+            outgoing_unreliable: VecDeque::new(),
+            received: VecDeque::new(),
+            ack_pending: false,
+            last_received: now,
+            last_sent: now,
+        }
+    }
+
+    pub fn send(&mut self, channel: ChannelKind, payload: Vec<u8>) {
+        match channel {
+            ChannelKind::Reliable => self.reliable.send(payload),
+            ChannelKind::Unreliable => self.outgoing_unreliable.push_back(payload),
+        }
+    }
+
+    pub fn receive(&mut self) -> Option<(ChannelKind, Vec<u8>)> {
+        self.received.pop_front()
+    }
+
+    pub fn mark_received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    pub fn process_data(
+        &mut self,
+        now: Instant,
+        reliable_ack: u32,
+        reliable: Vec<ReliableMessage>,
+        unreliable: Vec<Vec<u8>>,
+    ) {
+        self.mark_received(now);
+        self.reliable.process_ack(reliable_ack);
+
+        if !reliable.is_empty() {
+            self.ack_pending = true;
+        }
+
+        let mut delivered = Vec::new();
+        for message in reliable {
+            self.reliable.receive(message, &mut delivered);
+        }
+
+        self.received.extend(
+            delivered
+                .into_iter()
+                .map(|payload| (ChannelKind::Reliable, payload)),
+        );
+        self.received.extend(
+            unreliable
+                .into_iter()
+                .map(|payload| (ChannelKind::Unreliable, payload)),
+        );
+    }
+
+    /// Next data packet to send, an empty one when nothing was sent for a keep-alive interval.
+    /// Packets stay within `MAX_PACKET_SIZE`, unreliable payloads that don't fit wait for the
+    /// next one, so call this until it returns `None`.
+    pub fn poll_packet(&mut self, now: Instant, config: &NetConfig) -> Option<Packet> {
+        let reliable = self.reliable.messages_to_send(now, config.resend_interval);
+        let keep_alive_due = now.duration_since(self.last_sent) >= config.keep_alive_interval;
+
+        if reliable.is_empty()
+            && self.outgoing_unreliable.is_empty()
+            && !self.ack_pending
+            && !keep_alive_due
+        {
+            return None;
+        }
+
+        let mut size = Packet::data_size(&reliable, &[]);
+        let mut unreliable = Vec::new();
+        while let Some(payload) = self.outgoing_unreliable.front() {
+            let payload_size = Packet::unreliable_payload_size(payload);
+            let fits = size + payload_size <= MAX_PACKET_SIZE;
+            // Payloads too big for any packet go on their own, IP fragments them on the way
+            let alone = reliable.is_empty() && unreliable.is_empty();
+            if !fits && !alone {
+                break;
+            }
+
+            size += payload_size;
+            unreliable.extend(self.outgoing_unreliable.pop_front());
+            if !fits {
+                break;
+            }
+        }
+
+        self.ack_pending = false;
+        self.last_sent = now;
+
+        Some(Packet::Data {
+            reliable_ack: self.reliable.ack(),
+            reliable,
+            unreliable,
+        })
+    }
+
+    pub fn is_timed_out(&self, now: Instant, config: &NetConfig) -> bool {
+        now.duration_since(self.last_received) >= config.timeout
+    }
+
+    pub fn unacked_count(&self) -> usize {
+        self.reliable.unacked_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::server::tests::address;
+
+    #[test]
+    fn data_packets_stay_below_the_mtu() {
+        let now = Instant::now();
+        let config = NetConfig::default();
+        let mut connection = Connection::new(address(4000), now);
+
+        let payloads = (0..10u8).map(|i| vec![i; 300]).collect::<Vec<_>>();
+        connection.send(ChannelKind::Reliable, vec![255; 100]);
+        for payload in &payloads {
+            connection.send(ChannelKind::Unreliable, payload.clone());
+        }
+
+        let mut packets = Vec::new();
+        while let Some(packet) = connection.poll_packet(now, &config) {
+            packets.push(packet);
+        }
+        assert!(packets.len() > 1);
+
+        let mut unreliable_received = Vec::new();
+        for packet in packets {
+            let size = packet.encode().len();
+            assert!(size <= MAX_PACKET_SIZE, "{} byte packet", size);
+            match packet {
+                Packet::Data {
+                    reliable,
+                    unreliable,
+                    ..
+                } => {
+                    assert_eq!(size, Packet::data_size(&reliable, &unreliable));
+                    unreliable_received.extend(unreliable);
+                }
+                _ => panic!("expected a data packet"),
+            }
+        }
+        assert_eq!(unreliable_received, payloads);
+    }
+}
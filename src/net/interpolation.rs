@@ -0,0 +1,89 @@
+/// Render clock further than this many ticks off its target jumps instead of drifting back.
+const CLOCK_SNAP_TICKS: f64 = 10.0;
+/// Fraction of the distance to its target the render clock closes each second.
+const CLOCK_CORRECTION_PER_SECOND: f64 = 2.0;
+
+pub struct InterpolationSettings {
+    /// How far behind the newest snapshot remote entities are drawn. A couple of snapshot
+    /// intervals, so a lost snapshot still leaves a newer one to interpolate towards.
+    pub delay_in_seconds: f32,
+    /// How long to keep moving entities along their last velocity when snapshots stop coming.
+    pub max_extrapolation_in_seconds: f32,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self {
+            delay_in_seconds: 0.1,
+            max_extrapolation_in_seconds: 0.25,
+        }
+    }
+}
+
+/// Fractional server tick remote entities are drawn at, advanced every frame.
+#[derive(Debug, Default)]
+pub struct InterpolationClock {
+    render_tick: Option<f64>,
+}
+
+impl InterpolationClock {
+    pub fn render_tick(&self) -> Option<f64> {
+        self.render_tick
+    }
+
+    /// Moves the clock forward by a frame while steering it towards `delay_in_seconds` behind
+    /// the newest received snapshot.
+    pub fn advance(
+        &mut self,
+        latest_tick: Option<u32>,
+        frame_in_seconds: f64,
+        tick_in_seconds: f64,
+        settings: &InterpolationSettings,
+    ) {
+        let latest_tick = match latest_tick {
+            Some(latest_tick) => latest_tick as f64,
+            None => return,
+        };
+        let target = latest_tick - settings.delay_in_seconds as f64 / tick_in_seconds;
+
+        let render_tick = match self.render_tick {
+            Some(render_tick) if (render_tick - target).abs() <= CLOCK_SNAP_TICKS => {
+                let render_tick = render_tick + frame_in_seconds / tick_in_seconds;
+                let correction = (frame_in_seconds * CLOCK_CORRECTION_PER_SECOND).min(1.0);
+                render_tick + (target - render_tick) * correction
+            }
+            _ => target,
+        };
+
+        self.render_tick = Some(render_tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_clock_trails_latest_snapshot() {
+        let settings = InterpolationSettings::default();
+        let tick_in_seconds = 1.0 / 60.0;
+        let mut interpolation_clock = InterpolationClock::default();
+
+        interpolation_clock.advance(None, tick_in_seconds, tick_in_seconds, &settings);
+        assert_eq!(interpolation_clock.render_tick(), None);
+
+        // 0.1 seconds of delay is 6 ticks behind
+        interpolation_clock.advance(Some(100), tick_in_seconds, tick_in_seconds, &settings);
+        assert!((interpolation_clock.render_tick().unwrap() - 94.0).abs() < 0.0001);
+
+        // Snapshots keep arriving at the tick rate, so the clock keeps pace with them
+        for tick in 101..200 {
+            interpolation_clock.advance(Some(tick), tick_in_seconds, tick_in_seconds, &settings);
+        }
+        assert!((interpolation_clock.render_tick().unwrap() - 193.0).abs() < 0.01);
+
+        // Far behind after a long stall it jumps instead of fast forwarding
+        interpolation_clock.advance(Some(1000), tick_in_seconds, tick_in_seconds, &settings);
+        assert!((interpolation_clock.render_tick().unwrap() - 994.0).abs() < 0.0001);
+    }
+}
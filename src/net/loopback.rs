@@ -0,0 +1,75 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::net::transport::Transport;
+
+type Inboxes = HashMap<SocketAddr, VecDeque<(SocketAddr, Vec<u8>)>>;
+
+/// In-process network, transports bound on it deliver to each other without touching sockets.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    inboxes: Arc<Mutex<Inboxes>>,
+}
+
+impl LoopbackNetwork {
+    pub fn bind(&self, address: SocketAddr) -> LoopbackTransport {
+        self.inboxes
+            .lock()
+            .expect("loopback network lock poisoned?")
+            .entry(address)
+            .or_default();
+
+        LoopbackTransport {
+            address,
+            network: self.clone(),
+        }
+    }
+}
+
+pub struct LoopbackTransport {
+    address: SocketAddr,
+    network: LoopbackNetwork,
+}
+
+impl Transport for LoopbackTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn send(&mut self, to: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        let mut inboxes = self
+            .network
+            .inboxes
+            .lock()
+            .expect("loopback network lock poisoned?");
+
+        // Like UDP, datagrams to an address nobody is bound to are silently dropped
+        if let Some(inbox) = inboxes.get_mut(&to) {
+            inbox.push_back((self.address, payload.to_vec()));
+        }
+
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        let mut inboxes = self
+            .network
+            .inboxes
+            .lock()
+            .expect("loopback network lock poisoned?");
+
+        Ok(inboxes
+            .get_mut(&self.address)
+            .and_then(|inbox| inbox.pop_front()))
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        if let Ok(mut inboxes) = self.network.inboxes.lock() {
+            inboxes.remove(&self.address);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::code::components::player_input::PlayerInput;
+use crate::net::replication::{NetworkId, SnapshotDelta};
+
+/// Game level messages the server sends inside packet payloads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// Sent reliably after connecting, tells the client which replicated entity is its player.
+    Welcome { network_id: NetworkId },
+    Snapshot {
+        delta: SnapshotDelta,
+        /// Newest input of the receiving client the server had simulated, for reconciliation.
+        last_input_tick: Option<u32>,
+    },
+}
+
+/// Game level messages clients send inside packet payloads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    SnapshotAck {
+        tick: u32,
+    },
+    /// Latest inputs keyed by client tick, oldest first. Recent ones are repeated every time.
+    Input {
+        inputs: Vec<(u32, PlayerInput)>,
+    },
+}
+
+// Varint encoding keeps the quantized integers in snapshots down to a byte or two each, also
+// used for the component values inside them
+pub(crate) fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    bincode::DefaultOptions::new()
+        .serialize(message)
+        .expect("failed serializing message?")
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    bincode::DefaultOptions::new().deserialize(bytes).ok()
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        decode(bytes)
+    }
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        decode(bytes)
+    }
+}
@@ -0,0 +1,78 @@
+pub mod channel;
+pub mod client;
+pub mod connection;
+pub mod interpolation;
+#[cfg(test)]
+pub mod loopback;
+pub mod message;
+pub mod packet;
+pub mod prediction;
+pub mod replication;
+pub mod server;
+#[cfg(test)]
+pub mod simulator;
+pub mod transport;
+pub mod udp;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::*;
+
+pub const DEFAULT_PORT: u16 = 27015;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkMode {
+    Offline,
+    /// Authoritative dedicated server listening on the address.
+    Server(SocketAddr),
+    /// Client connecting to the server at the address.
+    Client(SocketAddr),
+}
+
+impl NetworkMode {
+    /// Parses `--server [address]` or `--connect <address>`, anything else plays offline.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self> {
+        match args.next().as_deref() {
+            Some("--server") => {
+                let address = match args.next() {
+                    Some(address) => address.parse().context("Invalid server address")?,
+                    None => SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+                };
+                Ok(NetworkMode::Server(address))
+            }
+            Some("--connect") => {
+                let address = args
+                    .next()
+                    .context("--connect requires a server address")?
+                    .parse()
+                    .context("Invalid server address")?;
+                Ok(NetworkMode::Client(address))
+            }
+            Some(argument) => bail!("Unknown argument: {}", argument),
+            None => Ok(NetworkMode::Offline),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    pub max_clients: usize,
+    /// Connection is dropped when nothing was received from the other side for this long.
+    pub timeout: Duration,
+    pub keep_alive_interval: Duration,
+    pub resend_interval: Duration,
+    pub connect_retry_interval: Duration,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            max_clients: 16,
+            timeout: Duration::from_secs(10),
+            keep_alive_interval: Duration::from_millis(250),
+            resend_interval: Duration::from_millis(100),
+            connect_retry_interval: Duration::from_millis(250),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Leading bytes of every packet, datagrams without it are not ours and get dropped.
+pub const PROTOCOL_ID: u32 = 0x4650_5301;
+const PROTOCOL_ID_SIZE: usize = std::mem::size_of::<u32>();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ClientId(pub u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelKind {
+    /// Delivered exactly once and in order, resent until acknowledged.
+    Reliable,
+    /// Fire and forget, may be lost, duplicated or arrive out of order.
+    Unreliable,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    ServerFull,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReliableMessage {
+    pub sequence: u32,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Packet {
+    ConnectRequest,
+    ConnectAccepted {
+        client_id: ClientId,
+    },
+    ConnectRejected {
+        reason: RejectReason,
+    },
+    Disconnect,
+    /// Also sent empty as a keep-alive.
+    Data {
+        /// Next reliable sequence the sender expects, acknowledges everything before it.
+        reliable_ack: u32,
+        reliable: Vec<ReliableMessage>,
+        unreliable: Vec<Vec<u8>>,
+    },
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = PROTOCOL_ID.to_le_bytes().to_vec();
+        bincode::serialize_into(&mut bytes, self).expect("failed serializing packet?");
+        bytes
+    }
+
+    /// Encoded size of a `Packet::Data` carrying these messages.
+    pub fn data_size(reliable: &[ReliableMessage], unreliable: &[Vec<u8>]) -> usize {
+        // Bincode writes the variant index, then the fields, with lists behind their length
+        let variant_and_ack = 4 + 4;
+        let reliable_size = reliable
+            .iter()
+            .map(|message| 4 + Self::unreliable_payload_size(&message.payload))
+            .sum::<usize>();
+        let unreliable_size = unreliable
+            .iter()
+            .map(|payload| Self::unreliable_payload_size(payload))
+            .sum::<usize>();
+
+        PROTOCOL_ID_SIZE + variant_and_ack + 8 + reliable_size + 8 + unreliable_size
+    }
+
+    /// What adding `payload` to the unreliable list adds to a `Packet::Data`.
+    pub fn unreliable_payload_size(payload: &[u8]) -> usize {
+        8 + payload.len()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[..4] != PROTOCOL_ID.to_le_bytes() {
+            return None;
+        }
+
+        bincode::deserialize(&bytes[4..]).ok()
+    }
+}
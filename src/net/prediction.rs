@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use crate::code::components::{player_input::PlayerInput, position::Position, rotation::Rotation};
+use crate::code::systems::movement::apply_player_input;
+
+/// Ticks of input kept for replaying, two seconds at 60 ticks covers any sane round trip.
+pub const INPUT_HISTORY_LENGTH: usize = 120;
+/// Every input message repeats this many of the latest inputs so a lost packet costs nothing.
+pub const INPUT_REDUNDANCY: usize = 3;
+/// Corrections further than this are teleports like respawns and snap instead of smoothing.
+pub const SNAP_DISTANCE: f32 = 2.0;
+/// Time constant of the exponential decay of the drawn prediction error.
+pub const PREDICTION_ERROR_DECAY_IN_SECONDS: f32 = 0.1;
+
+/// Differences below this are quantization noise from the snapshot, not mispredictions.
+const RECONCILE_TOLERANCE: f32 = 0.01;
+
+#[derive(Debug, Copy, Clone)]
+struct PredictedTick {
+    tick: u32,
+    input: PlayerInput,
+    /// Where the local player ended up after simulating `input`.
+    position: Position,
+}
+
+/// Client side input history of the local player, keyed by the client's fixed tick.
+#[derive(Debug, Default)]
+pub struct Prediction {
+    history: VecDeque<PredictedTick>,
+    /// Newest of our inputs the server had simulated in the latest snapshot.
+    pub server_input_tick: Option<u32>,
+}
+
+impl Prediction {
+    pub fn record(&mut self, tick: u32, input: PlayerInput, position: Position) {
+        self.history.push_back(PredictedTick {
+            tick,
+            input,
+            position,
+        });
+
+        if self.history.len() > INPUT_HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+    }
+
+    /// Latest inputs, oldest first, to be sent to the server.
+    pub fn recent_inputs(&self, count: usize) -> Vec<(u32, PlayerInput)> {
+        self.history
+            .iter()
+            .skip(self.history.len().saturating_sub(count))
+            .map(|predicted| (predicted.tick, predicted.input))
+            .collect()
+    }
+
+    /// Compares our prediction for `server_input_tick` with the server's `authoritative`
+    /// position. On a mismatch the inputs the server hasn't seen yet are replayed on top of it
+    /// and the corrected current position is returned.
+    pub fn reconcile(&mut self, authoritative: Position, delta_time: f32) -> Option<Position> {
+        let server_input_tick = self.server_input_tick?;
+
+        while matches!(self.history.front(), Some(predicted) if predicted.tick < server_input_tick)
+        {
+            self.history.pop_front();
+        }
+
+        // Without a prediction for that tick (we were dead or it fell out of the history)
+        // there is nothing to compare against, so always take the server's word for it
+        if let Some(confirmed) = self.history.front() {
+            if confirmed.tick == server_input_tick
+                && (confirmed.position - authoritative).mag() <= RECONCILE_TOLERANCE
+            {
+                return None;
+            }
+        }
+
+        let mut position = authoritative;
+        let mut rotation = Rotation::identity();
+
+        for predicted in self.history.iter_mut() {
+            if predicted.tick > server_input_tick {
+                apply_player_input(&mut position, &mut rotation, &predicted.input, delta_time);
+            }
+            predicted.position = position;
+        }
+
+        Some(position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ultraviolet::{Rotor3, Vec2, Vec3};
+
+    pub(crate) const DELTA_TIME: f32 = 1.0 / 60.0;
+
+    pub(crate) fn walk_forward() -> PlayerInput {
+        PlayerInput {
+            movement: Vec2::new(0.0, 1.0),
+            view: Rotor3::identity(),
+            fire: false,
+            reload: false,
+            view_tick: None,
+        }
+    }
+
+    /// Predicts `ticks` inputs from the origin like the client does and returns the final position.
+    fn predict(prediction: &mut Prediction, ticks: u32) -> Vec3 {
+        let mut position = Vec3::zero();
+        let mut rotation = Rotor3::identity();
+
+        for tick in 0..ticks {
+            apply_player_input(&mut position, &mut rotation, &walk_forward(), DELTA_TIME);
+            prediction.record(tick, walk_forward(), position);
+        }
+
+        position
+    }
+
+    #[test]
+    fn matching_server_state_needs_no_correction() {
+        let mut prediction = Prediction::default();
+        predict(&mut prediction, 10);
+
+        let mut server_position = Vec3::zero();
+        let mut server_rotation = Rotor3::identity();
+        for _ in 0..=4 {
+            apply_player_input(
+                &mut server_position,
+                &mut server_rotation,
+                &walk_forward(),
+                DELTA_TIME,
+            );
+        }
+
+        prediction.server_input_tick = Some(4);
+        assert_eq!(prediction.reconcile(server_position, DELTA_TIME), None);
+        assert_eq!(prediction.recent_inputs(100).len(), 6);
+    }
+
+    #[test]
+    fn misprediction_replays_unacknowledged_inputs() {
+        let mut prediction = Prediction::default();
+        let predicted = predict(&mut prediction, 10);
+
+        // The server pushed us a meter sideways at tick 4, the 5 inputs after it still apply
+        let mut server_position = Vec3::zero();
+        let mut server_rotation = Rotor3::identity();
+        for _ in 0..=4 {
+            apply_player_input(
+                &mut server_position,
+                &mut server_rotation,
+                &walk_forward(),
+                DELTA_TIME,
+            );
+        }
+        server_position.x += 1.0;
+
+        prediction.server_input_tick = Some(4);
+        let corrected = prediction.reconcile(server_position, DELTA_TIME).unwrap();
+        assert!((corrected - (predicted + Vec3::unit_x())).mag() < 0.0001);
+
+        // The replayed history now agrees with the server
+        assert_eq!(prediction.reconcile(server_position, DELTA_TIME), None);
+    }
+}
@@ -0,0 +1,774 @@
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use legion::storage::Component;
+use legion::systems::CommandBuffer;
+use legion::world::{ComponentError, EntryRef};
+use legion::Entity;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ultraviolet::{Bivec3, Rotor3, Vec3};
+
+use crate::code::components::{dead::Dead, health::Health, position::Position, rotation::Rotation};
+use crate::net::message::{decode, encode};
+use crate::net::packet::ClientId;
+
+/// Snapshots kept on both ends to delta against, a client acking older than this gets a full one.
+pub const SNAPSHOT_HISTORY_LENGTH: usize = 32;
+
+/// Quantization steps per meter, 1/256 m is below anything visible at player scale.
+const POSITION_STEPS_PER_METER: f32 = 256.0;
+/// Rotor components are all within -1..1, so they map onto the whole i16 range.
+const ROTATION_STEPS: f32 = i16::MAX as f32;
+/// Health is sent in tenths, multipliers like 0.75 leave fractional values.
+const HEALTH_STEPS_PER_POINT: f32 = 10.0;
+
+/// Identifies a replicated entity across the network, legion `Entity` ids are local to one world.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NetworkId(pub u32);
+
+/// Marks an entity on the server for replication to clients. The components registered in
+/// `replication_registry` are what ends up in snapshots.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Replicated;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedVec3 {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl QuantizedVec3 {
+    pub fn quantize(value: Vec3) -> Self {
+        Self {
+            x: (value.x * POSITION_STEPS_PER_METER).round() as i32,
+            y: (value.y * POSITION_STEPS_PER_METER).round() as i32,
+            z: (value.z * POSITION_STEPS_PER_METER).round() as i32,
+        }
+    }
+
+    pub fn dequantize(&self) -> Vec3 {
+        Vec3::new(self.x as f32, self.y as f32, self.z as f32) / POSITION_STEPS_PER_METER
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedRotor {
+    pub s: i16,
+    pub xy: i16,
+    pub xz: i16,
+    pub yz: i16,
+}
+
+impl QuantizedRotor {
+    pub fn quantize(value: Rotor3) -> Self {
+        let quantize = |component: f32| (component * ROTATION_STEPS).round() as i16;
+
+        Self {
+            s: quantize(value.s),
+            xy: quantize(value.bv.xy),
+            xz: quantize(value.bv.xz),
+            yz: quantize(value.bv.yz),
+        }
+    }
+
+    pub fn dequantize(&self) -> Rotor3 {
+        Rotor3::new(
+            self.s as f32 / ROTATION_STEPS,
+            Bivec3::new(
+                self.xy as f32 / ROTATION_STEPS,
+                self.xz as f32 / ROTATION_STEPS,
+                self.yz as f32 / ROTATION_STEPS,
+            ),
+        )
+        .normalized()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedHealth {
+    pub current: i16,
+    pub max: i16,
+}
+
+fn quantize_health(health: f32) -> i16 {
+    (health * HEALTH_STEPS_PER_POINT).round() as i16
+}
+
+fn dequantize_health(health: i16) -> f32 {
+    health as f32 / HEALTH_STEPS_PER_POINT
+}
+
+/// A component the server sends to clients, quantized to `State` on the way.
+pub trait ReplicatedComponent: Component {
+    type State: Serialize + DeserializeOwned;
+
+    fn quantize(&self) -> Self::State;
+
+    fn dequantize(state: Self::State) -> Self;
+}
+
+impl ReplicatedComponent for Position {
+    type State = QuantizedVec3;
+
+    fn quantize(&self) -> Self::State {
+        QuantizedVec3::quantize(*self)
+    }
+
+    fn dequantize(state: Self::State) -> Self {
+        state.dequantize()
+    }
+}
+
+impl ReplicatedComponent for Rotation {
+    type State = QuantizedRotor;
+
+    fn quantize(&self) -> Self::State {
+        QuantizedRotor::quantize(*self)
+    }
+
+    fn dequantize(state: Self::State) -> Self {
+        state.dequantize()
+    }
+}
+
+impl ReplicatedComponent for Health {
+    type State = QuantizedHealth;
+
+    fn quantize(&self) -> Self::State {
+        QuantizedHealth {
+            current: quantize_health(self.current),
+            max: quantize_health(self.max),
+        }
+    }
+
+    fn dequantize(state: Self::State) -> Self {
+        Health {
+            current: dequantize_health(state.current),
+            max: dequantize_health(state.max),
+        }
+    }
+}
+
+// Only whether the entity is dead is sent, the client counts the time since death itself
+impl ReplicatedComponent for Dead {
+    type State = ();
+
+    fn quantize(&self) -> Self::State {}
+
+    fn dequantize(_: Self::State) -> Self {
+        Dead::default()
+    }
+}
+
+/// Index of a component in the `ReplicationRegistry`, snapshots key component values by it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ComponentId(pub u8);
+
+/// How clients take changes to a replicated component.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ApplyMode {
+    /// The component is written whenever its value changes and removed when it's gone.
+    Overwrite,
+    /// The component is only written when the entity is spawned, `apply_snapshots` feeds later
+    /// values into prediction and interpolation instead.
+    Spawn,
+}
+
+struct RegisteredComponent {
+    type_id: TypeId,
+    apply_mode: ApplyMode,
+    capture: fn(&EntryRef) -> Option<Vec<u8>>,
+    insert: fn(&mut CommandBuffer, Entity, &[u8]),
+    remove: fn(&mut CommandBuffer, Entity),
+}
+
+fn capture<T: ReplicatedComponent>(entry: &EntryRef) -> Option<Vec<u8>> {
+    match entry.get_component::<T>() {
+        Ok(component) => Some(encode(&component.quantize())),
+        Err(ComponentError::Denied { component_name, .. }) => {
+            panic!("replicated {} without read access?", component_name)
+        }
+        Err(_) => None,
+    }
+}
+
+fn insert<T: ReplicatedComponent>(commands: &mut CommandBuffer, entity: Entity, bytes: &[u8]) {
+    if let Some(state) = decode::<T::State>(bytes) {
+        commands.add_component(entity, T::dequantize(state));
+    }
+}
+
+fn remove<T: ReplicatedComponent>(commands: &mut CommandBuffer, entity: Entity) {
+    commands.remove_component::<T>(entity);
+}
+
+/// Components that are replicated, in the order their `ComponentId`s are handed out.
+#[derive(Default)]
+pub struct ReplicationRegistry {
+    components: Vec<RegisteredComponent>,
+}
+
+impl ReplicationRegistry {
+    pub fn register<T: ReplicatedComponent>(&mut self, apply_mode: ApplyMode) {
+        assert!(
+            self.components.len() < u8::MAX as usize,
+            "too many replicated components?"
+        );
+
+        self.components.push(RegisteredComponent {
+            type_id: TypeId::of::<T>(),
+            apply_mode,
+            capture: capture::<T>,
+            insert: insert::<T>,
+            remove: remove::<T>,
+        });
+    }
+
+    pub fn id<T: ReplicatedComponent>(&self) -> Option<ComponentId> {
+        self.components
+            .iter()
+            .position(|component| component.type_id == TypeId::of::<T>())
+            .map(|index| ComponentId(index as u8))
+    }
+
+    fn component(&self, id: ComponentId) -> Option<&RegisteredComponent> {
+        self.components.get(id.0 as usize)
+    }
+
+    /// Quantizes the registered components the entity has.
+    pub fn capture(&self, entry: &EntryRef) -> EntityState {
+        let components = self
+            .components
+            .iter()
+            .enumerate()
+            .filter_map(|(index, component)| {
+                (component.capture)(entry).map(|bytes| (ComponentId(index as u8), bytes))
+            })
+            .collect();
+
+        EntityState { components }
+    }
+
+    /// Value of one component in `state`, if the entity has it.
+    pub fn get<T: ReplicatedComponent>(&self, state: &EntityState) -> Option<T> {
+        let bytes = state.components.get(&self.id::<T>()?)?;
+        decode::<T::State>(bytes).map(T::dequantize)
+    }
+
+    /// Adds every component in `state` to a newly spawned entity.
+    pub fn spawn(&self, commands: &mut CommandBuffer, entity: Entity, state: &EntityState) {
+        for (id, bytes) in state.components.iter() {
+            if let Some(component) = self.component(*id) {
+                (component.insert)(commands, entity, bytes);
+            }
+        }
+    }
+
+    /// Writes the `Overwrite` components that changed since `previous`, the state last applied to
+    /// the entity. Unchanged ones are left alone so client side state like `Dead`'s timer keeps
+    /// running.
+    pub fn apply(
+        &self,
+        commands: &mut CommandBuffer,
+        entity: Entity,
+        previous: Option<&EntityState>,
+        state: &EntityState,
+    ) {
+        for (index, component) in self.components.iter().enumerate() {
+            if component.apply_mode != ApplyMode::Overwrite {
+                continue;
+            }
+
+            let id = ComponentId(index as u8);
+            let previous_bytes = previous.and_then(|previous| previous.components.get(&id));
+            match state.components.get(&id) {
+                Some(bytes) if previous_bytes != Some(bytes) => {
+                    (component.insert)(commands, entity, bytes)
+                }
+                None if previous_bytes.is_some() => (component.remove)(commands, entity),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Components replicated to clients. Both ends build it here so the `ComponentId`s agree, and
+/// `send_snapshots` has to declare read access to every component registered.
+pub fn replication_registry() -> ReplicationRegistry {
+    let mut registry = ReplicationRegistry::default();
+
+    registry.register::<Position>(ApplyMode::Spawn);
+    registry.register::<Rotation>(ApplyMode::Spawn);
+    registry.register::<Health>(ApplyMode::Overwrite);
+    registry.register::<Dead>(ApplyMode::Overwrite);
+
+    registry
+}
+
+/// Replicated components of a single entity, quantized and encoded by the `ReplicationRegistry`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityState {
+    pub components: BTreeMap<ComponentId, Vec<u8>>,
+}
+
+/// Components of an `EntityState` that changed or were taken off since the baseline.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityDelta {
+    pub changed: Vec<(ComponentId, Vec<u8>)>,
+    pub removed: Vec<ComponentId>,
+}
+
+impl EntityDelta {
+    fn between(baseline: &EntityState, state: &EntityState) -> Self {
+        let changed = state
+            .components
+            .iter()
+            .filter(|(id, bytes)| baseline.components.get(id) != Some(bytes))
+            .map(|(id, bytes)| (*id, bytes.clone()))
+            .collect();
+
+        let removed = baseline
+            .components
+            .keys()
+            .filter(|id| !state.components.contains_key(id))
+            .copied()
+            .collect();
+
+        Self { changed, removed }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    fn apply(&self, state: &mut EntityState) {
+        for id in self.removed.iter() {
+            state.components.remove(id);
+        }
+
+        for (id, bytes) in self.changed.iter() {
+            state.components.insert(*id, bytes.clone());
+        }
+    }
+}
+
+/// State of every replicated entity at one server tick.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub entities: BTreeMap<NetworkId, EntityState>,
+}
+
+impl Snapshot {
+    pub fn new(tick: u32) -> Self {
+        Self {
+            tick,
+            entities: BTreeMap::new(),
+        }
+    }
+
+    /// Describes this snapshot relative to `baseline`, or in full without one.
+    pub fn delta_from(&self, baseline: Option<&Snapshot>) -> SnapshotDelta {
+        let empty = BTreeMap::new();
+        let baseline_entities = baseline.map_or(&empty, |baseline| &baseline.entities);
+
+        let changed = self
+            .entities
+            .iter()
+            .filter_map(|(network_id, state)| {
+                let baseline_state = baseline_entities.get(network_id);
+                let delta =
+                    EntityDelta::between(baseline_state.unwrap_or(&EntityState::default()), state);
+
+                // New entities are always listed so the client knows to spawn them
+                if baseline_state.is_some() && delta.is_empty() {
+                    None
+                } else {
+                    Some((*network_id, delta))
+                }
+            })
+            .collect();
+
+        let removed = baseline_entities
+            .keys()
+            .filter(|network_id| !self.entities.contains_key(network_id))
+            .copied()
+            .collect();
+
+        SnapshotDelta {
+            tick: self.tick,
+            baseline_tick: baseline.map(|baseline| baseline.tick),
+            changed,
+            removed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDelta {
+    pub tick: u32,
+    /// Tick of the snapshot this delta was made against, `None` for a full snapshot.
+    pub baseline_tick: Option<u32>,
+    pub changed: Vec<(NetworkId, EntityDelta)>,
+    pub removed: Vec<NetworkId>,
+}
+
+impl SnapshotDelta {
+    /// Rebuilds the full snapshot, `baseline` has to be the one at `baseline_tick`.
+    pub fn apply(&self, baseline: Option<&Snapshot>) -> Snapshot {
+        let mut snapshot = baseline.cloned().unwrap_or_default();
+        snapshot.tick = self.tick;
+
+        for network_id in self.removed.iter() {
+            snapshot.entities.remove(network_id);
+        }
+
+        for (network_id, delta) in self.changed.iter() {
+            delta.apply(snapshot.entities.entry(*network_id).or_default());
+        }
+
+        snapshot
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClientSnapshots {
+    sent: VecDeque<Snapshot>,
+    acked_tick: Option<u32>,
+}
+
+/// Server side bookkeeping, hands out network ids and remembers what each client has acked.
+#[derive(Debug, Default)]
+pub struct ServerReplication {
+    next_network_id: u32,
+    clients: HashMap<ClientId, ClientSnapshots>,
+}
+
+impl ServerReplication {
+    pub fn allocate_network_id(&mut self) -> NetworkId {
+        let network_id = NetworkId(self.next_network_id);
+        self.next_network_id += 1;
+        network_id
+    }
+
+    pub fn add_client(&mut self, client_id: ClientId) {
+        self.clients.insert(client_id, ClientSnapshots::default());
+    }
+
+    pub fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+
+    pub fn acknowledge(&mut self, client_id: ClientId, tick: u32) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            // Acks travel unreliably, so an older one can arrive after a newer one
+            match client.acked_tick {
+                Some(acked_tick) if acked_tick >= tick => {}
+                _ => client.acked_tick = Some(tick),
+            }
+        }
+    }
+
+    /// Deltas `snapshot` against the last one the client acked and remembers it as sent.
+    pub fn delta_for(&mut self, client_id: ClientId, snapshot: &Snapshot) -> Option<SnapshotDelta> {
+        let client = self.clients.get_mut(&client_id)?;
+
+        let baseline = client
+            .acked_tick
+            .and_then(|acked_tick| client.sent.iter().find(|sent| sent.tick == acked_tick));
+        let delta = snapshot.delta_from(baseline);
+
+        client.sent.push_back(snapshot.clone());
+        if client.sent.len() > SNAPSHOT_HISTORY_LENGTH {
+            client.sent.pop_front();
+        }
+
+        Some(delta)
+    }
+}
+
+/// Client side bookkeeping, rebuilds snapshots from deltas and maps network ids to local entities.
+#[derive(Debug, Default)]
+pub struct ClientReplication {
+    /// Replicated entity controlled by this client, announced by the server's welcome message.
+    pub local_network_id: Option<NetworkId>,
+    entities: HashMap<NetworkId, Entity>,
+    received: VecDeque<Snapshot>,
+    pending: Option<Snapshot>,
+    applied: Option<Snapshot>,
+}
+
+impl ClientReplication {
+    pub fn latest_tick(&self) -> Option<u32> {
+        self.received.back().map(|snapshot| snapshot.tick)
+    }
+
+    /// Rebuilds the snapshot and returns its tick for acking. Stale deltas and ones whose
+    /// baseline we no longer have are dropped.
+    pub fn receive(&mut self, delta: &SnapshotDelta) -> Option<u32> {
+        if matches!(self.latest_tick(), Some(latest_tick) if latest_tick >= delta.tick) {
+            return None;
+        }
+
+        let baseline = match delta.baseline_tick {
+            Some(baseline_tick) => Some(
+                self.received
+                    .iter()
+                    .find(|snapshot| snapshot.tick == baseline_tick)?,
+            ),
+            None => None,
+        };
+        let snapshot = delta.apply(baseline);
+
+        self.received.push_back(snapshot.clone());
+        if self.received.len() > SNAPSHOT_HISTORY_LENGTH {
+            self.received.pop_front();
+        }
+        self.pending = Some(snapshot);
+
+        Some(delta.tick)
+    }
+
+    /// Newest snapshot not yet applied to the world, along with the one applied before it.
+    pub fn take_pending(&mut self) -> Option<(Snapshot, Option<Snapshot>)> {
+        let pending = self.pending.take()?;
+        let applied = self.applied.replace(pending.clone());
+        Some((pending, applied))
+    }
+
+    pub fn entity(&self, network_id: NetworkId) -> Option<Entity> {
+        self.entities.get(&network_id).copied()
+    }
+
+    pub fn map_entity(&mut self, network_id: NetworkId, entity: Entity) {
+        self.entities.insert(network_id, entity);
+    }
+
+    pub fn unmap_entity(&mut self, network_id: NetworkId) -> Option<Entity> {
+        self.entities.remove(&network_id)
+    }
+
+    pub fn mapped_network_ids(&self) -> impl Iterator<Item = NetworkId> + '_ {
+        self.entities.keys().copied()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use legion::{EntityStore, World};
+
+    use super::*;
+    use crate::net::message::ServerMessage;
+
+    pub(crate) fn player_state(position: Vec3, health: f32) -> EntityState {
+        let mut world = World::default();
+        let entity = world.push((
+            position,
+            Rotor3::identity(),
+            Health {
+                current: health,
+                max: 100.0,
+            },
+        ));
+
+        replication_registry().capture(&world.entry_ref(entity).unwrap())
+    }
+
+    pub(crate) fn snapshot(tick: u32, entities: &[(u32, EntityState)]) -> Snapshot {
+        let mut snapshot = Snapshot::new(tick);
+        for (network_id, state) in entities {
+            snapshot
+                .entities
+                .insert(NetworkId(*network_id), state.clone());
+        }
+        snapshot
+    }
+
+    #[test]
+    fn quantization_stays_within_precision() {
+        let position = Vec3::new(12.345, -0.001, 250.5);
+        let restored = QuantizedVec3::quantize(position).dequantize();
+        assert!((restored - position).mag() < 0.005);
+
+        let rotation = Rotor3::from_euler_angles(0.3, -1.2, 2.0);
+        let restored = QuantizedRotor::quantize(rotation).dequantize();
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        assert!((forward.rotated_by(restored) - forward.rotated_by(rotation)).mag() < 0.001);
+
+        let health = replication_registry()
+            .get::<Health>(&player_state(Vec3::zero(), 42.25))
+            .unwrap();
+        assert!((health.current - 42.25).abs() <= 0.05);
+        assert_eq!(health.max, 100.0);
+    }
+
+    #[test]
+    fn delta_only_carries_changes() {
+        let baseline = snapshot(
+            1,
+            &[
+                (0, player_state(Vec3::zero(), 100.0)),
+                (1, player_state(Vec3::one(), 100.0)),
+                (2, player_state(Vec3::one(), 100.0)),
+            ],
+        );
+        let current = snapshot(
+            2,
+            &[
+                (0, player_state(Vec3::zero(), 100.0)),
+                (1, player_state(Vec3::one(), 42.5)),
+                (3, player_state(Vec3::unit_x(), 100.0)),
+            ],
+        );
+
+        let delta = current.delta_from(Some(&baseline));
+
+        assert_eq!(delta.baseline_tick, Some(1));
+        assert_eq!(delta.removed, vec![NetworkId(2)]);
+        let changed = delta
+            .changed
+            .iter()
+            .map(|(network_id, _)| *network_id)
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec![NetworkId(1), NetworkId(3)]);
+        let changed_components = delta.changed[0]
+            .1
+            .changed
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changed_components,
+            vec![replication_registry().id::<Health>().unwrap()]
+        );
+
+        assert_eq!(delta.apply(Some(&baseline)), current);
+    }
+
+    #[test]
+    fn only_changed_components_are_applied() {
+        let registry = replication_registry();
+        let mut world = World::default();
+
+        let server = world.push((
+            Vec3::zero(),
+            Rotor3::identity(),
+            Health::new(100.0),
+            Dead::default(),
+        ));
+        let dead = registry.capture(&world.entry_ref(server).unwrap());
+        let alive = player_state(Vec3::zero(), 75.0);
+
+        let delta = EntityDelta::between(&dead, &alive);
+        assert_eq!(delta.removed, vec![registry.id::<Dead>().unwrap()]);
+
+        let client = world.push((
+            Vec3::one(),
+            Health::new(50.0),
+            Dead {
+                seconds_since_death: 2.0,
+            },
+        ));
+        let mut commands = CommandBuffer::new(&world);
+
+        // Nothing changed, so the client's own death timer keeps going
+        registry.apply(&mut commands, client, Some(&dead), &dead);
+        commands.flush(&mut world);
+        let entry = world.entry_ref(client).unwrap();
+        assert_eq!(
+            entry.get_component::<Dead>().unwrap().seconds_since_death,
+            2.0
+        );
+        assert_eq!(entry.get_component::<Health>().unwrap().current, 50.0);
+
+        registry.apply(&mut commands, client, Some(&dead), &alive);
+        commands.flush(&mut world);
+        let entry = world.entry_ref(client).unwrap();
+        assert!(entry.get_component::<Dead>().is_err());
+        assert_eq!(entry.get_component::<Health>().unwrap().current, 75.0);
+        // Transforms only change through prediction and interpolation
+        assert_eq!(*entry.get_component::<Position>().unwrap(), Vec3::one());
+    }
+
+    #[test]
+    fn server_deltas_against_acked_snapshot() {
+        let mut replication = ServerReplication::default();
+        replication.add_client(ClientId(0));
+
+        let first = snapshot(1, &[(0, player_state(Vec3::zero(), 100.0))]);
+        let second = snapshot(2, &[(0, player_state(Vec3::zero(), 90.0))]);
+        let third = snapshot(3, &[(0, player_state(Vec3::zero(), 80.0))]);
+
+        // Nothing acked yet, so the client has to get everything
+        assert_eq!(
+            replication
+                .delta_for(ClientId(0), &first)
+                .unwrap()
+                .baseline_tick,
+            None
+        );
+
+        replication.acknowledge(ClientId(0), 1);
+        assert_eq!(
+            replication
+                .delta_for(ClientId(0), &second)
+                .unwrap()
+                .baseline_tick,
+            Some(1)
+        );
+
+        // A late ack for an older snapshot doesn't move the baseline back
+        replication.acknowledge(ClientId(0), 2);
+        replication.acknowledge(ClientId(0), 1);
+        assert_eq!(
+            replication
+                .delta_for(ClientId(0), &third)
+                .unwrap()
+                .baseline_tick,
+            Some(2)
+        );
+
+        assert!(replication.delta_for(ClientId(1), &third).is_none());
+    }
+
+    #[test]
+    fn client_rebuilds_snapshots_and_drops_stale_ones() {
+        let first = snapshot(1, &[(0, player_state(Vec3::zero(), 100.0))]);
+        let second = snapshot(2, &[(0, player_state(Vec3::unit_y(), 100.0))]);
+        let third = snapshot(3, &[(0, player_state(Vec3::unit_z(), 100.0))]);
+
+        let mut replication = ClientReplication::default();
+        assert_eq!(replication.receive(&first.delta_from(None)), Some(1));
+        assert_eq!(replication.take_pending(), Some((first.clone(), None)));
+
+        assert_eq!(
+            replication.receive(&third.delta_from(Some(&first))),
+            Some(3)
+        );
+        assert_eq!(
+            replication.take_pending(),
+            Some((third.clone(), Some(first.clone())))
+        );
+
+        // Arrived out of order after the newer snapshot
+        assert_eq!(replication.receive(&second.delta_from(Some(&first))), None);
+        assert_eq!(replication.take_pending(), None);
+
+        // Baseline the client never received
+        let fourth = snapshot(4, &[]);
+        assert_eq!(replication.receive(&fourth.delta_from(Some(&second))), None);
+        assert_eq!(replication.latest_tick(), Some(3));
+    }
+
+    #[test]
+    fn snapshot_messages_round_trip() {
+        let current = snapshot(7, &[(3, player_state(Vec3::new(1.0, 2.0, 3.0), 55.5))]);
+        let message = ServerMessage::Snapshot {
+            delta: current.delta_from(None),
+            last_input_tick: Some(5),
+        };
+
+        assert_eq!(ServerMessage::decode(&message.encode()), Some(message));
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::client::DisconnectReason;
+use crate::net::connection::Connection;
+use crate::net::packet::{ChannelKind, ClientId, Packet, RejectReason};
+use crate::net::transport::Transport;
+use crate::net::NetConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    ClientConnected(ClientId),
+    ClientDisconnected(ClientId, DisconnectReason),
+    Message(ClientId, ChannelKind, Vec<u8>),
+}
+
+/// Authoritative end of the connection, accepts clients and owns one `Connection` per client.
+pub struct Server {
+    transport: Box<dyn Transport>,
+    config: NetConfig,
+    clients: HashMap<ClientId, Connection>,
+    client_ids: HashMap<SocketAddr, ClientId>,
+    next_client_id: u32,
+}
+
+impl Server {
+    pub fn new(transport: Box<dyn Transport>, config: NetConfig) -> Self {
+        Self {
+            transport,
+            config,
+            clients: HashMap::new(),
+            client_ids: HashMap::new(),
+            next_client_id: 0,
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+
+    pub fn client_ids(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.keys().copied()
+    }
+
+    pub fn send(&mut self, client_id: ClientId, channel: ChannelKind, payload: Vec<u8>) {
+        if let Some(connection) = self.clients.get_mut(&client_id) {
+            connection.send(channel, payload);
+        }
+    }
+
+    pub fn broadcast(&mut self, channel: ChannelKind, payload: Vec<u8>) {
+        for connection in self.clients.values_mut() {
+            connection.send(channel, payload.clone());
+        }
+    }
+
+    pub fn disconnect(&mut self, client_id: ClientId) {
+        if let Some(connection) = self.clients.remove(&client_id) {
+            self.client_ids.remove(&connection.remote);
+            self.send_packet(connection.remote, &Packet::Disconnect);
+        }
+    }
+
+    pub fn update(&mut self, now: Instant) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+
+        loop {
+            let (from, bytes) = match self.transport.recv() {
+                Ok(Some(datagram)) => datagram,
+                Ok(None) => break,
+                Err(error) => {
+                    log::warn!("server receive failed: {}", error);
+                    break;
+                }
+            };
+
+            if let Some(packet) = Packet::decode(&bytes) {
+                self.process_packet(from, packet, now, &mut events);
+            }
+        }
+
+        let timed_out = self
+            .clients
+            .iter()
+            .filter(|(_, connection)| connection.is_timed_out(now, &self.config))
+            .map(|(client_id, _)| *client_id)
+            .collect::<Vec<_>>();
+        for client_id in timed_out {
+            if let Some(connection) = self.clients.remove(&client_id) {
+                self.client_ids.remove(&connection.remote);
+            }
+            events.push(ServerEvent::ClientDisconnected(
+                client_id,
+                DisconnectReason::TimedOut,
+            ));
+        }
+
+        for (client_id, connection) in self.clients.iter_mut() {
+            while let Some((channel, payload)) = connection.receive() {
+                events.push(ServerEvent::Message(*client_id, channel, payload));
+            }
+        }
+
+        let mut outgoing = Vec::new();
+        for connection in self.clients.values_mut() {
+            while let Some(packet) = connection.poll_packet(now, &self.config) {
+                outgoing.push((connection.remote, packet));
+            }
+        }
+        for (address, packet) in outgoing {
+            self.send_packet(address, &packet);
+        }
+
+        events
+    }
+
+    fn process_packet(
+        &mut self,
+        from: SocketAddr,
+        packet: Packet,
+        now: Instant,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let client_id = self.client_ids.get(&from).copied();
+
+        match (packet, client_id) {
+            // Requests keep coming until the client sees our answer, so accepting is idempotent
+            (Packet::ConnectRequest, Some(client_id)) => {
+                self.send_packet(from, &Packet::ConnectAccepted { client_id });
+            }
+            (Packet::ConnectRequest, None) => {
+                if self.clients.len() >= self.config.max_clients {
+                    self.send_packet(
+                        from,
+                        &Packet::ConnectRejected {
+                            reason: RejectReason::ServerFull,
+                        },
+                    );
+                    return;
+                }
+
+                let client_id = ClientId(self.next_client_id);
+                self.next_client_id += 1;
+
+                self.clients.insert(client_id, Connection::new(from, now));
+                self.client_ids.insert(from, client_id);
+                self.send_packet(from, &Packet::ConnectAccepted { client_id });
+
+                events.push(ServerEvent::ClientConnected(client_id));
+            }
+            (Packet::Disconnect, Some(client_id)) => {
+                self.clients.remove(&client_id);
+                self.client_ids.remove(&from);
+                events.push(ServerEvent::ClientDisconnected(
+                    client_id,
+                    DisconnectReason::Closed,
+                ));
+            }
+            (
+                Packet::Data {
+                    reliable_ack,
+                    reliable,
+                    unreliable,
+                },
+                Some(client_id),
+            ) => {
+                if let Some(connection) = self.clients.get_mut(&client_id) {
+                    connection.process_data(now, reliable_ack, reliable, unreliable);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn send_packet(&mut self, to: SocketAddr, packet: &Packet) {
+        if let Err(error) = self.transport.send(to, &packet.encode()) {
+            log::warn!("server send failed: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::net::client::{Client, ClientEvent, ClientState};
+    use crate::net::loopback::LoopbackNetwork;
+    use crate::net::message::ServerMessage;
+    use crate::net::replication::tests::{player_state, snapshot};
+    use std::time::Duration;
+    use ultraviolet::Vec3;
+
+    pub(crate) fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    pub(crate) fn server_and_client(
+        network: &LoopbackNetwork,
+        config: NetConfig,
+        now: Instant,
+    ) -> (Server, Client) {
+        let server = Server::new(Box::new(network.bind(address(4000))), config.clone());
+        let client = Client::connect(
+            Box::new(network.bind(address(5000))),
+            address(4000),
+            config,
+            now,
+        );
+
+        (server, client)
+    }
+
+    /// Runs both ends a few times so packets and their replies go back and forth.
+    pub(crate) fn pump(
+        server: &mut Server,
+        client: &mut Client,
+        now: Instant,
+    ) -> (Vec<ServerEvent>, Vec<ClientEvent>) {
+        let mut server_events = Vec::new();
+        let mut client_events = Vec::new();
+
+        for _ in 0..4 {
+            client_events.extend(client.update(now));
+            server_events.extend(server.update(now));
+        }
+
+        (server_events, client_events)
+    }
+
+    #[test]
+    fn handshake_connects_client() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let (mut server, mut client) = server_and_client(&network, NetConfig::default(), now);
+
+        let (server_events, client_events) = pump(&mut server, &mut client, now);
+
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::ClientConnected(ClientId(0))]
+        );
+        assert_eq!(client_events, vec![ClientEvent::Connected(ClientId(0))]);
+        assert_eq!(
+            client.state(),
+            ClientState::Connected {
+                client_id: ClientId(0)
+            }
+        );
+    }
+
+    #[test]
+    fn messages_flow_both_ways() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let (mut server, mut client) = server_and_client(&network, NetConfig::default(), now);
+
+        // Queued before the handshake completes
+        client.send(ChannelKind::Reliable, b"first".to_vec());
+        client.send(ChannelKind::Reliable, b"second".to_vec());
+        let (mut server_events, _) = pump(&mut server, &mut client, now);
+
+        let now = now + Duration::from_millis(16);
+        client.send(ChannelKind::Unreliable, b"input".to_vec());
+        server.send(ClientId(0), ChannelKind::Reliable, b"welcome".to_vec());
+        let (more_server_events, client_events) = pump(&mut server, &mut client, now);
+        server_events.extend(more_server_events);
+
+        let server_messages = server_events
+            .into_iter()
+            .filter_map(|event| match event {
+                ServerEvent::Message(ClientId(0), channel, payload) => Some((channel, payload)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            server_messages,
+            vec![
+                (ChannelKind::Reliable, b"first".to_vec()),
+                (ChannelKind::Reliable, b"second".to_vec()),
+                (ChannelKind::Unreliable, b"input".to_vec()),
+            ]
+        );
+        assert!(client_events.contains(&ClientEvent::Message(
+            ChannelKind::Reliable,
+            b"welcome".to_vec()
+        )));
+    }
+
+    #[test]
+    fn full_server_rejects_client() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let config = NetConfig {
+            max_clients: 0,
+            ..NetConfig::default()
+        };
+        let (mut server, mut client) = server_and_client(&network, config, now);
+
+        let (server_events, client_events) = pump(&mut server, &mut client, now);
+
+        assert!(server_events.is_empty());
+        assert_eq!(
+            client_events,
+            vec![ClientEvent::Disconnected(DisconnectReason::Rejected(
+                RejectReason::ServerFull
+            ))]
+        );
+    }
+
+    #[test]
+    fn silent_peers_time_out() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let config = NetConfig::default();
+        let (mut server, mut client) = server_and_client(&network, config.clone(), now);
+        pump(&mut server, &mut client, now);
+
+        let later = now + config.timeout;
+
+        assert_eq!(
+            server.update(later),
+            vec![ServerEvent::ClientDisconnected(
+                ClientId(0),
+                DisconnectReason::TimedOut
+            )]
+        );
+        assert_eq!(
+            client.update(later),
+            vec![ClientEvent::Disconnected(DisconnectReason::TimedOut)]
+        );
+    }
+
+    #[test]
+    fn keep_alives_hold_connection_open() {
+        let network = LoopbackNetwork::default();
+        let mut now = Instant::now();
+        let config = NetConfig::default();
+        let (mut server, mut client) = server_and_client(&network, config.clone(), now);
+        pump(&mut server, &mut client, now);
+
+        let end = now + config.timeout * 2;
+        while now < end {
+            now += config.keep_alive_interval;
+            let (server_events, client_events) = pump(&mut server, &mut client, now);
+            assert!(server_events.is_empty());
+            assert!(client_events.is_empty());
+        }
+
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn unconnected_client_gives_up() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let config = NetConfig::default();
+        let mut client = Client::connect(
+            Box::new(network.bind(address(5000))),
+            address(4000),
+            config.clone(),
+            now,
+        );
+
+        assert!(client.update(now).is_empty());
+        assert_eq!(
+            client.update(now + config.timeout),
+            vec![ClientEvent::Disconnected(DisconnectReason::TimedOut)]
+        );
+    }
+
+    #[test]
+    fn foreign_datagrams_are_ignored() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let (mut server, mut client) = server_and_client(&network, NetConfig::default(), now);
+
+        let mut stranger = network.bind(address(6000));
+        stranger.send(address(4000), b"not a game packet").unwrap();
+
+        let (server_events, _) = pump(&mut server, &mut client, now);
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::ClientConnected(ClientId(0))]
+        );
+    }
+
+    #[test]
+    fn snapshots_larger_than_the_mtu_arrive_whole() {
+        let network = LoopbackNetwork::default();
+        let now = Instant::now();
+        let (mut server, mut client) = server_and_client(&network, NetConfig::default(), now);
+        pump(&mut server, &mut client, now);
+
+        let entities = (0..200)
+            .map(|i| (i, player_state(Vec3::new(i as f32, 0.0, 0.0), 100.0)))
+            .collect::<Vec<_>>();
+        let message = ServerMessage::Snapshot {
+            delta: snapshot(1, &entities).delta_from(None),
+            last_input_tick: None,
+        };
+        let payload = message.encode();
+        assert!(payload.len() > 1500);
+
+        let now = now + Duration::from_millis(16);
+        server.send(ClientId(0), ChannelKind::Unreliable, b"before".to_vec());
+        server.send(ClientId(0), ChannelKind::Unreliable, payload);
+        server.send(ClientId(0), ChannelKind::Unreliable, b"after".to_vec());
+        let (_, client_events) = pump(&mut server, &mut client, now);
+
+        let received = client_events
+            .into_iter()
+            .filter_map(|event| match event {
+                ClientEvent::Message(ChannelKind::Unreliable, payload) => Some(payload),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], b"before".to_vec());
+        assert_eq!(ServerMessage::decode(&received[1]), Some(message));
+        assert_eq!(received[2], b"after".to_vec());
+    }
+}
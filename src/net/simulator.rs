@@ -0,0 +1,279 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::net::transport::Transport;
+
+/// Reordered datagrams are held back at least this long, so they fall behind even without
+/// any configured latency.
+const MIN_REORDER_DELAY: Duration = Duration::from_millis(10);
+
+/// Network trouble to inject into outgoing datagrams. Wrap both ends to affect both directions.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConditions {
+    /// One way delay added to every datagram.
+    pub latency: Duration,
+    /// Each datagram's delay varies randomly by up to this much either way.
+    pub jitter: Duration,
+    /// Chances between 0 and 1 of a datagram being dropped, sent twice or held back long
+    /// enough for later ones to overtake it.
+    pub loss_chance: f32,
+    pub duplicate_chance: f32,
+    pub reorder_chance: f32,
+}
+
+/// Time source of a `SimulatedTransport` that tests step themselves, shared by every
+/// transport that is handed a clone.
+#[derive(Clone)]
+pub struct SimulationClock(Arc<Mutex<Instant>>);
+
+impl SimulationClock {
+    pub fn new(start: Instant) -> Self {
+        SimulationClock(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn now(&self) -> Instant {
+        *self.0.lock().expect("simulation clock lock poisoned?")
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("simulation clock lock poisoned?") += duration;
+    }
+}
+
+struct DelayedDatagram {
+    deliver_at: Instant,
+    to: SocketAddr,
+    payload: Vec<u8>,
+}
+
+/// Transport wrapper that degrades outgoing traffic according to `NetworkConditions`.
+/// Randomness comes from a seeded RNG so a failing test replays the same way every run.
+pub struct SimulatedTransport {
+    inner: Box<dyn Transport>,
+    conditions: NetworkConditions,
+    clock: SimulationClock,
+    rng: StdRng,
+    in_flight: Vec<DelayedDatagram>,
+}
+
+impl SimulatedTransport {
+    pub fn new(
+        inner: Box<dyn Transport>,
+        conditions: NetworkConditions,
+        clock: SimulationClock,
+        seed: u64,
+    ) -> Self {
+        Self {
+            inner,
+            conditions,
+            clock,
+            rng: StdRng::seed_from_u64(seed),
+            in_flight: Vec::new(),
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_secs_f64();
+        let offset = if jitter > 0.0 {
+            self.rng.gen_range(-jitter, jitter)
+        } else {
+            0.0
+        };
+        let mut delay = (self.conditions.latency.as_secs_f64() + offset).max(0.0);
+
+        if self.rng.gen::<f32>() < self.conditions.reorder_chance {
+            delay += self.conditions.latency.max(MIN_REORDER_DELAY).as_secs_f64();
+        }
+
+        Duration::from_secs_f64(delay)
+    }
+
+    /// Hands every datagram whose delay has passed to the wrapped transport.
+    fn flush(&mut self) -> io::Result<()> {
+        let now = self.clock.now();
+
+        // Stable sort keeps datagrams due at the same time in the order they were sent
+        self.in_flight.sort_by_key(|datagram| datagram.deliver_at);
+        let due = self
+            .in_flight
+            .iter()
+            .take_while(|datagram| datagram.deliver_at <= now)
+            .count();
+
+        for datagram in self.in_flight.drain(..due) {
+            self.inner.send(datagram.to, &datagram.payload)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for SimulatedTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+
+    fn send(&mut self, to: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        let copies = if self.rng.gen::<f32>() < self.conditions.loss_chance {
+            0
+        } else if self.rng.gen::<f32>() < self.conditions.duplicate_chance {
+            2
+        } else {
+            1
+        };
+
+        let now = self.clock.now();
+        for _ in 0..copies {
+            let deliver_at = now + self.delay();
+            self.in_flight.push(DelayedDatagram {
+                deliver_at,
+                to,
+                payload: payload.to_vec(),
+            });
+        }
+
+        self.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        // Whoever owns us polls for incoming datagrams regularly, so delayed outgoing ones
+        // get sent from here too
+        self.flush()?;
+
+        self.inner.recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::client::Client;
+    use crate::net::loopback::LoopbackNetwork;
+    use crate::net::packet::ChannelKind;
+    use crate::net::server::tests::address;
+    use crate::net::server::{Server, ServerEvent};
+    use crate::net::NetConfig;
+
+    /// Client and server on one loopback network, each sending through its own simulator.
+    fn simulated_server_and_client(
+        conditions: NetworkConditions,
+        clock: &SimulationClock,
+        seed: u64,
+    ) -> (Server, Client) {
+        let network = LoopbackNetwork::default();
+        let server_transport = SimulatedTransport::new(
+            Box::new(network.bind(address(4000))),
+            conditions.clone(),
+            clock.clone(),
+            seed,
+        );
+        let client_transport = SimulatedTransport::new(
+            Box::new(network.bind(address(5000))),
+            conditions,
+            clock.clone(),
+            seed + 1,
+        );
+
+        let server = Server::new(Box::new(server_transport), NetConfig::default());
+        let client = Client::connect(
+            Box::new(client_transport),
+            address(4000),
+            NetConfig::default(),
+            clock.now(),
+        );
+
+        (server, client)
+    }
+
+    /// Steps both ends at 60 updates a second, returning server events with the step they arrived on.
+    fn run_simulation(
+        server: &mut Server,
+        client: &mut Client,
+        clock: &SimulationClock,
+        steps: u32,
+    ) -> Vec<(u32, ServerEvent)> {
+        let mut server_events = Vec::new();
+
+        for step in 0..steps {
+            clock.advance(Duration::from_millis(16));
+            client.update(clock.now());
+            server_events.extend(
+                server
+                    .update(clock.now())
+                    .into_iter()
+                    .map(|event| (step, event)),
+            );
+        }
+
+        server_events
+    }
+
+    fn bad_network() -> NetworkConditions {
+        NetworkConditions {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(20),
+            loss_chance: 0.2,
+            duplicate_chance: 0.2,
+            reorder_chance: 0.2,
+        }
+    }
+
+    #[test]
+    fn simulated_latency_delays_delivery() {
+        let clock = SimulationClock::new(Instant::now());
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(50),
+            ..NetworkConditions::default()
+        };
+        let (mut server, mut client) = simulated_server_and_client(conditions, &clock, 1);
+
+        // A round trip takes 100 ms, so five 16 ms steps aren't enough
+        run_simulation(&mut server, &mut client, &clock, 5);
+        assert!(!client.is_connected());
+
+        run_simulation(&mut server, &mut client, &clock, 10);
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn reliable_messages_survive_bad_network() {
+        let clock = SimulationClock::new(Instant::now());
+        let (mut server, mut client) = simulated_server_and_client(bad_network(), &clock, 7);
+
+        let sent = (0..20u8).map(|index| vec![index]).collect::<Vec<_>>();
+        for payload in &sent {
+            client.send(ChannelKind::Reliable, payload.clone());
+        }
+
+        let received = run_simulation(&mut server, &mut client, &clock, 300)
+            .into_iter()
+            .filter_map(|(_, event)| match event {
+                ServerEvent::Message(_, ChannelKind::Reliable, payload) => Some(payload),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert!(client.is_connected());
+        assert_eq!(received, sent);
+    }
+
+    #[test]
+    fn same_seed_replays_same_network() {
+        let run = |seed| {
+            let clock = SimulationClock::new(Instant::now());
+            let (mut server, mut client) = simulated_server_and_client(bad_network(), &clock, seed);
+            for index in 0..10u8 {
+                client.send(ChannelKind::Unreliable, vec![index]);
+            }
+
+            run_simulation(&mut server, &mut client, &clock, 60)
+        };
+
+        assert_eq!(run(3), run(3));
+    }
+}
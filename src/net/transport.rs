@@ -0,0 +1,12 @@
+use std::io;
+use std::net::SocketAddr;
+
+/// Datagram transport the client and server send their packets over.
+pub trait Transport: Send + Sync {
+    fn local_addr(&self) -> SocketAddr;
+
+    fn send(&mut self, to: SocketAddr, payload: &[u8]) -> io::Result<()>;
+
+    /// Non-blocking, returns `None` when no datagram is waiting.
+    fn recv(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>>;
+}
@@ -0,0 +1,73 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::net::transport::Transport;
+
+// The largest UDP payload. Packets are kept below the MTU, but a single unreliable payload can
+// be bigger, IP fragments it and it has to be received whole.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    receive_buffer: Vec<u8>,
+}
+
+impl UdpTransport {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        let local_addr = socket.local_addr()?;
+
+        Ok(Self {
+            socket,
+            local_addr,
+            receive_buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn send(&mut self, to: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        self.socket.send_to(payload, to).map(|_| ())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        match self.socket.recv_from(&mut self.receive_buffer) {
+            Ok((length, from)) => Ok(Some((from, self.receive_buffer[..length].to_vec()))),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn udp_receives_datagrams_larger_than_the_mtu() {
+        let mut sender = UdpTransport::bind("127.0.0.1:0").expect("failed binding sender?");
+        let mut receiver = UdpTransport::bind("127.0.0.1:0").expect("failed binding receiver?");
+        let payload = (0..4000).map(|i| i as u8).collect::<Vec<_>>();
+
+        sender
+            .send(receiver.local_addr(), &payload)
+            .expect("failed sending datagram?");
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let received = loop {
+            if let Some((_, received)) = receiver.recv().expect("failed receiving datagram?") {
+                break received;
+            }
+            assert!(Instant::now() < deadline, "datagram never arrived");
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(received, payload);
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use legion::serialize::Canon;
+use legion::world::Allocate;
+use legion::{Entity, World};
+use serde::de::DeserializeSeed;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::code::components::{position::Position, rotation::Rotation};
+use crate::serialization::component_registry_with_canon;
+
+/// Prefabs basing themselves on each other deeper than this are assumed to be circular.
+const MAX_BASE_DEPTH: usize = 16;
+
+/// Template for an entity and its children, written as JSON so designers can add pickups,
+/// enemies and props without touching code.
+///
+/// ```json
+/// {
+///     "base": "pickup.json",
+///     "model": "../models/medkit.gltf",
+///     "components": { "health": { "current": 50.0, "max": 50.0 } },
+///     "children": [{ "components": { "position": { "x": 0.0, "y": 0.5, "z": 0.0 } } }]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prefab {
+    /// Prefab file this one starts from, relative to this file. Its components and children
+    /// are kept and ours are applied on top.
+    pub base: Option<PathBuf>,
+    /// glTF file the entity is drawn with, relative to this file. Spawned as a `Model`.
+    pub model: Option<PathBuf>,
+    /// Components by their key in `component_registry`. A component the base also has is
+    /// overridden field by field, so only the changed fields need to be written.
+    pub components: Map<String, Value>,
+    /// Spawned as their own entities with a `Parent`, their `Position` and `Rotation` are
+    /// relative to the parent.
+    pub children: Vec<Prefab>,
+}
+
+impl Prefab {
+    /// Reads a prefab file and resolves its bases and paths, including those of its children.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_nested(path.as_ref(), 0)
+    }
+
+    fn load_nested(path: &Path, depth: usize) -> Result<Self> {
+        if depth > MAX_BASE_DEPTH {
+            bail!(
+                "Prefab bases nest too deep at {}, are they circular?",
+                path.display()
+            );
+        }
+
+        let contents =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let prefab: Prefab = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse prefab {}", path.display()))?;
+
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        prefab.resolve(directory, depth)
+    }
+
+    /// Makes paths relative to `directory` and folds the base into this prefab.
+    fn resolve(self, directory: &Path, depth: usize) -> Result<Self> {
+        let children = self
+            .children
+            .into_iter()
+            .map(|child| child.resolve(directory, depth))
+            .collect::<Result<Vec<_>>>()?;
+
+        let own = Prefab {
+            base: None,
+            model: self.model.map(|model| directory.join(model)),
+            components: self.components,
+            children,
+        };
+
+        match self.base {
+            Some(base) => {
+                let base = Self::load_nested(&directory.join(base), depth + 1)?;
+                Ok(base.overridden_by(own))
+            }
+            None => Ok(own),
+        }
+    }
+
+    fn overridden_by(mut self, overrides: Prefab) -> Prefab {
+        for (key, value) in overrides.components {
+            match self.components.get_mut(&key) {
+                Some(base_value) => merge_json(base_value, value),
+                None => {
+                    self.components.insert(key, value);
+                }
+            }
+        }
+
+        self.model = overrides.model.or(self.model);
+        self.children.extend(overrides.children);
+        self
+    }
+}
+
+/// Objects are merged key by key, anything else is replaced.
+fn merge_json(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_json(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+struct PrefabEntity {
+    name: String,
+    entity: Entity,
+    components: Map<String, Value>,
+}
+
+/// Spawns `prefab` and its children with the root at `position` and `rotation`, returns the root.
+pub fn spawn_prefab(
+    world: &mut World,
+    prefab: &Prefab,
+    position: Position,
+    rotation: Rotation,
+) -> Result<Entity> {
+    let mut allocate = Allocate::new();
+    let mut entities = Vec::new();
+    flatten(prefab, None, &mut allocate, &mut entities)?;
+
+    let root_components = &mut entities[0].components;
+    root_components.insert("position".to_string(), serde_json::to_value(position)?);
+    root_components.insert("rotation".to_string(), serde_json::to_value(rotation)?);
+
+    // Binding our names to entities up front tells us which entity each one loads as
+    let mut canon = Canon::default();
+    for prefab_entity in &entities {
+        let name = Uuid::parse_str(&prefab_entity.name).expect("generated invalid entity name?");
+        canon.canonize(prefab_entity.entity, *name.as_bytes())?;
+    }
+
+    let root = entities[0].entity;
+    let document = json!({
+        "entities": entities
+            .into_iter()
+            .map(|prefab_entity| (prefab_entity.name, Value::Object(prefab_entity.components)))
+            .collect::<Map<_, _>>(),
+    });
+
+    let registry = component_registry_with_canon(canon);
+    registry
+        .as_deserialize_into_world(world)
+        .deserialize(document)
+        .context("Failed to spawn prefab")?;
+
+    Ok(root)
+}
+
+/// Lists the entities of the prefab tree, parents before their children. Transforms are left
+/// as written, those of children stay relative to their parent.
+fn flatten(
+    prefab: &Prefab,
+    parent: Option<&str>,
+    allocate: &mut Allocate,
+    entities: &mut Vec<PrefabEntity>,
+) -> Result<()> {
+    let name = Uuid::from_u128(entities.len() as u128 + 1)
+        .to_hyphenated()
+        .to_string();
+    let mut components = prefab.components.clone();
+
+    if let Some(model) = &prefab.model {
+        components.insert("model".to_string(), json!({ "path": model }));
+    }
+    if let Some(parent) = parent {
+        components.insert("parent".to_string(), json!({ "entity": parent }));
+        // Only entities with a position are drawn, children without one sit on their parent
+        if !components.contains_key("position") {
+            components.insert(
+                "position".to_string(),
+                serde_json::to_value(Position::zero())?,
+            );
+        }
+    }
+
+    entities.push(PrefabEntity {
+        name: name.clone(),
+        entity: allocate.next().expect("entity allocator ran out?"),
+        components,
+    });
+
+    for child in &prefab.children {
+        flatten(child, Some(&name), allocate, entities)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::code::components::{health::Health, model::Model, parent::Parent, weapon::Weapon};
+    use crate::code::systems::render::extract_render_scene_system;
+    use crate::renderer::scene::RenderScene;
+    use legion::{EntityStore, IntoQuery, Resources, Schedule};
+    use ultraviolet::Vec3;
+
+    fn prefab(json: Value) -> Prefab {
+        serde_json::from_value::<Prefab>(json)
+            .unwrap()
+            .resolve(Path::new("prefabs"), 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn overrides_merge_field_by_field() {
+        let mut base = prefab(json!({
+            "model": "crate.gltf",
+            "components": { "health": { "current": 100.0, "max": 100.0 } },
+        }));
+        let overrides = prefab(json!({
+            "components": { "health": { "current": 25.0 } },
+            "children": [{}],
+        }));
+        base = base.overridden_by(overrides);
+
+        assert_eq!(
+            base.components["health"],
+            json!({ "current": 25.0, "max": 100.0 })
+        );
+        assert_eq!(base.model, Some(PathBuf::from("prefabs/crate.gltf")));
+        assert_eq!(base.children.len(), 1);
+    }
+
+    #[test]
+    fn spawns_children_relative_to_parent() {
+        let prefab = prefab(json!({
+            "model": "crate.gltf",
+            "components": { "health": { "current": 50.0, "max": 50.0 } },
+            "children": [{
+                "model": "lamp.gltf",
+                "components": { "position": { "x": 1.0, "y": 0.0, "z": 0.0 } },
+            }],
+        }));
+
+        let mut world = World::default();
+        let root = spawn_prefab(
+            &mut world,
+            &prefab,
+            Vec3::new(0.0, 0.0, 5.0),
+            Rotation::from_rotation_xz(std::f32::consts::FRAC_PI_2),
+        )
+        .unwrap();
+
+        let entry = world.entry_ref(root).unwrap();
+        assert_eq!(entry.get_component::<Health>().unwrap().current, 50.0);
+        assert_eq!(
+            entry.get_component::<Model>().unwrap().path,
+            PathBuf::from("prefabs/crate.gltf")
+        );
+
+        let (child_position, parent) = <(&Position, &Parent)>::query()
+            .iter(&world)
+            .next()
+            .expect("Child wasn't spawned?");
+        assert_eq!(parent.entity, root);
+        assert_eq!(*child_position, Vec3::new(1.0, 0.0, 0.0));
+
+        let mut resources = Resources::default();
+        resources.insert(RenderScene::default());
+        let mut schedule = Schedule::builder()
+            .add_system(extract_render_scene_system())
+            .build();
+        schedule.execute(&mut world, &mut resources);
+
+        let render_scene = resources.get::<RenderScene>().unwrap();
+        let lamp = render_scene
+            .meshes
+            .iter()
+            .find(|mesh| mesh.model == Path::new("prefabs/lamp.gltf"))
+            .expect("Child wasn't drawn?");
+        // A quarter turn in the xz plane turns the x offset into a z offset
+        let translation = lamp.transform.cols[3].truncated();
+        assert!((translation - Vec3::new(0.0, 0.0, 6.0)).mag() < 0.0001);
+    }
+
+    #[test]
+    fn player_prefab_spawns() {
+        let prefab = Prefab::load("./src/assets/prefabs/player.json").unwrap();
+        let mut world = World::default();
+        let player =
+            spawn_prefab(&mut world, &prefab, Position::zero(), Rotation::identity()).unwrap();
+
+        let entry = world.entry_ref(player).unwrap();
+        assert_eq!(entry.get_component::<Health>().unwrap().max, 100.0);
+        assert!(entry.get_component::<Camera>().is_ok());
+
+        // The prefab only configures the weapon, it starts out loaded
+        let weapon = entry.get_component::<Weapon>().unwrap();
+        assert_eq!(weapon.rounds_in_magazine, weapon.magazine_size);
+        assert!(!weapon.is_reloading());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use ultraviolet::*;
+
+use super::scene::RenderScene;
+use crate::collision::{Aabb, Sphere};
+
+/// Points `p` with `normal.dot(p) + distance >= 0` are on the inside.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Normalizes `coefficients`, so `signed_distance` is in world units.
+    fn new(coefficients: Vec4) -> Self {
+        let length = coefficients.xyz().mag();
+        Self {
+            normal: coefficients.xyz() / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// The volume a camera sees, bounded by six planes facing inwards.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of a view projection with the 0..1 depth range of
+    /// `perspective_wgpu_dx`. A point is inside when its clip space position has
+    /// -w <= x <= w, -w <= y <= w and 0 <= z <= w, each of which is a plane.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let columns = view_projection.cols;
+        let row = |index: usize| {
+            Vec4::new(
+                columns[0][index],
+                columns[1][index],
+                columns[2][index],
+                columns[3][index],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [
+                Plane::new(w + x),
+                Plane::new(w - x),
+                Plane::new(w + y),
+                Plane::new(w - y),
+                Plane::new(z),
+                Plane::new(w - z),
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Conservative, boxes outside of the frustum near its corners still count as inside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal is the last to leave through the plane
+            let furthest = Vec3::new(
+                if plane.normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.signed_distance(furthest) >= 0.0
+        })
+    }
+}
+
+/// Model space bounds of the models meshes are drawn with, by path.
+#[derive(Debug, Default)]
+pub struct MeshBounds {
+    bounds: HashMap<PathBuf, Aabb>,
+}
+
+impl MeshBounds {
+    pub fn insert(&mut self, model: PathBuf, bounds: Aabb) {
+        self.bounds.insert(model, bounds);
+    }
+
+    pub fn get(&self, model: &Path) -> Option<&Aabb> {
+        self.bounds.get(model)
+    }
+}
+
+/// How many of the meshes of a frame the culling pass skipped.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct CullStats {
+    pub tested: usize,
+    pub culled: usize,
+}
+
+/// Marks the meshes of `scene` outside the camera's view as invisible. Meshes whose model has no
+/// bounds yet are kept, and invisible ones still cast shadows into the view.
+pub fn cull(scene: &mut RenderScene, mesh_bounds: &MeshBounds) -> CullStats {
+    let frustum = match scene.camera() {
+        Some(camera) => Frustum::from_view_projection(camera.view_projection()),
+        None => return CullStats::default(),
+    };
+
+    let mut stats = CullStats::default();
+    for mesh in &mut scene.meshes {
+        let bounds = match mesh_bounds.get(&mesh.model) {
+            Some(bounds) => bounds.transformed(mesh.transform),
+            None => continue,
+        };
+
+        stats.tested += 1;
+        // The sphere test is cheaper and rejects most of what is far off to the side
+        mesh.visible = frustum.intersects_sphere(&bounds.bounding_sphere())
+            && frustum.intersects_aabb(&bounds);
+        if !mesh.visible {
+            stats.culled += 1;
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::scene::RenderMesh;
+    use ultraviolet::projection::rh_yup::perspective_wgpu_dx;
+
+    /// At the origin looking down -z, seeing 45 degrees to either side.
+    fn frustum() -> Frustum {
+        Frustum::from_view_projection(perspective_wgpu_dx(
+            std::f32::consts::FRAC_PI_2,
+            1.0,
+            0.1,
+            100.0,
+        ))
+    }
+
+    fn unit_box(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::one(),
+            max: center + Vec3::one(),
+        }
+    }
+
+    #[test]
+    fn planes_face_inwards() {
+        let frustum = frustum();
+        let inside = Vec3::new(0.0, 0.0, -10.0);
+
+        for plane in frustum.planes.iter() {
+            assert!(plane.signed_distance(inside) > 0.0);
+            assert!((plane.normal.mag() - 1.0).abs() < 1e-5);
+        }
+        // The near and far planes are at their distances
+        assert!(
+            frustum.planes[4]
+                .signed_distance(Vec3::new(0.0, 0.0, -0.1))
+                .abs()
+                < 1e-4
+        );
+        assert!(
+            frustum.planes[5]
+                .signed_distance(Vec3::new(0.0, 0.0, -100.0))
+                .abs()
+                < 1e-3
+        );
+        // The side planes are at 45 degrees
+        assert!(
+            frustum.planes[0]
+                .signed_distance(Vec3::new(-10.0, 0.0, -10.0))
+                .abs()
+                < 1e-4
+        );
+    }
+
+    #[test]
+    fn spheres_are_culled_outside_any_plane() {
+        let frustum = frustum();
+        let sphere = |x: f32, y: f32, z: f32| Sphere::new(Vec3::new(x, y, z), 1.0);
+
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -10.0)));
+        // Behind the camera, past the far plane, and off to the sides
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 5.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -102.0)));
+        assert!(!frustum.intersects_sphere(&sphere(-15.0, 0.0, -10.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 15.0, -10.0)));
+        // Poking in through the left plane
+        assert!(frustum.intersects_sphere(&sphere(-10.5, 0.0, -10.0)));
+    }
+
+    #[test]
+    fn boxes_are_culled_outside_any_plane() {
+        let frustum = frustum();
+
+        assert!(frustum.intersects_aabb(&unit_box(Vec3::new(0.0, 0.0, -10.0))));
+        assert!(!frustum.intersects_aabb(&unit_box(Vec3::new(0.0, 0.0, 5.0))));
+        assert!(!frustum.intersects_aabb(&unit_box(Vec3::new(15.0, 0.0, -10.0))));
+        // Straddling the far plane
+        assert!(frustum.intersects_aabb(&unit_box(Vec3::new(0.0, 0.0, -100.0))));
+        // The camera is inside this one
+        assert!(frustum.intersects_aabb(&Aabb {
+            min: Vec3::broadcast(-50.0),
+            max: Vec3::broadcast(50.0),
+        }));
+    }
+
+    #[test]
+    fn transformed_boxes_enclose_the_rotated_corners() {
+        let rotated = unit_box(Vec3::zero()).transformed(
+            Mat4::from_translation(Vec3::new(0.0, 0.0, -10.0))
+                * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_4),
+        );
+
+        let half_diagonal = 2.0f32.sqrt();
+        assert!((rotated.max.x - half_diagonal).abs() < 1e-5);
+        assert!((rotated.min.z - (-10.0 - half_diagonal)).abs() < 1e-5);
+        assert!((rotated.max.y - 1.0).abs() < 1e-5);
+
+        let sphere = unit_box(Vec3::zero()).bounding_sphere();
+        assert!((sphere.radius - 3.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn culls_meshes_outside_the_view_and_counts_them() {
+        let model = PathBuf::from("crate.gltf");
+        let mut scene = RenderScene::single_mesh(
+            model.clone(),
+            perspective_wgpu_dx(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0),
+        );
+        let in_view = scene.meshes[0].clone();
+        scene.meshes[0].transform = Mat4::from_translation(Vec3::new(0.0, 0.0, -10.0));
+        for offset in &[Vec3::new(0.0, 0.0, 10.0), Vec3::new(50.0, 0.0, -10.0)] {
+            scene.meshes.push(RenderMesh {
+                transform: Mat4::from_translation(*offset),
+                ..in_view.clone()
+            });
+        }
+        // Without bounds there is nothing to test, it is drawn anyway
+        scene.meshes.push(RenderMesh {
+            model: PathBuf::from("not_loaded.gltf"),
+            transform: Mat4::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+            ..in_view
+        });
+
+        let mut mesh_bounds = MeshBounds::default();
+        mesh_bounds.insert(model, unit_box(Vec3::zero()));
+        let stats = cull(&mut scene, &mesh_bounds);
+
+        assert_eq!(
+            stats,
+            CullStats {
+                tested: 3,
+                culled: 2
+            }
+        );
+        let visible = scene
+            .meshes
+            .iter()
+            .map(|mesh| mesh.visible)
+            .collect::<Vec<_>>();
+        assert_eq!(visible, vec![true, false, false, true]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::ops::Range;
+use std::path::Path;
+
+use ultraviolet::Mat4;
+
+use super::scene::RenderMesh;
+use super::MeshAttribute;
+use crate::code::components::material::Material;
+
+/// What the `i_` inputs of the vertex shaders read from the instance buffer, see
+/// `src/assets/include/instance.glsl`.
+pub(super) const INSTANCE_ATTRIBUTES: [MeshAttribute; 4] = [
+    MeshAttribute {
+        name: "model_0",
+        format: wgpu::VertexFormat::Float4,
+        offset: 0,
+    },
+    MeshAttribute {
+        name: "model_1",
+        format: wgpu::VertexFormat::Float4,
+        offset: 16,
+    },
+    MeshAttribute {
+        name: "model_2",
+        format: wgpu::VertexFormat::Float4,
+        offset: 32,
+    },
+    MeshAttribute {
+        name: "model_3",
+        format: wgpu::VertexFormat::Float4,
+        offset: 48,
+    },
+];
+pub(super) const INSTANCE_STRIDE: wgpu::BufferAddress = std::mem::size_of::<Mat4>() as _;
+
+/// Meshes drawn by a single instanced draw call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<'a> {
+    pub model: &'a Path,
+    /// Always `None` for batches that don't care about materials.
+    pub material: Option<&'a Material>,
+    /// Which of the transforms `batch` returns belong to this batch.
+    pub instances: Range<u32>,
+}
+
+/// Groups `meshes` drawn with the same model, and the same material when `by_material`, into
+/// batches. Returns them in the order their first mesh came in, with the transforms of all
+/// batches one after another, ready for the instance buffer.
+pub fn batch<'a>(
+    meshes: impl IntoIterator<Item = &'a RenderMesh>,
+    by_material: bool,
+) -> (Vec<Batch<'a>>, Vec<Mat4>) {
+    // Levels have a handful of different models, a linear search finds their batch quickly
+    let mut groups: Vec<(&Path, Option<&Material>, Vec<Mat4>)> = Vec::new();
+    for mesh in meshes {
+        let material = if by_material {
+            mesh.material.as_ref()
+        } else {
+            None
+        };
+
+        match groups
+            .iter_mut()
+            .find(|(model, group_material, _)| *model == mesh.model && *group_material == material)
+        {
+            Some((_, _, transforms)) => transforms.push(mesh.transform),
+            None => groups.push((&mesh.model, material, vec![mesh.transform])),
+        }
+    }
+
+    let mut batches = Vec::with_capacity(groups.len());
+    let mut all_transforms = Vec::new();
+    for (model, material, transforms) in groups {
+        let first = all_transforms.len() as u32;
+        all_transforms.extend(transforms);
+        batches.push(Batch {
+            model,
+            material,
+            instances: first..all_transforms.len() as u32,
+        });
+    }
+
+    (batches, all_transforms)
+}
+
+/// A vertex buffer of model matrices, grown when more instances are drawn than fit.
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let capacity = 1;
+        Self {
+            buffer: create_instance_buffer(device, capacity),
+            capacity,
+        }
+    }
+
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, transforms: &[Mat4]) {
+        if transforms.len() > self.capacity {
+            self.capacity = transforms.len().next_power_of_two();
+            self.buffer = create_instance_buffer(device, self.capacity);
+        }
+
+        let matrices = transforms
+            .iter()
+            .map(|transform| *transform.as_array())
+            .collect::<Vec<_>>();
+        if !matrices.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&matrices));
+        }
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: capacity as wgpu::BufferAddress * INSTANCE_STRIDE,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use ultraviolet::Vec3;
+
+    fn mesh(model: &str, material: Option<&str>, x: f32) -> RenderMesh {
+        RenderMesh {
+            model: PathBuf::from(model),
+            material: material.map(|texture| Material::new(PathBuf::from(texture))),
+            transform: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            visible: true,
+        }
+    }
+
+    fn x(transform: &Mat4) -> f32 {
+        transform.cols[3].x
+    }
+
+    #[test]
+    fn batches_meshes_sharing_model_and_material() {
+        let meshes = vec![
+            mesh("crate.gltf", Some("wood.png"), 0.0),
+            mesh("barrel.gltf", Some("wood.png"), 1.0),
+            mesh("crate.gltf", Some("wood.png"), 2.0),
+            mesh("crate.gltf", Some("metal.png"), 3.0),
+            mesh("crate.gltf", None, 4.0),
+            mesh("barrel.gltf", Some("wood.png"), 5.0),
+        ];
+
+        let (batches, transforms) = batch(&meshes, true);
+
+        let summary = batches
+            .iter()
+            .map(|batch| {
+                (
+                    batch.model.to_str().unwrap(),
+                    batch
+                        .material
+                        .map(|material| material.diffuse_texture.to_str().unwrap()),
+                    batch.instances.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("crate.gltf", Some("wood.png"), 0..2),
+                ("barrel.gltf", Some("wood.png"), 2..4),
+                ("crate.gltf", Some("metal.png"), 4..5),
+                ("crate.gltf", None, 5..6),
+            ]
+        );
+        // Instances keep the order their meshes came in
+        assert_eq!(
+            transforms.iter().map(x).collect::<Vec<_>>(),
+            vec![0.0, 2.0, 1.0, 5.0, 3.0, 4.0]
+        );
+    }
+
+    #[test]
+    fn batches_by_model_only_when_materials_dont_matter() {
+        let meshes = vec![
+            mesh("crate.gltf", Some("wood.png"), 0.0),
+            mesh("crate.gltf", Some("metal.png"), 1.0),
+            mesh("barrel.gltf", None, 2.0),
+        ];
+
+        let (batches, transforms) = batch(&meshes, false);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].instances, 0..2);
+        assert_eq!(batches[0].material, None);
+        assert_eq!(batches[1].instances, 2..3);
+        assert_eq!(transforms.len(), 3);
+    }
+
+    #[test]
+    fn instance_attributes_cover_a_matrix() {
+        let covered = INSTANCE_ATTRIBUTES
+            .iter()
+            .map(|attribute| attribute.format.size())
+            .sum::<wgpu::BufferAddress>();
+
+        assert_eq!(covered, INSTANCE_STRIDE);
+        assert_eq!(
+            INSTANCE_ATTRIBUTES.last().unwrap().offset + 16,
+            INSTANCE_STRIDE
+        );
+    }
+}
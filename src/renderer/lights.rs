@@ -0,0 +1,206 @@
+use crate::code::components::light::LightKind;
+
+use super::scene::{RenderCamera, RenderLight};
+use super::shadows::{self, ShadowMap, MAX_SHADOW_MAPS};
+
+/// Kinds as the shaders know them, see `src/assets/include/lights.glsl`.
+const LIGHT_DIRECTIONAL: f32 = 0.0;
+const LIGHT_POINT: f32 = 1.0;
+const LIGHT_SPOT: f32 = 2.0;
+
+/// A `RenderLight` laid out for the `Lights` storage buffer of the fragment shader.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GpuLight {
+    /// w is the range, 0 for none.
+    position_range: [f32; 4],
+    /// w is the kind.
+    direction_kind: [f32; 4],
+    color_intensity: [f32; 4],
+    /// Cosines of the inner and outer cone angle, so the shader only needs a dot product.
+    cone: [f32; 4],
+    /// First shadow map layer, -1 for none, the number of layers, depth bias and slope bias.
+    shadow: [f32; 4],
+    /// View depths up to which each cascade of a directional light is used.
+    cascade_splits: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for GpuLight {}
+unsafe impl bytemuck::Zeroable for GpuLight {}
+
+impl From<&RenderLight> for GpuLight {
+    fn from(light: &RenderLight) -> Self {
+        let (kind, range, cone) = match light.kind {
+            LightKind::Directional => (LIGHT_DIRECTIONAL, None, [0.0; 4]),
+            LightKind::Point { range } => (LIGHT_POINT, range, [0.0; 4]),
+            LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (
+                LIGHT_SPOT,
+                range,
+                [inner_cone_angle.cos(), outer_cone_angle.cos(), 0.0, 0.0],
+            ),
+        };
+        let position = light.position;
+        let direction = light.direction;
+        let color = light.color;
+
+        Self {
+            position_range: [position.x, position.y, position.z, range.unwrap_or(0.0)],
+            direction_kind: [direction.x, direction.y, direction.z, kind],
+            color_intensity: [color.x, color.y, color.z, light.intensity],
+            cone,
+            shadow: [-1.0, 0.0, 0.0, 0.0],
+            cascade_splits: [0.0; 4],
+        }
+    }
+}
+
+/// Packs `lights` for the shaders, handing out the layers of the shadow map array in order
+/// until they run out. Returns the shadow maps to render, one per layer.
+pub fn pack_lights(
+    lights: &[RenderLight],
+    camera: &RenderCamera,
+) -> (Vec<GpuLight>, Vec<ShadowMap>) {
+    let mut gpu_lights = Vec::with_capacity(lights.len());
+    let mut shadow_maps = Vec::new();
+
+    for light in lights {
+        let mut gpu_light = GpuLight::from(light);
+        let maps = shadows::shadow_maps(light, camera);
+
+        if let Some(settings) = light.shadows {
+            if !maps.is_empty() && shadow_maps.len() + maps.len() <= MAX_SHADOW_MAPS {
+                gpu_light.shadow = [
+                    shadow_maps.len() as f32,
+                    maps.len() as f32,
+                    settings.depth_bias,
+                    settings.slope_bias,
+                ];
+                for (split, map) in gpu_light.cascade_splits.iter_mut().zip(&maps) {
+                    *split = map.split_depth;
+                }
+                shadow_maps.extend(maps);
+            }
+        }
+
+        gpu_lights.push(gpu_light);
+    }
+
+    (gpu_lights, shadow_maps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::light::ShadowSettings;
+    use ultraviolet::{projection::rh_yup::perspective_wgpu_dx, Mat4, Vec3};
+
+    fn light(kind: LightKind) -> RenderLight {
+        RenderLight {
+            kind,
+            position: Vec3::new(1.0, 2.0, 3.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            color: Vec3::new(1.0, 0.5, 0.25),
+            intensity: 4.0,
+            shadows: None,
+        }
+    }
+
+    fn shadowed(kind: LightKind) -> RenderLight {
+        RenderLight {
+            shadows: Some(ShadowSettings::default()),
+            ..light(kind)
+        }
+    }
+
+    fn camera() -> RenderCamera {
+        RenderCamera {
+            position: Vec3::zero(),
+            view: Mat4::identity(),
+            projection: perspective_wgpu_dx(1.0, 1.0, 0.1, 100.0),
+            z_near: 0.1,
+            z_far: 100.0,
+        }
+    }
+
+    #[test]
+    fn packs_lights_for_the_shader() {
+        let directional = GpuLight::from(&light(LightKind::Directional));
+        assert_eq!(directional.position_range, [1.0, 2.0, 3.0, 0.0]);
+        assert_eq!(
+            directional.direction_kind,
+            [0.0, -1.0, 0.0, LIGHT_DIRECTIONAL]
+        );
+        assert_eq!(directional.color_intensity, [1.0, 0.5, 0.25, 4.0]);
+
+        let point = GpuLight::from(&light(LightKind::Point { range: Some(10.0) }));
+        assert_eq!(point.position_range[3], 10.0);
+        assert_eq!(point.direction_kind[3], LIGHT_POINT);
+
+        let spot = GpuLight::from(&light(LightKind::Spot {
+            range: None,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: std::f32::consts::FRAC_PI_2,
+        }));
+        assert_eq!(spot.position_range[3], 0.0);
+        assert_eq!(spot.direction_kind[3], LIGHT_SPOT);
+        assert_eq!(spot.cone[0], 1.0);
+        assert!(spot.cone[1].abs() < 1e-6);
+        assert_eq!(spot.shadow[0], -1.0);
+    }
+
+    #[test]
+    fn hands_out_shadow_map_layers_until_they_run_out() {
+        let spot = LightKind::Spot {
+            range: Some(10.0),
+            inner_cone_angle: 0.2,
+            outer_cone_angle: 0.4,
+        };
+        let lights = vec![
+            shadowed(LightKind::Directional),
+            light(LightKind::Directional),
+            shadowed(LightKind::Point { range: None }),
+            shadowed(spot),
+            shadowed(LightKind::Directional),
+            // Only 8 layers, this one doesn't fit anymore
+            shadowed(LightKind::Directional),
+            shadowed(spot),
+        ];
+
+        let (gpu_lights, shadow_maps) = pack_lights(&lights, &camera());
+
+        assert_eq!(gpu_lights.len(), lights.len());
+        assert_eq!(shadow_maps.len(), MAX_SHADOW_MAPS);
+        let layers: Vec<[f32; 2]> = gpu_lights
+            .iter()
+            .map(|light| [light.shadow[0], light.shadow[1]])
+            .collect();
+        assert_eq!(
+            layers,
+            vec![
+                [0.0, 3.0],
+                [-1.0, 0.0],
+                [-1.0, 0.0],
+                [3.0, 1.0],
+                [4.0, 3.0],
+                [-1.0, 0.0],
+                [7.0, 1.0],
+            ]
+        );
+
+        let settings = ShadowSettings::default();
+        assert_eq!(gpu_lights[0].shadow[2], settings.depth_bias);
+        assert_eq!(gpu_lights[0].shadow[3], settings.slope_bias);
+        assert_eq!(gpu_lights[0].cascade_splits[2], 100.0);
+        assert_eq!(shadow_maps[3].split_depth, f32::INFINITY);
+    }
+
+    #[test]
+    fn matches_the_shader_layout() {
+        // Six vec4s, a std430 array stride of 96
+        assert_eq!(std::mem::size_of::<GpuLight>(), 96);
+    }
+}
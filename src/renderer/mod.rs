@@ -0,0 +1,1248 @@
+pub mod culling;
+pub mod instancing;
+pub mod lights;
+#[cfg(test)]
+pub mod null;
+pub mod scene;
+pub mod shadows;
+#[cfg(test)]
+pub mod software;
+#[cfg(test)]
+pub mod test_backend;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fs, iter, thread};
+
+use anyhow::*;
+use futures::executor::block_on;
+
+use crate::code::components::material::Material;
+use crate::gltf::Mesh;
+use crate::shader;
+use crate::shader::reflection::{self, ShaderReflection};
+use wgpu::util::DeviceExt;
+use winit::window::Window;
+
+use ultraviolet::*;
+
+use crate::texture::{self, ColorSpace, SamplerSettings};
+use instancing::{Batch, InstanceBuffer, INSTANCE_ATTRIBUTES, INSTANCE_STRIDE};
+use lights::GpuLight;
+use scene::{RenderCamera, RenderScene};
+use shadows::{ShadowMap, ShadowPass, SHADOW_VERTEX_SHADER_PATH};
+
+// #[repr(C)]
+// #[derive(Copy, Clone, Debug)]
+// struct Vertex {
+//     position: [f32; 3],
+//     texture_coordinates: [f32; 2],
+// }
+
+// unsafe impl bytemuck::Pod for Vertex {}
+// unsafe impl bytemuck::Zeroable for Vertex {}
+
+// impl Vertex {
+//     fn desc<'a>(&self) -> wgpu::VertexBufferDescriptor<'a> {
+//         use std::mem;
+//         wgpu::VertexBufferDescriptor {
+//             stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
+//             step_mode: wgpu::InputStepMode::Vertex,
+//             attributes: &[
+//                 wgpu::VertexAttributeDescriptor {
+//                     offset: 0,
+//                     shader_location: 0,
+//                     format: wgpu::VertexFormat::Float3,
+//                 },
+//                 wgpu::VertexAttributeDescriptor {
+//                     offset: self.position.len() as wgpu::BufferAddress,
+//                     shader_location: 1,
+//                     format: wgpu::VertexFormat::Float2,
+//                 },
+//             ],
+//         }
+//     }
+// }
+
+/// Matches `src/assets/include/uniforms.glsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Uniforms {
+    view_proj: ultraviolet::Mat4,
+    camera_position: [f32; 4],
+    /// Metallic and roughness.
+    material: [f32; 4],
+    light_count: [u32; 4],
+}
+
+unsafe impl bytemuck::Pod for Uniforms {}
+unsafe impl bytemuck::Zeroable for Uniforms {}
+
+impl Uniforms {
+    fn new() -> Self {
+        Self {
+            view_proj: Mat4::identity(),
+            camera_position: [0.0; 4],
+            material: [0.0; 4],
+            light_count: [0; 4],
+        }
+    }
+}
+
+const VERTEX_SHADER_PATH: &str = "./src/assets/shader.vert";
+const FRAGMENT_SHADER_PATH: &str = "./src/assets/shader.frag";
+
+/// Bind group sets, as declared in the shaders.
+const TEXTURE_SET: u32 = 0;
+const UNIFORM_SET: u32 = 1;
+const SHADOW_SET: u32 = 2;
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Draws a `RenderScene`. Implemented by the wgpu `State`, by `SoftwareRenderer`, which runs
+/// on the CPU so rendering can be tested on machines without a GPU, and by `NullBackend` and
+/// `TestBackend`, which draw nothing.
+///
+/// Backends keep what they are given by path. A `RenderMesh` is drawn with the meshes loaded
+/// for its `model` and the texture loaded for the `diffuse_texture` of its material, meshes
+/// whose model isn't loaded yet are skipped.
+pub trait RenderBackend {
+    /// Loads the meshes of the model at `path`, replacing those loaded for it before. If they
+    /// can't be drawn the old ones are kept.
+    fn load_model(&mut self, path: &Path, meshes: &[Mesh]) -> Result<()>;
+    /// Loads the diffuse texture at `path`, replacing the one loaded for it before.
+    fn load_diffuse_texture(
+        &mut self,
+        path: &Path,
+        image: &image::DynamicImage,
+        sampler: &SamplerSettings,
+    ) -> Result<()>;
+    /// Draws a frame to the window, if there is one.
+    fn render(&mut self, scene: &RenderScene);
+    /// Draws a frame offscreen and reads the image back.
+    fn capture(&mut self, scene: &RenderScene) -> Result<image::RgbaImage>;
+}
+
+pub struct State {
+    /// Headless renderers only draw offscreen, they have no surface or swap chain.
+    surface: Option<wgpu::Surface>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Also the size and format of offscreen render targets.
+    sc_desc: wgpu::SwapChainDescriptor,
+    swap_chain: Option<wgpu::SwapChain>,
+    /// Same size as the swap chain, so nearer meshes hide the ones behind them.
+    depth_texture: DepthTexture,
+    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    fs_module: wgpu::ShaderModule,
+    vs_reflection: ShaderReflection,
+    fs_reflection: ShaderReflection,
+    bind_group_layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    size: winit::dpi::PhysicalSize<u32>,
+    models: HashMap<PathBuf, Vec<GpuMesh>>,
+    mesh_layout: MeshLayout,
+    /// Model matrices of the visible meshes, batch after batch.
+    instance_buffer: InstanceBuffer,
+    diffuse_textures: HashMap<PathBuf, DiffuseTexture>,
+    /// White, for meshes without a material or whose texture isn't loaded.
+    default_diffuse_texture: DiffuseTexture,
+    uniforms: Uniforms,
+    /// The `Uniforms` of every batch, each in a block of its own picked by dynamic offset.
+    uniform_buffer: wgpu::Buffer,
+    /// Batches that fit into the uniform buffer, grown like `lights_capacity`.
+    uniforms_capacity: usize,
+    /// `GpuLight`s, grown when a scene has more lights than fit.
+    lights_buffer: wgpu::Buffer,
+    lights_capacity: usize,
+    uniform_bind_group: wgpu::BindGroup,
+    shadow_pass: ShadowPass,
+    screenshot_path: Option<PathBuf>,
+}
+
+impl State {
+    pub async fn new(window: &Window) -> Self {
+        let size = window.inner_size();
+
+        // The instance is a handle to our GPU
+        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let surface = unsafe { instance.create_surface(window) };
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::Default,
+                compatible_surface: Some(&surface),
+            })
+            .await
+            .unwrap();
+
+        let mut state = Self::with_adapter(&adapter, size).await;
+        state.swap_chain = Some(state.device.create_swap_chain(&surface, &state.sc_desc));
+        state.surface = Some(surface);
+        state
+    }
+
+    async fn with_adapter(adapter: &wgpu::Adapter, size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::default(),
+                    shader_validation: true,
+                },
+                None, // Trace path
+            )
+            .await
+            .unwrap();
+
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        // The layouts come from the shaders, so they can't disagree with them
+        let vs_spirv = include_bytes!("../assets/shader.vert.spv");
+        let fs_spirv = include_bytes!("../assets/shader.frag.spv");
+        let vs_reflection = reflection::reflect(vs_spirv).expect("invalid vertex shader SPIR-V?");
+        let fs_reflection = reflection::reflect(fs_spirv).expect("invalid fragment shader SPIR-V?");
+        let bind_group_layout_entries = bind_group_layouts(&vs_reflection, &fs_reflection)
+            .expect("vertex and fragment shader disagree on their bindings?");
+        let bind_group_layouts = bind_group_layout_entries
+            .iter()
+            .enumerate()
+            .map(|(set, entries)| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries,
+                    label: Some(&format!("bind_group_layout_{}", set)),
+                })
+            })
+            .collect::<Vec<_>>();
+        let texture_bind_group_layout = &bind_group_layouts[TEXTURE_SET as usize];
+        let uniform_bind_group_layout = &bind_group_layouts[UNIFORM_SET as usize];
+
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([255, 255, 255, 255]),
+        ));
+        let default_diffuse_texture = DiffuseTexture::new(
+            &device,
+            &queue,
+            texture_bind_group_layout,
+            &white,
+            &SamplerSettings::default(),
+        )
+        .expect("failed creating default diffuse texture?");
+
+        let uniforms = Uniforms::new();
+
+        let uniforms_capacity = 1;
+        let uniform_buffer = create_uniform_buffer(&device, uniforms_capacity);
+
+        let lights_capacity = 1;
+        let lights_buffer = create_lights_buffer(&device, lights_capacity);
+
+        let uniform_bind_group = create_uniform_bind_group(
+            &device,
+            uniform_bind_group_layout,
+            &uniform_buffer,
+            &lights_buffer,
+        );
+
+        let vs_module = device.create_shader_module(wgpu::util::make_spirv(vs_spirv));
+        let fs_module = device.create_shader_module(wgpu::util::make_spirv(fs_spirv));
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+                push_constant_ranges: &[],
+            });
+
+        let mesh_layout = MeshLayout::new(&vs_reflection)
+            .expect("meshes don't have what the vertex shader needs?");
+        let shadow_pass = ShadowPass::new(&device, &bind_group_layouts[SHADOW_SET as usize]);
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &vs_module,
+            &fs_module,
+            sc_desc.format,
+            &mesh_layout,
+        );
+
+        let instance_buffer = InstanceBuffer::new(&device);
+        let depth_texture = DepthTexture::new(&device, sc_desc.width, sc_desc.height);
+
+        Self {
+            surface: None,
+            device,
+            queue,
+            sc_desc,
+            swap_chain: None,
+            depth_texture,
+            render_pipeline,
+            render_pipeline_layout,
+            vs_module,
+            fs_module,
+            vs_reflection,
+            fs_reflection,
+            bind_group_layout_entries,
+            bind_group_layouts,
+            models: HashMap::new(),
+            mesh_layout,
+            instance_buffer,
+            diffuse_textures: HashMap::new(),
+            default_diffuse_texture,
+            uniform_buffer,
+            uniforms_capacity,
+            lights_buffer,
+            lights_capacity,
+            uniform_bind_group,
+            shadow_pass,
+            uniforms,
+            size,
+            screenshot_path: None,
+        }
+    }
+
+    /// Recompiles our shaders that are or include `path` and rebuilds the pipeline with them, a
+    /// shader that fails to compile leaves the old one in place. Returns whether any was affected.
+    pub fn reload_shader(&mut self, path: &Path) -> bool {
+        let mut affected = false;
+        let mut recompiled = false;
+
+        for &shader_path in &[VERTEX_SHADER_PATH, FRAGMENT_SHADER_PATH] {
+            let shader_path = Path::new(shader_path);
+            if !shader::depends_on(shader_path, path) {
+                continue;
+            }
+            affected = true;
+
+            let spirv = match shader::compile_glsl(shader_path, &[]) {
+                Ok(spirv) => spirv,
+                Err(error) => {
+                    log::warn!(
+                        "keeping old shader, {} failed to compile:\n{:#}",
+                        shader_path.display(),
+                        error
+                    );
+                    continue;
+                }
+            };
+
+            let is_vertex_shader = shader_path == Path::new(VERTEX_SHADER_PATH);
+            if let Err(error) = self.check_reloaded_shader(&spirv, is_vertex_shader) {
+                log::warn!("keeping old shader {}: {:#}", shader_path.display(), error);
+                continue;
+            }
+
+            let module = self
+                .device
+                .create_shader_module(wgpu::util::make_spirv(&spirv));
+            if is_vertex_shader {
+                self.vs_module = module;
+            } else {
+                self.fs_module = module;
+            }
+            recompiled = true;
+            log::info!("reloaded shader {}", shader_path.display());
+        }
+
+        if recompiled {
+            self.rebuild_render_pipeline();
+        }
+
+        // Shares the instance inputs with the main vertex shader, but has a pipeline of its own
+        let shadow_shader_path = Path::new(SHADOW_VERTEX_SHADER_PATH);
+        if shader::depends_on(shadow_shader_path, path) {
+            affected = true;
+
+            match shader::compile_glsl(shadow_shader_path, &[]) {
+                Ok(spirv) => match self.shadow_pass.reload_vertex_shader(&self.device, &spirv) {
+                    Ok(()) => log::info!("reloaded shader {}", shadow_shader_path.display()),
+                    Err(error) => log::warn!(
+                        "keeping old shader {}: {:#}",
+                        shadow_shader_path.display(),
+                        error
+                    ),
+                },
+                Err(error) => log::warn!(
+                    "keeping old shader, {} failed to compile:\n{:#}",
+                    shadow_shader_path.display(),
+                    error
+                ),
+            }
+        }
+
+        affected
+    }
+
+    /// Takes in the reflection of a recompiled shader if the pipeline can use it. The bind
+    /// groups were made for the old bindings, changing those needs a restart.
+    fn check_reloaded_shader(&mut self, spirv: &[u8], is_vertex_shader: bool) -> Result<()> {
+        let reloaded = reflection::reflect(spirv)?;
+        let bind_group_layout_entries = if is_vertex_shader {
+            bind_group_layouts(&reloaded, &self.fs_reflection)?
+        } else {
+            bind_group_layouts(&self.vs_reflection, &reloaded)?
+        };
+        if bind_group_layout_entries != self.bind_group_layout_entries {
+            bail!("its bindings changed, restart to pick them up");
+        }
+
+        if is_vertex_shader {
+            self.mesh_layout = MeshLayout::new(&reloaded)?;
+            self.vs_reflection = reloaded;
+        } else {
+            self.fs_reflection = reloaded;
+        }
+        Ok(())
+    }
+
+    fn rebuild_render_pipeline(&mut self) {
+        self.render_pipeline = create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.vs_module,
+            &self.fs_module,
+            self.sc_desc.format,
+            &self.mesh_layout,
+        );
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.size = new_size;
+        self.sc_desc.width = new_size.width;
+        self.sc_desc.height = new_size.height;
+        if let Some(surface) = &self.surface {
+            self.swap_chain = Some(self.device.create_swap_chain(surface, &self.sc_desc));
+        }
+        self.depth_texture = DepthTexture::new(&self.device, new_size.width, new_size.height);
+
+        // camera.aspect = self.sc_desc.width as f32 / self.sc_desc.height as f32;
+    }
+
+    /// Saves the next frame `render` draws to `path` as a PNG.
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.screenshot_path = Some(path);
+    }
+
+    fn capture_frame(&mut self, scene: &RenderScene) -> Result<image::RgbaImage> {
+        let (width, height) = (self.sc_desc.width, self.sc_desc.height);
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+
+        let target = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_target"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.sc_desc.format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        if (self.depth_texture.width, self.depth_texture.height) != (width, height) {
+            self.depth_texture = DepthTexture::new(&self.device, width, height);
+        }
+        self.draw(
+            &target.create_view(&wgpu::TextureViewDescriptor::default()),
+            scene,
+        );
+
+        // Rows copied into buffers have to start at aligned offsets
+        let row_size = width * 4;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_size = row_size + (alignment - row_size % alignment) % alignment;
+
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_buffer"),
+            size: u64::from(padded_row_size * height),
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &target,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &readback_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_row_size,
+                    rows_per_image: height,
+                },
+            },
+            extent,
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let readback = readback_buffer.slice(..);
+        let mapping = readback.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).context("Failed reading back the rendered image")?;
+
+        let mut pixels = Vec::with_capacity((row_size * height) as usize);
+        for row in readback.get_mapped_range().chunks(padded_row_size as usize) {
+            pixels.extend_from_slice(&row[..row_size as usize]);
+        }
+        readback_buffer.unmap();
+
+        if self.sc_desc.format == wgpu::TextureFormat::Bgra8UnormSrgb {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .context("Read back image has the wrong size")
+    }
+
+    /// Records the shadow maps and the scene into one encoder, submitted once for the frame.
+    fn draw(&mut self, target: &wgpu::TextureView, scene: &RenderScene) {
+        let (batches, transforms) =
+            instancing::batch(scene.meshes.iter().filter(|mesh| mesh.visible), true);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        // Without a camera there is nothing to see, the frame is only cleared
+        let batches = match scene.camera() {
+            Some(camera) if !batches.is_empty() => {
+                let shadow_maps = self.write_lights(scene, camera);
+                self.shadow_pass.render(
+                    &self.device,
+                    &self.queue,
+                    &mut encoder,
+                    &shadow_maps,
+                    &scene.meshes,
+                    &self.models,
+                );
+
+                self.instance_buffer
+                    .write(&self.device, &self.queue, &transforms);
+                self.write_uniforms(camera, &batches);
+                batches
+            }
+            _ => Vec::new(),
+        };
+
+        self.draw_pass(&mut encoder, target, &batches);
+        self.queue.submit(iter::once(encoder.finish()));
+    }
+
+    /// Writes the uniforms of every batch to a block of its own, for `draw_pass` to pick by
+    /// dynamic offset.
+    fn write_uniforms(&mut self, camera: &RenderCamera, batches: &[Batch]) {
+        if batches.len() > self.uniforms_capacity {
+            self.uniforms_capacity = batches.len().next_power_of_two();
+            self.uniform_buffer = create_uniform_buffer(&self.device, self.uniforms_capacity);
+            self.uniform_bind_group = create_uniform_bind_group(
+                &self.device,
+                &self.bind_group_layouts[UNIFORM_SET as usize],
+                &self.uniform_buffer,
+                &self.lights_buffer,
+            );
+        }
+
+        let position = camera.position;
+        self.uniforms.view_proj = camera.view_projection();
+        self.uniforms.camera_position = [position.x, position.y, position.z, 1.0];
+
+        let default_material = Material::new(PathBuf::new());
+        let uniforms = self.uniforms;
+        let blocks = batches
+            .iter()
+            .map(|batch| {
+                let material = batch.material.unwrap_or(&default_material);
+                Uniforms {
+                    material: [material.metallic, material.roughness, 0.0, 0.0],
+                    ..uniforms
+                }
+            })
+            .collect::<Vec<_>>();
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, &uniform_blocks(&blocks));
+    }
+
+    /// Returns the shadow maps the lights need rendered.
+    fn write_lights(&mut self, scene: &RenderScene, camera: &RenderCamera) -> Vec<ShadowMap> {
+        let (lights, shadow_maps) = lights::pack_lights(&scene.lights, camera);
+
+        if lights.len() > self.lights_capacity {
+            self.lights_capacity = lights.len().next_power_of_two();
+            self.lights_buffer = create_lights_buffer(&self.device, self.lights_capacity);
+            self.uniform_bind_group = create_uniform_bind_group(
+                &self.device,
+                &self.bind_group_layouts[UNIFORM_SET as usize],
+                &self.uniform_buffer,
+                &self.lights_buffer,
+            );
+        }
+
+        if !lights.is_empty() {
+            self.queue
+                .write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(&lights));
+        }
+        self.uniforms.light_count = [lights.len() as u32, 0, 0, 0];
+
+        shadow_maps
+    }
+
+    /// Draws every batch in a single render pass, with the uniforms `write_uniforms` wrote for
+    /// it.
+    fn draw_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        batches: &[Batch],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(SHADOW_SET, &self.shadow_pass.bind_group, &[]);
+        for (index, batch) in batches.iter().enumerate() {
+            let diffuse_texture = batch
+                .material
+                .and_then(|material| self.diffuse_textures.get(&material.diffuse_texture))
+                .unwrap_or(&self.default_diffuse_texture);
+            let uniforms_offset = index as wgpu::BufferAddress * uniform_stride::<Uniforms>();
+
+            render_pass.set_bind_group(TEXTURE_SET, &diffuse_texture.bind_group, &[]);
+            render_pass.set_bind_group(
+                UNIFORM_SET,
+                &self.uniform_bind_group,
+                &[uniforms_offset as wgpu::DynamicOffset],
+            );
+            for mesh in self.models.get(batch.model).into_iter().flatten() {
+                self.mesh_layout
+                    .set_buffers(&mut render_pass, mesh, &self.instance_buffer.buffer);
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..));
+                render_pass.draw_indexed(0..mesh.num_indices, 0, batch.instances.clone());
+            }
+        }
+    }
+}
+
+impl RenderBackend for State {
+    fn load_model(&mut self, path: &Path, meshes: &[Mesh]) -> Result<()> {
+        let meshes = meshes
+            .iter()
+            .map(|mesh| GpuMesh::new(&self.device, mesh))
+            .collect();
+        self.models.insert(path.to_path_buf(), meshes);
+        Ok(())
+    }
+
+    fn load_diffuse_texture(
+        &mut self,
+        path: &Path,
+        image: &image::DynamicImage,
+        sampler: &SamplerSettings,
+    ) -> Result<()> {
+        let diffuse_texture = DiffuseTexture::new(
+            &self.device,
+            &self.queue,
+            &self.bind_group_layouts[TEXTURE_SET as usize],
+            image,
+            sampler,
+        )?;
+        self.diffuse_textures
+            .insert(path.to_path_buf(), diffuse_texture);
+        Ok(())
+    }
+
+    fn render(&mut self, scene: &RenderScene) {
+        if let Some(swap_chain) = &mut self.swap_chain {
+            let frame = swap_chain
+                .get_current_frame()
+                .expect("Timeout getting texture")
+                .output;
+            self.draw(&frame.view, scene);
+        }
+
+        if let Some(path) = self.screenshot_path.take() {
+            match self.capture(scene) {
+                // Encoding the PNG takes a while, no need to hold up the next frame for it
+                Ok(screenshot) => {
+                    thread::spawn(move || match save_png(&screenshot, &path) {
+                        Ok(()) => log::info!("saved screenshot {}", path.display()),
+                        Err(error) => log::error!("failed saving screenshot: {:#}", error),
+                    });
+                }
+                Err(error) => log::error!("failed taking screenshot: {:#}", error),
+            }
+        }
+    }
+
+    fn capture(&mut self, scene: &RenderScene) -> Result<image::RgbaImage> {
+        self.capture_frame(scene)
+    }
+}
+
+/// Writes `image` to `path` as a PNG, creating the directory it is in.
+pub fn save_png(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Failed creating {}", directory.display()))?;
+    }
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("Failed writing {}", path.display()))
+}
+
+/// An attribute array of a mesh, they are packed one after another into the vertex buffer.
+#[derive(Debug, Clone)]
+struct MeshAttribute {
+    name: &'static str,
+    format: wgpu::VertexFormat,
+    offset: wgpu::BufferAddress,
+}
+
+impl MeshAttribute {
+    fn of(mesh: &Mesh) -> Vec<Self> {
+        let normals_offset = mesh.positions.len();
+        let tex_coords_offset = normals_offset + mesh.normals.len();
+
+        vec![
+            MeshAttribute {
+                name: "position",
+                format: wgpu::VertexFormat::Float3,
+                offset: 0,
+            },
+            MeshAttribute {
+                name: "normal",
+                format: wgpu::VertexFormat::Float3,
+                offset: normals_offset as wgpu::BufferAddress,
+            },
+            MeshAttribute {
+                name: "tex_coords",
+                format: wgpu::VertexFormat::Float2,
+                offset: tex_coords_offset as wgpu::BufferAddress,
+            },
+        ]
+    }
+}
+
+/// A mesh uploaded to the GPU, its attributes one after another in the vertex buffer.
+struct GpuMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    attributes: Vec<MeshAttribute>,
+}
+
+impl GpuMesh {
+    fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: &[
+                mesh.positions.as_slice(),
+                mesh.normals.as_slice(),
+                mesh.texture_coordinates.as_slice(),
+            ]
+            .concat(),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: mesh.indices.as_slice(),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices: (mesh.indices.len() / 2) as u32,
+            attributes: MeshAttribute::of(mesh),
+        }
+    }
+}
+
+/// A diffuse texture with the bind group of the texture set that samples it.
+struct DiffuseTexture {
+    #[allow(dead_code)]
+    texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl DiffuseTexture {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        image: &image::DynamicImage,
+        sampler: &SamplerSettings,
+    ) -> Result<Self> {
+        let texture = texture::Texture::from_image(
+            device,
+            queue,
+            image,
+            ColorSpace::Srgb,
+            sampler,
+            Some("diffuse_texture"),
+        )?;
+        let bind_group = create_diffuse_bind_group(device, texture_bind_group_layout, &texture);
+
+        Ok(Self {
+            texture,
+            bind_group,
+        })
+    }
+}
+
+struct DepthTexture {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+}
+
+impl DepthTexture {
+    fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("depth_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+}
+
+/// Which mesh attribute feeds each vertex shader input, one vertex buffer slot per input, and
+/// which part of the instance buffer feeds each per instance input.
+#[derive(Debug, Clone)]
+struct MeshLayout {
+    /// Read from the start of the slot, where each mesh's attribute starts is bound per mesh.
+    attributes: Vec<(&'static str, wgpu::VertexAttributeDescriptor)>,
+    instance_attributes: Vec<wgpu::VertexAttributeDescriptor>,
+}
+
+impl MeshLayout {
+    /// Vertex shader inputs are matched to mesh attributes by name, `a_normal` reads `normal`.
+    /// Inputs starting with `i_` read `INSTANCE_ATTRIBUTES` instead.
+    fn new(vertex_shader: &ShaderReflection) -> Result<Self> {
+        // Every mesh has the same attributes, only at different offsets
+        let mesh_attributes = MeshAttribute::of(&Mesh::default());
+        let mut attributes = Vec::new();
+        let mut instance_attributes = Vec::new();
+
+        for input in &vertex_shader.inputs {
+            let (name, available, kind) = match input.name.strip_prefix("i_") {
+                Some(name) => (name, &INSTANCE_ATTRIBUTES[..], "instances"),
+                None => (
+                    input.name.strip_prefix("a_").unwrap_or(&input.name),
+                    &mesh_attributes[..],
+                    "meshes",
+                ),
+            };
+
+            let attribute = available
+                .iter()
+                .find(|attribute| attribute.name == name)
+                .with_context(|| {
+                    format!(
+                        "Vertex shader reads {} at location {} but {} have no {} attribute",
+                        input.name, input.location, kind, name
+                    )
+                })?;
+            if attribute.format != input.format {
+                bail!(
+                    "Vertex shader reads {} as {:?} but {} store it as {:?}",
+                    input.name,
+                    input.format,
+                    kind,
+                    attribute.format
+                );
+            }
+
+            if input.name.starts_with("i_") {
+                instance_attributes.push(wgpu::VertexAttributeDescriptor {
+                    offset: attribute.offset,
+                    shader_location: input.location,
+                    format: input.format,
+                });
+            } else {
+                attributes.push((
+                    attribute.name,
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 0,
+                        shader_location: input.location,
+                        format: input.format,
+                    },
+                ));
+            }
+        }
+
+        Ok(Self {
+            attributes,
+            instance_attributes,
+        })
+    }
+
+    /// Every attribute reads the mesh's vertex buffer through a slot of its own, and the
+    /// instance buffer goes into the slot after them.
+    fn vertex_buffers(&self) -> Vec<wgpu::VertexBufferDescriptor<'_>> {
+        let mut vertex_buffers = self
+            .attributes
+            .iter()
+            .map(|(_, attribute)| wgpu::VertexBufferDescriptor {
+                stride: attribute.format.size(),
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: std::slice::from_ref(attribute),
+            })
+            .collect::<Vec<_>>();
+
+        if !self.instance_attributes.is_empty() {
+            vertex_buffers.push(wgpu::VertexBufferDescriptor {
+                stride: INSTANCE_STRIDE,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &self.instance_attributes,
+            });
+        }
+
+        vertex_buffers
+    }
+
+    /// Binds the mesh's vertex buffer to every attribute slot, from where the attribute starts,
+    /// and the instance buffer after them.
+    fn set_buffers<'a>(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        mesh: &'a GpuMesh,
+        instance_buffer: &'a wgpu::Buffer,
+    ) {
+        for (slot, (name, _)) in self.attributes.iter().enumerate() {
+            let offset = mesh
+                .attributes
+                .iter()
+                .find(|attribute| attribute.name == *name)
+                .map_or(0, |attribute| attribute.offset);
+            render_pass.set_vertex_buffer(slot as u32, mesh.vertex_buffer.slice(offset..));
+        }
+        if !self.instance_attributes.is_empty() {
+            render_pass.set_vertex_buffer(self.attributes.len() as u32, instance_buffer.slice(..));
+        }
+    }
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    mesh_layout: &MeshLayout,
+) -> wgpu::RenderPipeline {
+    let vertex_buffers = mesh_layout.vertex_buffers();
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(render_pipeline_layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+            clamp_depth: false,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilStateDescriptor {
+                front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &vertex_buffers,
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+/// Layouts of the main pipeline's bind groups. The uniforms are bound at a different offset for
+/// every batch.
+fn bind_group_layouts(
+    vs_reflection: &ShaderReflection,
+    fs_reflection: &ShaderReflection,
+) -> Result<Vec<Vec<wgpu::BindGroupLayoutEntry>>> {
+    let mut bind_group_layout_entries =
+        reflection::bind_group_layouts(&[vs_reflection, fs_reflection])?;
+    let uniforms = bind_group_layout_entries
+        .get_mut(UNIFORM_SET as usize)
+        .context("No uniforms")?;
+    reflection::use_dynamic_offset(uniforms, 0)?;
+
+    Ok(bind_group_layout_entries)
+}
+
+/// Distance between the blocks of a uniform buffer holding one `T` per draw, dynamic offsets
+/// have to be aligned.
+fn uniform_stride<T>() -> wgpu::BufferAddress {
+    let size = std::mem::size_of::<T>() as wgpu::BufferAddress;
+    let alignment = wgpu::BIND_BUFFER_ALIGNMENT;
+
+    size + (alignment - size % alignment) % alignment
+}
+
+/// `blocks` laid out `uniform_stride` apart, ready to write to a uniform buffer.
+fn uniform_blocks<T: bytemuck::Pod>(blocks: &[T]) -> Vec<u8> {
+    let stride = uniform_stride::<T>() as usize;
+    let mut bytes = vec![0; blocks.len() * stride];
+    for (block, chunk) in blocks.iter().zip(bytes.chunks_mut(stride)) {
+        chunk[..std::mem::size_of::<T>()].copy_from_slice(bytemuck::bytes_of(block));
+    }
+
+    bytes
+}
+
+fn create_uniform_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Uniform Buffer"),
+        size: capacity as wgpu::BufferAddress * uniform_stride::<Uniforms>(),
+        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_lights_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Lights Buffer"),
+        size: (capacity * std::mem::size_of::<GpuLight>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_uniform_bind_group(
+    device: &wgpu::Device,
+    uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    lights_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: uniform_bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                // One batch's block, the dynamic offset picks which
+                resource: wgpu::BindingResource::Buffer(
+                    uniform_buffer.slice(..std::mem::size_of::<Uniforms>() as wgpu::BufferAddress),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(lights_buffer.slice(..)),
+            },
+        ],
+        label: Some("uniform_bind_group"),
+    })
+}
+
+fn create_diffuse_bind_group(
+    device: &wgpu::Device,
+    texture_bind_group_layout: &wgpu::BindGroupLayout,
+    diffuse_texture: &texture::Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: texture_bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gltf::GltfLoader;
+
+    /// Different adapters round and filter a little differently.
+    const CHANNEL_TOLERANCE: u8 = 8;
+
+    fn mismatched_pixels(image: &image::RgbaImage, golden: &image::RgbaImage) -> usize {
+        image
+            .pixels()
+            .zip(golden.pixels())
+            .filter(|(pixel, golden_pixel)| {
+                pixel
+                    .0
+                    .iter()
+                    .zip(golden_pixel.0.iter())
+                    .any(|(channel, golden_channel)| {
+                        (i16::from(*channel) - i16::from(*golden_channel)).abs()
+                            > i16::from(CHANNEL_TOLERANCE)
+                    })
+            })
+            .count()
+    }
+
+    /// Loads the test scene into `backend` and captures it from a fixed camera.
+    pub(crate) fn capture_test_scene<B: RenderBackend>(backend: &mut B) -> image::RgbaImage {
+        let model = PathBuf::from("./src/assets/render_test_scene.gltf");
+        let diffuse_texture = PathBuf::from("./src/assets/cube_texture_uv.png");
+        let meshes = GltfLoader::load(&model).unwrap();
+        backend.load_model(&model, &meshes).unwrap();
+        backend
+            .load_diffuse_texture(
+                &diffuse_texture,
+                &image::open(&diffuse_texture).unwrap(),
+                &meshes[0].diffuse_sampler,
+            )
+            .unwrap();
+
+        let projection = projection::rh_yup::perspective_wgpu_dx(1.0, 1.0, 0.1, 100.0);
+        let view = Mat4::look_at(Vec3::new(3.0, 3.0, 3.0), Vec3::zero(), Vec3::unit_y());
+        let mut scene = RenderScene::single_mesh(model, projection * view);
+        scene.meshes[0].material = Some(Material::new(diffuse_texture));
+
+        backend.capture(&scene).unwrap()
+    }
+
+    /// Compares `image` to the golden image at `path`. Run with `UPDATE_GOLDEN=1` to write the
+    /// golden image instead, so it can be looked over and committed.
+    pub(crate) fn assert_matches_golden_image(image: &image::RgbaImage, path: &str) {
+        if std::env::var("UPDATE_GOLDEN").as_deref() == Ok("1") {
+            save_png(image, Path::new(path)).unwrap();
+            println!("wrote golden image {}", path);
+            return;
+        }
+
+        let golden = match image::open(path) {
+            Ok(golden) => golden.to_rgba(),
+            Err(error) => panic!(
+                "failed opening golden image {}, run with UPDATE_GOLDEN=1 to write it: {}",
+                path, error
+            ),
+        };
+
+        assert_eq!(image.dimensions(), golden.dimensions());
+        assert_eq!(mismatched_pixels(image, &golden), 0);
+    }
+
+    #[test]
+    fn uniform_blocks_start_at_aligned_offsets() {
+        let stride = uniform_stride::<Uniforms>();
+        assert_eq!(stride % wgpu::BIND_BUFFER_ALIGNMENT, 0);
+        assert!(stride >= std::mem::size_of::<Uniforms>() as wgpu::BufferAddress);
+
+        let blocks = [[1.0f32; 4], [2.0; 4]];
+        let bytes = uniform_blocks(&blocks);
+        let stride = uniform_stride::<[f32; 4]>() as usize;
+        assert_eq!(bytes.len(), 2 * stride);
+        assert_eq!(&bytes[stride..stride + 16], bytemuck::bytes_of(&blocks[1]));
+    }
+
+    #[test]
+    fn instance_inputs_read_the_instance_buffer() {
+        let vertex_shader =
+            reflection::reflect(include_bytes!("../assets/shader.vert.spv")).unwrap();
+        let layout = MeshLayout::new(&vertex_shader).unwrap();
+
+        let vertex_buffers = layout.vertex_buffers();
+        assert_eq!(vertex_buffers.len(), 4);
+        assert!(vertex_buffers[..3]
+            .iter()
+            .all(|buffer| buffer.step_mode == wgpu::InputStepMode::Vertex));
+        let instances = &vertex_buffers[3];
+        assert_eq!(instances.step_mode, wgpu::InputStepMode::Instance);
+        assert_eq!(instances.stride, INSTANCE_STRIDE);
+        assert_eq!(
+            instances
+                .attributes
+                .iter()
+                .map(|attribute| (attribute.shader_location, attribute.offset))
+                .collect::<Vec<_>>(),
+            vec![(8, 0), (9, 16), (10, 32), (11, 48)]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use anyhow::*;
+
+use super::scene::RenderScene;
+use super::RenderBackend;
+use crate::gltf::Mesh;
+use crate::texture::SamplerSettings;
+
+/// Accepts everything and draws nothing, for running the game without a window or GPU.
+#[derive(Debug, Default)]
+pub struct NullBackend;
+
+impl RenderBackend for NullBackend {
+    fn load_model(&mut self, _path: &Path, _meshes: &[Mesh]) -> Result<()> {
+        Ok(())
+    }
+
+    fn load_diffuse_texture(
+        &mut self,
+        _path: &Path,
+        _image: &image::DynamicImage,
+        _sampler: &SamplerSettings,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn render(&mut self, _scene: &RenderScene) {}
+
+    fn capture(&mut self, _scene: &RenderScene) -> Result<image::RgbaImage> {
+        bail!("The null backend doesn't draw anything to capture")
+    }
+}
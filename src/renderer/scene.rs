@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use ultraviolet::*;
+
+use super::culling::CullStats;
+use crate::code::components::{
+    light::{LightKind, ShadowSettings},
+    material::Material,
+};
+
+/// What a frame shows, copied out of the world by `extract_render_scene` so backends never
+/// touch legion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderScene {
+    /// Backends draw the scene as seen by the first one.
+    pub cameras: Vec<RenderCamera>,
+    pub meshes: Vec<RenderMesh>,
+    pub lights: Vec<RenderLight>,
+    /// Filled in by `cull_render_scene`.
+    pub cull_stats: CullStats,
+}
+
+impl RenderScene {
+    /// A scene drawing one mesh, untransformed, with `view_projection` as the camera.
+    #[cfg(test)]
+    pub fn single_mesh(model: PathBuf, view_projection: Mat4) -> Self {
+        Self {
+            cameras: vec![RenderCamera {
+                position: Vec3::zero(),
+                view: Mat4::identity(),
+                projection: view_projection,
+                z_near: 0.1,
+                z_far: 100.0,
+            }],
+            meshes: vec![RenderMesh {
+                model,
+                material: None,
+                transform: Mat4::identity(),
+                visible: true,
+            }],
+            lights: Vec::new(),
+            cull_stats: CullStats::default(),
+        }
+    }
+
+    pub fn camera(&self) -> Option<&RenderCamera> {
+        self.cameras.first()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderCamera {
+    pub position: Vec3,
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// The clip planes `projection` was made with.
+    pub z_near: f32,
+    pub z_far: f32,
+}
+
+impl RenderCamera {
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection * self.view
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderMesh {
+    /// glTF file the mesh comes from.
+    pub model: PathBuf,
+    /// `None` is shaded with the defaults of `Material::new`.
+    pub material: Option<Material>,
+    /// Model to world space.
+    pub transform: Mat4,
+    /// False when culled, the mesh is then only drawn into shadow maps.
+    pub visible: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderLight {
+    pub kind: LightKind,
+    pub position: Vec3,
+    /// Normalized, in world space.
+    pub direction: Vec3,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+    pub shadows: Option<ShadowSettings>,
+}
@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+
+use anyhow::*;
+use ultraviolet::projection::rh_yup::{orthographic_wgpu_dx, perspective_wgpu_dx};
+use ultraviolet::*;
+
+use super::instancing::{self, InstanceBuffer};
+use super::scene::{RenderCamera, RenderLight, RenderMesh};
+use super::{uniform_blocks, uniform_stride, GpuMesh, MeshLayout};
+use crate::code::components::light::LightKind;
+use crate::shader::reflection;
+
+/// Layers of the shadow map array, shared by all shadow casting lights. Lights that don't fit
+/// anymore don't cast shadows.
+pub const MAX_SHADOW_MAPS: usize = 8;
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Shadow maps a directional light covers the camera frustum with, the nearest one covering the
+/// smallest slice in the most detail.
+pub const CASCADE_COUNT: usize = 3;
+/// Blend between evenly sized cascades at 0 and logarithmically growing ones at 1.
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+/// How far behind a cascade casters are still caught, so things outside the view like a
+/// rooftop still shadow it.
+const CASTER_DISTANCE: f32 = 50.0;
+
+pub const SHADOW_VERTEX_SHADER_PATH: &str = "./src/assets/shadow.vert";
+
+const SPOT_SHADOW_NEAR: f32 = 0.1;
+/// Used for spot lights without a range.
+const SPOT_SHADOW_FAR: f32 = 100.0;
+
+/// A shadow map to render, and what the shaders need to look it up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowMap {
+    /// From world space to the shadow map's clip space.
+    pub view_projection: Mat4,
+    /// View depth of the camera up to which this cascade is used, infinite for spot lights.
+    pub split_depth: f32,
+}
+
+/// The shadow maps `light` needs to cast shadows seen from `camera`, none if it doesn't.
+pub fn shadow_maps(light: &RenderLight, camera: &RenderCamera) -> Vec<ShadowMap> {
+    if light.shadows.is_none() {
+        return Vec::new();
+    }
+
+    match light.kind {
+        LightKind::Directional => cascades(light.direction, camera),
+        LightKind::Spot {
+            range,
+            outer_cone_angle,
+            ..
+        } => vec![ShadowMap {
+            view_projection: perspective_wgpu_dx(
+                outer_cone_angle * 2.0,
+                1.0,
+                SPOT_SHADOW_NEAR,
+                range.unwrap_or(SPOT_SHADOW_FAR),
+            ) * look_along(light.position, light.direction),
+            split_depth: f32::INFINITY,
+        }],
+        // Would need six maps in a cube
+        LightKind::Point { .. } => Vec::new(),
+    }
+}
+
+/// The far ends of the camera frustum slices each cascade covers, in view depth.
+pub fn cascade_splits(z_near: f32, z_far: f32) -> [f32; CASCADE_COUNT] {
+    let mut splits = [z_far; CASCADE_COUNT];
+    for (index, split) in splits.iter_mut().enumerate() {
+        let fraction = (index + 1) as f32 / CASCADE_COUNT as f32;
+        let logarithmic = z_near * (z_far / z_near).powf(fraction);
+        let uniform = z_near + (z_far - z_near) * fraction;
+        *split = CASCADE_SPLIT_LAMBDA * logarithmic + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform;
+    }
+
+    splits
+}
+
+fn cascades(direction: Vec3, camera: &RenderCamera) -> Vec<ShadowMap> {
+    let inverse_view_projection = camera.view_projection().inversed();
+    let mut near = camera.z_near;
+
+    cascade_splits(camera.z_near, camera.z_far)
+        .iter()
+        .map(|&far| {
+            let corners = frustum_slice_corners(camera, inverse_view_projection, near, far);
+            near = far;
+
+            ShadowMap {
+                view_projection: fit_cascade(direction, &corners),
+                split_depth: far,
+            }
+        })
+        .collect()
+}
+
+/// World space corners of the part of the camera frustum between the view depths `near` and
+/// `far`.
+fn frustum_slice_corners(
+    camera: &RenderCamera,
+    inverse_view_projection: Mat4,
+    near: f32,
+    far: f32,
+) -> [Vec3; 8] {
+    let mut corners = [Vec3::zero(); 8];
+    for (index, corner) in corners.iter_mut().enumerate() {
+        let x = if index & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if index & 2 == 0 { -1.0 } else { 1.0 };
+        let depth = if index & 4 == 0 { near } else { far };
+
+        let world = inverse_view_projection * Vec4::new(x, y, ndc_depth(camera, depth), 1.0);
+        *corner = world.xyz() / world.w;
+    }
+
+    corners
+}
+
+/// Where `perspective_wgpu_dx` puts a view depth in the 0..1 depth range.
+fn ndc_depth(camera: &RenderCamera, view_depth: f32) -> f32 {
+    camera.z_far * (view_depth - camera.z_near) / (view_depth * (camera.z_far - camera.z_near))
+}
+
+/// An orthographic projection along `direction` enclosing `corners`. It encloses their bounding
+/// sphere rather than the corners themselves, so it doesn't change size as the camera turns,
+/// and moves in whole texels, so shadow edges don't crawl as the camera moves.
+fn fit_cascade(direction: Vec3, corners: &[Vec3; 8]) -> Mat4 {
+    let center = corners
+        .iter()
+        .fold(Vec3::zero(), |sum, corner| sum + *corner)
+        / 8.0;
+    let radius = corners
+        .iter()
+        .map(|corner| (*corner - center).mag())
+        .fold(0.0, f32::max);
+    // Rounded up so float noise doesn't change the size from frame to frame either
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let view = look_along(center - direction * (radius + CASTER_DISTANCE), direction);
+    let projection = orthographic_wgpu_dx(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_DISTANCE,
+    );
+    let view_projection = projection * view;
+
+    // Shift so the world origin lands on a texel corner, snapping the whole map to texels
+    let texels_per_unit = SHADOW_MAP_SIZE as f32 / 2.0;
+    let origin = view_projection * Vec4::new(0.0, 0.0, 0.0, 1.0);
+    let snapped = Vec2::new(
+        (origin.x * texels_per_unit).round(),
+        (origin.y * texels_per_unit).round(),
+    ) / texels_per_unit;
+
+    Mat4::from_translation(Vec3::new(snapped.x - origin.x, snapped.y - origin.y, 0.0))
+        * view_projection
+}
+
+fn look_along(eye: Vec3, direction: Vec3) -> Mat4 {
+    // Any up works as long as it isn't parallel to the direction
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+
+    Mat4::look_at(eye, eye + direction, up)
+}
+
+/// Matches `ShadowUniforms` in `src/assets/shadow.vert`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ShadowUniforms {
+    light_view_proj: Mat4,
+}
+
+unsafe impl bytemuck::Pod for ShadowUniforms {}
+unsafe impl bytemuck::Zeroable for ShadowUniforms {}
+
+/// Renders the depth of the scene as seen by each light into the layers of a texture array,
+/// which the main pass samples through the bind group of `include/shadows.glsl`.
+pub struct ShadowPass {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    /// One per layer, to render into.
+    layer_views: Vec<wgpu::TextureView>,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// What the uniform bind group was made for, a reloaded shader has to keep to it.
+    bind_group_layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    mesh_layout: MeshLayout,
+    /// Model matrices of all meshes, visible or not, batch after batch.
+    instance_buffer: InstanceBuffer,
+    /// The `ShadowUniforms` of every layer, each in a block of its own picked by dynamic offset.
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    /// The view projection of every layer, for looking up shadows.
+    view_projections_buffer: wgpu::Buffer,
+    /// What the main pass binds to sample the shadow maps.
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowPass {
+    /// `bind_group_layout` is the layout of the shadow set of the main pipeline.
+    pub(super) fn new(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_maps"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth: MAX_SHADOW_MAPS as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_MAP_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let layer_views = (0..MAX_SHADOW_MAPS as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_map_layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_maps"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering blends the results of comparing the four nearest texels
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let view_projections_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow View Projections Buffer"),
+            size: (MAX_SHADOW_MAPS * std::mem::size_of::<Mat4>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&array_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(view_projections_buffer.slice(..)),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        });
+
+        let vs_spirv = include_bytes!("../assets/shadow.vert.spv");
+        let vs_reflection =
+            reflection::reflect(vs_spirv).expect("invalid shadow vertex shader SPIR-V?");
+        let bind_group_layout_entries =
+            bind_group_layouts(&vs_reflection).expect("invalid shadow vertex shader bindings?");
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &bind_group_layout_entries[0],
+                label: Some("shadow_uniform_bind_group_layout"),
+            });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: MAX_SHADOW_MAPS as wgpu::BufferAddress * uniform_stride::<ShadowUniforms>(),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                // One layer's block, the dynamic offset picks which
+                resource: wgpu::BindingResource::Buffer(
+                    uniform_buffer
+                        .slice(..std::mem::size_of::<ShadowUniforms>() as wgpu::BufferAddress),
+                ),
+            }],
+            label: Some("shadow_uniform_bind_group"),
+        });
+
+        let vs_module = device.create_shader_module(wgpu::util::make_spirv(vs_spirv));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mesh_layout = MeshLayout::new(&vs_reflection)
+            .expect("meshes don't have what the shadow vertex shader needs?");
+        let pipeline = create_shadow_pipeline(device, &pipeline_layout, &vs_module, &mesh_layout);
+
+        Self {
+            texture,
+            layer_views,
+            pipeline,
+            pipeline_layout,
+            bind_group_layout_entries,
+            mesh_layout,
+            instance_buffer: InstanceBuffer::new(device),
+            uniform_buffer,
+            uniform_bind_group,
+            view_projections_buffer,
+            bind_group,
+        }
+    }
+
+    /// Rebuilds the pipeline with a recompiled `shadow.vert`, unless its bindings changed.
+    pub(super) fn reload_vertex_shader(
+        &mut self,
+        device: &wgpu::Device,
+        spirv: &[u8],
+    ) -> Result<()> {
+        let reloaded = reflection::reflect(spirv)?;
+        if bind_group_layouts(&reloaded)? != self.bind_group_layout_entries {
+            bail!("its bindings changed, restart to pick them up");
+        }
+        let mesh_layout = MeshLayout::new(&reloaded)?;
+
+        let vs_module = device.create_shader_module(wgpu::util::make_spirv(spirv));
+        self.pipeline =
+            create_shadow_pipeline(device, &self.pipeline_layout, &vs_module, &mesh_layout);
+        self.mesh_layout = mesh_layout;
+        Ok(())
+    }
+
+    /// Records rendering every mesh into the layer of every shadow map, `shadow_maps` being
+    /// ordered by layer like `pack_lights` returns them.
+    pub(super) fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        shadow_maps: &[ShadowMap],
+        meshes: &[RenderMesh],
+        models: &HashMap<PathBuf, Vec<GpuMesh>>,
+    ) {
+        let shadow_maps = &shadow_maps[..shadow_maps.len().min(MAX_SHADOW_MAPS)];
+        if shadow_maps.is_empty() {
+            return;
+        }
+
+        let view_projections = shadow_maps
+            .iter()
+            .map(|shadow_map| *shadow_map.view_projection.as_array())
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.view_projections_buffer,
+            0,
+            bytemuck::cast_slice(&view_projections),
+        );
+
+        let uniforms = shadow_maps
+            .iter()
+            .map(|shadow_map| ShadowUniforms {
+                light_view_proj: shadow_map.view_projection,
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.uniform_buffer, 0, &uniform_blocks(&uniforms));
+
+        // Materials don't matter for depth, so batches only split by model
+        let (batches, transforms) = instancing::batch(meshes, false);
+        self.instance_buffer.write(device, queue, &transforms);
+
+        // Every layer is an attachment of its own, so it gets a render pass of its own
+        for (index, layer_view) in self.layer_views.iter().take(shadow_maps.len()).enumerate() {
+            let uniforms_offset = index as wgpu::BufferAddress * uniform_stride::<ShadowUniforms>();
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: layer_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(
+                0,
+                &self.uniform_bind_group,
+                &[uniforms_offset as wgpu::DynamicOffset],
+            );
+            for batch in &batches {
+                for mesh in models.get(batch.model).into_iter().flatten() {
+                    self.mesh_layout.set_buffers(
+                        &mut render_pass,
+                        mesh,
+                        &self.instance_buffer.buffer,
+                    );
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..));
+                    render_pass.draw_indexed(0..mesh.num_indices, 0, batch.instances.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Layouts of the shadow pipeline's bind groups. The uniforms are bound at a different offset
+/// for every layer.
+fn bind_group_layouts(
+    vs_reflection: &reflection::ShaderReflection,
+) -> Result<Vec<Vec<wgpu::BindGroupLayoutEntry>>> {
+    let mut bind_group_layout_entries = reflection::bind_group_layouts(&[vs_reflection])?;
+    let uniforms = bind_group_layout_entries
+        .first_mut()
+        .context("No shadow uniforms")?;
+    reflection::use_dynamic_offset(uniforms, 0)?;
+
+    Ok(bind_group_layout_entries)
+}
+
+fn create_shadow_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    mesh_layout: &MeshLayout,
+) -> wgpu::RenderPipeline {
+    let vertex_buffers = mesh_layout.vertex_buffers();
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        // Only depth is written
+        fragment_stage: None,
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+            clamp_depth: false,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: SHADOW_MAP_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilStateDescriptor {
+                front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &vertex_buffers,
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::light::ShadowSettings;
+
+    fn camera() -> RenderCamera {
+        let position = Vec3::new(3.0, 2.0, 10.0);
+        RenderCamera {
+            position,
+            view: Mat4::look_at(position, Vec3::new(0.0, 0.0, 0.0), Vec3::unit_y()),
+            projection: perspective_wgpu_dx(1.0, 16.0 / 9.0, 0.1, 100.0),
+            z_near: 0.1,
+            z_far: 100.0,
+        }
+    }
+
+    fn light(kind: LightKind, direction: Vec3) -> RenderLight {
+        RenderLight {
+            kind,
+            position: Vec3::new(0.0, 10.0, 0.0),
+            direction: direction.normalized(),
+            color: Vec3::one(),
+            intensity: 1.0,
+            shadows: Some(ShadowSettings::default()),
+        }
+    }
+
+    fn in_clip_volume(view_projection: Mat4, point: Vec3) -> bool {
+        let clip = view_projection * point.into_homogeneous_point();
+        let ndc = clip.xyz() / clip.w;
+        let epsilon = 1e-4;
+
+        ndc.x.abs() <= 1.0 + epsilon
+            && ndc.y.abs() <= 1.0 + epsilon
+            && ndc.z >= -epsilon
+            && ndc.z <= 1.0 + epsilon
+    }
+
+    #[test]
+    fn splits_grow_towards_the_far_plane() {
+        let splits = cascade_splits(0.1, 100.0);
+
+        assert!(splits[0] > 0.1);
+        assert!((splits[CASCADE_COUNT - 1] - 100.0).abs() < 1e-3);
+        for pair in splits.windows(2) {
+            assert!(pair[1] - pair[0] > 0.0);
+        }
+        // Near cascades cover less so they get more detail
+        assert!(splits[1] - splits[0] > splits[0] - 0.1);
+    }
+
+    #[test]
+    fn cascades_enclose_their_frustum_slice() {
+        let camera = camera();
+        let sun = light(LightKind::Directional, Vec3::new(-0.3, -1.0, -0.5));
+        let maps = shadow_maps(&sun, &camera);
+        assert_eq!(maps.len(), CASCADE_COUNT);
+
+        let inverse_view_projection = camera.view_projection().inversed();
+        let mut near = camera.z_near;
+        for map in &maps {
+            let corners =
+                frustum_slice_corners(&camera, inverse_view_projection, near, map.split_depth);
+            for corner in corners.iter() {
+                assert!(in_clip_volume(map.view_projection, *corner));
+            }
+            near = map.split_depth;
+        }
+    }
+
+    #[test]
+    fn frustum_slice_corners_are_at_their_view_depth() {
+        let camera = camera();
+        let corners =
+            frustum_slice_corners(&camera, camera.view_projection().inversed(), 1.0, 20.0);
+
+        for (index, corner) in corners.iter().enumerate() {
+            let view_depth = -(camera.view * corner.into_homogeneous_point()).z;
+            let expected = if index < 4 { 1.0 } else { 20.0 };
+            assert!((view_depth - expected).abs() < 1e-2 * expected);
+        }
+    }
+
+    #[test]
+    fn cascades_move_in_whole_texels() {
+        let sun = light(LightKind::Directional, Vec3::new(-0.3, -1.0, -0.5));
+        let mut moved = camera();
+        moved.position += Vec3::new(0.013, 0.0, 0.007);
+        moved.view = Mat4::look_at(moved.position, Vec3::new(0.013, 0.0, 0.007), Vec3::unit_y());
+
+        let before = shadow_maps(&sun, &camera())[0].view_projection;
+        let after = shadow_maps(&sun, &moved)[0].view_projection;
+
+        // Same size, so any point moves by a whole number of texels between the two
+        let point = Vec4::new(1.0, 2.0, 3.0, 1.0);
+        let texels = ((after * point) - (before * point)).xy() * (SHADOW_MAP_SIZE as f32 / 2.0);
+        assert!((texels.x - texels.x.round()).abs() < 1e-2);
+        assert!((texels.y - texels.y.round()).abs() < 1e-2);
+    }
+
+    #[test]
+    fn spot_lights_look_down_their_cone() {
+        let spot = light(
+            LightKind::Spot {
+                range: Some(20.0),
+                inner_cone_angle: 0.3,
+                outer_cone_angle: 0.5,
+            },
+            Vec3::new(0.0, -1.0, 0.0),
+        );
+        let maps = shadow_maps(&spot, &camera());
+        assert_eq!(maps.len(), 1);
+
+        let center = maps[0].view_projection * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+        assert!(in_clip_volume(
+            maps[0].view_projection,
+            Vec3::new(0.0, 0.0, 0.0)
+        ));
+        // Just inside and outside the outer cone, 10 below the light
+        assert!(in_clip_volume(
+            maps[0].view_projection,
+            Vec3::new(10.0 * 0.49f32.tan(), 0.0, 0.0)
+        ));
+        assert!(!in_clip_volume(
+            maps[0].view_projection,
+            Vec3::new(10.0 * 0.51f32.tan(), 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn only_shadow_casting_lights_get_maps() {
+        let mut sun = light(LightKind::Directional, Vec3::new(0.0, -1.0, 0.0));
+        sun.shadows = None;
+        assert!(shadow_maps(&sun, &camera()).is_empty());
+
+        let point = light(LightKind::Point { range: None }, Vec3::new(0.0, -1.0, 0.0));
+        assert!(shadow_maps(&point, &camera()).is_empty());
+    }
+}
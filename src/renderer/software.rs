@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use image::{GenericImageView, Rgba, RgbaImage};
+use ultraviolet::*;
+
+use super::scene::RenderScene;
+use super::RenderBackend;
+use crate::gltf::Mesh;
+use crate::texture::SamplerSettings;
+
+/// Draws like the wgpu renderer but on the CPU, for testing rendering without a GPU. Triangles
+/// are depth tested, back faces culled and textures sampled perspective correct with the
+/// nearest texel. Surfaces are unlit, they show their diffuse texture as is.
+pub struct SoftwareRenderer {
+    framebuffer: Framebuffer,
+    models: HashMap<PathBuf, Vec<SoftwareMesh>>,
+    diffuse_textures: HashMap<PathBuf, RgbaImage>,
+    /// White, for meshes without a material or whose texture isn't loaded.
+    default_diffuse_texture: RgbaImage,
+}
+
+/// A mesh unpacked into what the rasterizer reads.
+struct SoftwareMesh {
+    positions: Vec<Vec3>,
+    tex_coords: Vec<Vec2>,
+    indices: Vec<u32>,
+}
+
+/// Color and depth of the frame being drawn.
+struct Framebuffer {
+    color: RgbaImage,
+    depth: Vec<f32>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ClipVertex {
+    position: Vec4,
+    tex_coords: Vec2,
+}
+
+impl ClipVertex {
+    fn lerp(self, other: ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position + (other.position - self.position) * t,
+            tex_coords: self.tex_coords + (other.tex_coords - self.tex_coords) * t,
+        }
+    }
+}
+
+/// A vertex after the perspective divide, in pixels with y pointing down.
+#[derive(Debug, Copy, Clone)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    depth: f32,
+    inverse_w: f32,
+    /// Texture coordinates divided by w, so they can be interpolated linearly on screen.
+    tex_coords_over_w: Vec2,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            framebuffer: Framebuffer {
+                color: RgbaImage::new(width, height),
+                depth: vec![1.0; (width * height) as usize],
+            },
+            models: HashMap::new(),
+            diffuse_textures: HashMap::new(),
+            default_diffuse_texture: RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 255])),
+        }
+    }
+}
+
+impl SoftwareMesh {
+    fn new(mesh: &Mesh) -> Result<Self> {
+        let positions = read_floats(&mesh.positions)
+            .chunks_exact(3)
+            .map(|position| Vec3::new(position[0], position[1], position[2]))
+            .collect::<Vec<_>>();
+        let tex_coords = read_floats(&mesh.texture_coordinates)
+            .chunks_exact(2)
+            .map(|tex_coords| Vec2::new(tex_coords[0], tex_coords[1]))
+            .collect::<Vec<_>>();
+        // Indices are 16 bit, like the wgpu renderer's index buffer
+        let indices = mesh
+            .indices
+            .chunks_exact(2)
+            .map(|index| u32::from(u16::from_le_bytes([index[0], index[1]])))
+            .collect::<Vec<_>>();
+
+        if tex_coords.len() != positions.len() {
+            bail!(
+                "Mesh has {} positions but {} texture coordinates",
+                positions.len(),
+                tex_coords.len()
+            );
+        }
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= positions.len())
+        {
+            bail!(
+                "Mesh index {} is out of range of its {} vertices",
+                index,
+                positions.len()
+            );
+        }
+
+        Ok(Self {
+            positions,
+            tex_coords,
+            indices,
+        })
+    }
+}
+
+impl Framebuffer {
+    fn clear(&mut self) {
+        // The same color the wgpu renderer clears to, encoded for the sRGB swap chain
+        let clear_color = Rgba([
+            srgb_byte(0.1),
+            srgb_byte(0.2),
+            srgb_byte(0.3),
+            srgb_byte(1.0),
+        ]);
+        for pixel in self.color.pixels_mut() {
+            *pixel = clear_color;
+        }
+        for depth in &mut self.depth {
+            *depth = 1.0;
+        }
+    }
+
+    fn draw_triangle(&mut self, triangle: [ClipVertex; 3], texture: &RgbaImage) {
+        let polygon = clip_to_near_plane(&triangle);
+
+        for index in 2..polygon.len() {
+            let screen_triangle = [
+                self.to_screen(polygon[0]),
+                self.to_screen(polygon[index - 1]),
+                self.to_screen(polygon[index]),
+            ];
+            self.rasterize(screen_triangle, texture);
+        }
+    }
+
+    fn to_screen(&self, vertex: ClipVertex) -> ScreenVertex {
+        let inverse_w = 1.0 / vertex.position.w;
+        let (width, height) = self.color.dimensions();
+
+        ScreenVertex {
+            x: (vertex.position.x * inverse_w * 0.5 + 0.5) * width as f32,
+            y: (0.5 - vertex.position.y * inverse_w * 0.5) * height as f32,
+            depth: vertex.position.z * inverse_w,
+            inverse_w,
+            tex_coords_over_w: vertex.tex_coords * inverse_w,
+        }
+    }
+
+    fn rasterize(&mut self, [a, b, c]: [ScreenVertex; 3], texture: &RgbaImage) {
+        // y points down on screen, so triangles counter-clockwise on the GPU are clockwise here
+        let area = edge(a, b, c.x, c.y);
+        if area >= 0.0 {
+            return;
+        }
+
+        let (width, height) = self.color.dimensions();
+        let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+        let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+        let max_x = (a.x.max(b.x).max(c.x).ceil().max(0.0) as u32).min(width);
+        let max_y = (a.y.max(b.y).max(c.y).ceil().max(0.0) as u32).min(height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (center_x, center_y) = (x as f32 + 0.5, y as f32 + 0.5);
+                let weight_a = edge(b, c, center_x, center_y) / area;
+                let weight_b = edge(c, a, center_x, center_y) / area;
+                let weight_c = edge(a, b, center_x, center_y) / area;
+                if weight_a < 0.0 || weight_b < 0.0 || weight_c < 0.0 {
+                    continue;
+                }
+
+                let depth = weight_a * a.depth + weight_b * b.depth + weight_c * c.depth;
+                let depth_index = (y * width + x) as usize;
+                if depth > 1.0 || depth >= self.depth[depth_index] {
+                    continue;
+                }
+
+                let inverse_w =
+                    weight_a * a.inverse_w + weight_b * b.inverse_w + weight_c * c.inverse_w;
+                let tex_coords = (a.tex_coords_over_w * weight_a
+                    + b.tex_coords_over_w * weight_b
+                    + c.tex_coords_over_w * weight_c)
+                    / inverse_w;
+
+                self.depth[depth_index] = depth;
+                self.color.put_pixel(x, y, sample(texture, tex_coords));
+            }
+        }
+    }
+}
+
+/// Nearest texel, clamped to the edge like the wgpu sampler.
+fn sample(texture: &RgbaImage, tex_coords: Vec2) -> Rgba<u8> {
+    let (width, height) = texture.dimensions();
+    let x = (tex_coords.x * width as f32).floor().max(0.0) as u32;
+    let y = (tex_coords.y * height as f32).floor().max(0.0) as u32;
+
+    *texture.get_pixel(x.min(width - 1), y.min(height - 1))
+}
+
+impl RenderBackend for SoftwareRenderer {
+    fn load_model(&mut self, path: &Path, meshes: &[Mesh]) -> Result<()> {
+        let meshes = meshes
+            .iter()
+            .map(SoftwareMesh::new)
+            .collect::<Result<Vec<_>>>()?;
+        self.models.insert(path.to_path_buf(), meshes);
+        Ok(())
+    }
+
+    /// Textures are always sampled nearest and clamped, without mip maps, whatever `_sampler`
+    /// says.
+    fn load_diffuse_texture(
+        &mut self,
+        path: &Path,
+        image: &image::DynamicImage,
+        _sampler: &SamplerSettings,
+    ) -> Result<()> {
+        if image.width() == 0 || image.height() == 0 {
+            bail!("Diffuse texture is empty");
+        }
+
+        self.diffuse_textures
+            .insert(path.to_path_buf(), image.to_rgba());
+        Ok(())
+    }
+
+    fn render(&mut self, scene: &RenderScene) {
+        self.framebuffer.clear();
+
+        let camera = match scene.camera() {
+            Some(camera) => camera,
+            None => return,
+        };
+
+        let diffuse_textures = &self.diffuse_textures;
+        for mesh in scene.meshes.iter().filter(|mesh| mesh.visible) {
+            let model_view_projection = camera.view_projection() * mesh.transform;
+            let texture = mesh
+                .material
+                .as_ref()
+                .and_then(|material| diffuse_textures.get(&material.diffuse_texture))
+                .unwrap_or(&self.default_diffuse_texture);
+
+            for model_mesh in self.models.get(&mesh.model).into_iter().flatten() {
+                for triangle in model_mesh.indices.chunks_exact(3) {
+                    let vertex = |index: u32| ClipVertex {
+                        position: model_view_projection
+                            * model_mesh.positions[index as usize].into_homogeneous_point(),
+                        tex_coords: model_mesh.tex_coords[index as usize],
+                    };
+                    let triangle = [
+                        vertex(triangle[0]),
+                        vertex(triangle[1]),
+                        vertex(triangle[2]),
+                    ];
+
+                    self.framebuffer.draw_triangle(triangle, texture);
+                }
+            }
+        }
+    }
+
+    fn capture(&mut self, scene: &RenderScene) -> Result<RgbaImage> {
+        self.render(scene);
+        Ok(self.framebuffer.color.clone())
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `point`.
+fn edge(a: ScreenVertex, b: ScreenVertex, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+/// Cuts off the part of the triangle in front of the near plane, where depth would be below 0
+/// and w can be 0 or flip sign. Returns a convex polygon with up to 4 corners.
+fn clip_to_near_plane(triangle: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+    let mut polygon = Vec::with_capacity(4);
+
+    for index in 0..3 {
+        let current = triangle[index];
+        let next = triangle[(index + 1) % 3];
+        let current_inside = current.position.z >= 0.0;
+        let next_inside = next.position.z >= 0.0;
+
+        if current_inside {
+            polygon.push(current);
+        }
+        if current_inside != next_inside {
+            let t = current.position.z / (current.position.z - next.position.z);
+            polygon.push(current.lerp(next, t));
+        }
+    }
+
+    polygon
+}
+
+fn read_floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|float| f32::from_le_bytes([float[0], float[1], float[2], float[3]]))
+        .collect()
+}
+
+fn srgb_byte(linear: f32) -> u8 {
+    let srgb = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::components::material::Material;
+    use crate::renderer::tests::{assert_matches_golden_image, capture_test_scene};
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const MODEL: &str = "test.gltf";
+    const TEXTURE: &str = "test.png";
+
+    fn mesh(positions: &[[f32; 3]], tex_coords: &[[f32; 2]], indices: &[u16]) -> Mesh {
+        let bytes = |floats: Vec<f32>| {
+            floats
+                .iter()
+                .flat_map(|float| float.to_le_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        Mesh {
+            positions: bytes(positions.iter().flatten().copied().collect()),
+            normals: bytes(vec![0.0; positions.len() * 3]),
+            texture_coordinates: bytes(tex_coords.iter().flatten().copied().collect()),
+            indices: indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes().to_vec())
+                .collect(),
+            ..Mesh::default()
+        }
+    }
+
+    /// Left half red, right half blue, or the top and bottom halves when `vertical`.
+    fn two_color_texture(vertical: bool) -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| {
+            match if vertical { y } else { x } < 2 {
+                true => RED,
+                false => BLUE,
+            }
+        }))
+    }
+
+    /// A square facing the camera at depth `z`, looking down -z from the origin.
+    fn square(z: f32) -> ([[f32; 3]; 4], [[f32; 2]; 4], [u16; 6]) {
+        (
+            [
+                [-1.0, -1.0, z],
+                [1.0, -1.0, z],
+                [1.0, 1.0, z],
+                [-1.0, 1.0, z],
+            ],
+            [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]],
+            [0, 1, 2, 0, 2, 3],
+        )
+    }
+
+    /// A renderer with `mesh` and `texture` loaded as what `scene` draws.
+    fn renderer(
+        width: u32,
+        height: u32,
+        mesh: &Mesh,
+        texture: &image::DynamicImage,
+    ) -> Result<SoftwareRenderer> {
+        let mut renderer = SoftwareRenderer::new(width, height);
+        renderer.load_model(Path::new(MODEL), std::slice::from_ref(mesh))?;
+        renderer.load_diffuse_texture(Path::new(TEXTURE), texture, &SamplerSettings::default())?;
+        Ok(renderer)
+    }
+
+    /// Draws the mesh once, looking down -z from the origin.
+    fn scene() -> RenderScene {
+        let mut scene = RenderScene::single_mesh(
+            PathBuf::from(MODEL),
+            projection::rh_yup::perspective_wgpu_dx(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0),
+        );
+        scene.meshes[0].material = Some(Material::new(PathBuf::from(TEXTURE)));
+        scene
+    }
+
+    #[test]
+    fn draws_triangles_over_clear_color() {
+        let (positions, tex_coords, indices) = square(-4.0);
+        let mut renderer = renderer(
+            16,
+            16,
+            &mesh(&positions, &tex_coords, &indices),
+            &two_color_texture(false),
+        )
+        .unwrap();
+
+        let frame = renderer.capture(&scene()).unwrap();
+
+        // The square covers the middle half of the screen
+        assert_eq!(*frame.get_pixel(6, 8), RED);
+        assert_eq!(*frame.get_pixel(9, 8), BLUE);
+        assert_ne!(*frame.get_pixel(1, 1), RED);
+        assert_ne!(*frame.get_pixel(1, 1), BLUE);
+    }
+
+    #[test]
+    fn back_faces_are_culled() {
+        let (positions, tex_coords, _) = square(-4.0);
+        let mut renderer = renderer(
+            16,
+            16,
+            &mesh(&positions, &tex_coords, &[0, 2, 1, 0, 3, 2]),
+            &two_color_texture(false),
+        )
+        .unwrap();
+
+        let frame = renderer.capture(&scene()).unwrap();
+
+        assert!(frame.pixels().all(|pixel| *pixel != RED && *pixel != BLUE));
+    }
+
+    #[test]
+    fn nearer_triangles_win_the_depth_test() {
+        let (near_positions, _, _) = square(-2.0);
+        let (far_positions, _, _) = square(-4.0);
+        // Red near square drawn after the far one, then again in the other order
+        let mut positions = far_positions.to_vec();
+        positions.extend_from_slice(&near_positions);
+        let tex_coords = [
+            [0.9, 0.0],
+            [0.9, 0.0],
+            [0.9, 0.0],
+            [0.9, 0.0],
+            [0.1, 0.0],
+            [0.1, 0.0],
+            [0.1, 0.0],
+            [0.1, 0.0],
+        ];
+
+        for indices in &[
+            [0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7],
+            [4, 5, 6, 4, 6, 7, 0, 1, 2, 0, 2, 3],
+        ] {
+            let mut renderer = renderer(
+                16,
+                16,
+                &mesh(&positions, &tex_coords, indices),
+                &two_color_texture(false),
+            )
+            .unwrap();
+
+            let frame = renderer.capture(&scene()).unwrap();
+            assert_eq!(*frame.get_pixel(8, 8), RED);
+        }
+    }
+
+    #[test]
+    fn texture_coordinates_are_perspective_correct() {
+        // A floor receding from z = -1 to z = -3, red up to halfway and blue after. With a 90
+        // degree field of view its near edge is at the bottom of the screen and its far edge
+        // at a third of the way down from the middle.
+        let positions = [
+            [-1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, -1.0, -3.0],
+            [-1.0, -1.0, -3.0],
+        ];
+        let tex_coords = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let mut renderer = renderer(
+            100,
+            100,
+            &mesh(&positions, &tex_coords, &[0, 1, 2, 0, 2, 3]),
+            &two_color_texture(true),
+        )
+        .unwrap();
+
+        let frame = renderer.capture(&scene()).unwrap();
+
+        // At y = -0.6 in clip space the floor is 1 / 0.6 away, a third of the way along it.
+        // Interpolating linearly on screen would put it 60% along, into the blue half.
+        assert_eq!(*frame.get_pixel(50, 80), RED);
+        // Just past halfway along the floor, which projects to y = -0.5
+        assert_eq!(*frame.get_pixel(50, 74), BLUE);
+    }
+
+    #[test]
+    fn triangles_crossing_the_near_plane_are_clipped() {
+        let positions = [
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, 1.0],
+            [1.0, -1.0, -3.0],
+            [-1.0, -1.0, -3.0],
+        ];
+        let tex_coords = [[0.1, 0.0]; 4];
+        let mut renderer = renderer(
+            32,
+            32,
+            &mesh(&positions, &tex_coords, &[0, 1, 2, 0, 2, 3]),
+            &two_color_texture(false),
+        )
+        .unwrap();
+
+        let frame = renderer.capture(&scene()).unwrap();
+
+        // The floor runs behind the camera, what is in front of it still fills the bottom
+        assert_eq!(*frame.get_pixel(16, 31), RED);
+    }
+
+    #[test]
+    fn draws_every_mesh_in_the_scene_with_its_transform() {
+        let (positions, tex_coords, indices) = square(-4.0);
+        let mut renderer = renderer(
+            16,
+            16,
+            &mesh(&positions, &tex_coords, &indices),
+            &two_color_texture(false),
+        )
+        .unwrap();
+
+        let mut scene = scene();
+        let mut moved = scene.meshes[0].clone();
+        moved.transform = Mat4::from_translation(Vec3::new(0.0, 3.0, 0.0));
+        scene.meshes.push(moved);
+        let frame = renderer.capture(&scene).unwrap();
+
+        // The square in the middle, and the moved one at the top of the screen
+        assert_eq!(*frame.get_pixel(6, 8), RED);
+        assert_eq!(*frame.get_pixel(6, 1), RED);
+
+        scene.cameras.clear();
+        let frame = renderer.capture(&scene).unwrap();
+        assert!(frame.pixels().all(|pixel| *pixel != RED && *pixel != BLUE));
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let (positions, tex_coords, _) = square(-4.0);
+        assert!(renderer(
+            8,
+            8,
+            &mesh(&positions, &tex_coords, &[0, 1, 4]),
+            &two_color_texture(false),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn draws_each_model_with_its_own_texture() {
+        let (positions, tex_coords, indices) = square(-4.0);
+        let square = mesh(&positions, &tex_coords, &indices);
+        let mut renderer = renderer(16, 16, &square, &two_color_texture(false)).unwrap();
+        renderer
+            .load_model(Path::new("other.gltf"), &[square])
+            .unwrap();
+        let blue = image::DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, BLUE));
+        renderer
+            .load_diffuse_texture(Path::new("blue.png"), &blue, &SamplerSettings::default())
+            .unwrap();
+
+        let mut scene = scene();
+        let mut other = scene.meshes[0].clone();
+        other.model = PathBuf::from("other.gltf");
+        other.material = Some(Material::new(PathBuf::from("blue.png")));
+        other.transform = Mat4::from_translation(Vec3::new(0.0, 3.0, 0.0));
+        // Models that aren't loaded are skipped, meshes without a texture are white
+        let mut missing = scene.meshes[0].clone();
+        missing.model = PathBuf::from("missing.gltf");
+        let mut untextured = scene.meshes[0].clone();
+        untextured.material = None;
+        untextured.transform = Mat4::from_translation(Vec3::new(0.0, -3.0, 0.0));
+        scene.meshes.extend(vec![other, missing, untextured]);
+        let frame = renderer.capture(&scene).unwrap();
+
+        assert_eq!(*frame.get_pixel(6, 8), RED);
+        assert_eq!(*frame.get_pixel(6, 1), BLUE);
+        assert_eq!(*frame.get_pixel(6, 14), WHITE);
+    }
+
+    #[test]
+    fn render_test_scene_matches_golden_image() {
+        let mut renderer = SoftwareRenderer::new(128, 128);
+        let image = capture_test_scene(&mut renderer);
+
+        assert_matches_golden_image(&image, "./src/assets/golden/render_test_scene_software.png");
+    }
+}
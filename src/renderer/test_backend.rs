@@ -0,0 +1,47 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use super::scene::RenderScene;
+use super::RenderBackend;
+use crate::gltf::Mesh;
+use crate::texture::SamplerSettings;
+
+/// Draws nothing but remembers what it was asked to draw, so tests can check what reaches the
+/// renderer.
+#[derive(Debug, Default)]
+pub struct TestBackend {
+    /// Every scene passed to `render` or `capture`, oldest first.
+    pub frames: Vec<RenderScene>,
+    /// Paths passed to `load_model`, oldest first.
+    pub loaded_models: Vec<PathBuf>,
+    /// Paths passed to `load_diffuse_texture`, oldest first.
+    pub loaded_diffuse_textures: Vec<PathBuf>,
+}
+
+impl RenderBackend for TestBackend {
+    fn load_model(&mut self, path: &Path, _meshes: &[Mesh]) -> Result<()> {
+        self.loaded_models.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn load_diffuse_texture(
+        &mut self,
+        path: &Path,
+        _image: &image::DynamicImage,
+        _sampler: &SamplerSettings,
+    ) -> Result<()> {
+        self.loaded_diffuse_textures.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn render(&mut self, scene: &RenderScene) {
+        self.frames.push(scene.clone());
+    }
+
+    /// A blank 1x1 image, the scene is only recorded.
+    fn capture(&mut self, scene: &RenderScene) -> Result<image::RgbaImage> {
+        self.frames.push(scene.clone());
+        Ok(image::RgbaImage::new(1, 1))
+    }
+}
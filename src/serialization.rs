@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::*;
+use bincode::Options;
+use legion::query::LayoutFilter;
+use legion::serialize::Canon;
+use legion::{Registry, World};
+use serde::de::DeserializeSeed;
+
+use crate::camera::Camera;
+use crate::code::components::{
+    dead::Dead,
+    health::{Armor, Health},
+    hitbox::Hitbox,
+    light::Light,
+    material::Material,
+    model::Model,
+    parent::Parent,
+    position::Position,
+    projectile::Projectile,
+    rotation::Rotation,
+    spawn_point::SpawnPoint,
+    team::Team,
+    weapon::Weapon,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorldFormat {
+    /// Pretty printed entity by entity, for levels authored or diffed by hand.
+    Json,
+    /// Components packed per archetype with bincode, for save games.
+    Binary,
+}
+
+impl WorldFormat {
+    /// `.json` files are `Json`, anything else is `Binary`.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => WorldFormat::Json,
+            _ => WorldFormat::Binary,
+        }
+    }
+}
+
+/// Components that are saved and loaded, anything else on an entity is skipped. The keys end up
+/// in the files, so renaming one breaks existing saves and levels.
+///
+/// Entities are written under random names and every load gives them fresh ids, so create a new
+/// registry for each save or load.
+pub fn component_registry() -> Registry<String> {
+    component_registry_with_canon(Canon::default())
+}
+
+/// `component_registry` with entity names already bound, to know which entity a name loads as.
+pub fn component_registry_with_canon(canon: Canon) -> Registry<String> {
+    let mut registry = Registry::<String>::new(canon);
+
+    registry.register::<Position>("position".to_string());
+    registry.register::<Rotation>("rotation".to_string());
+    registry.register::<Camera>("camera".to_string());
+    registry.register::<Health>("health".to_string());
+    registry.register::<Armor>("armor".to_string());
+    registry.register::<Dead>("dead".to_string());
+    registry.register::<Team>("team".to_string());
+    registry.register::<Hitbox>("hitbox".to_string());
+    registry.register::<Weapon>("weapon".to_string());
+    registry.register::<Projectile>("projectile".to_string());
+    registry.register::<SpawnPoint>("spawn_point".to_string());
+    registry.register::<Parent>("parent".to_string());
+    registry.register::<Model>("model".to_string());
+    registry.register::<Material>("material".to_string());
+    registry.register::<Light>("light".to_string());
+
+    registry
+}
+
+/// Serializes the registered components of entities matching `filter`.
+pub fn save_world<F: LayoutFilter>(
+    world: &World,
+    filter: F,
+    format: WorldFormat,
+) -> Result<Vec<u8>> {
+    let registry = component_registry();
+    let serializable = world.as_serializable(filter, &registry);
+
+    let bytes = match format {
+        WorldFormat::Json => serde_json::to_vec_pretty(&serializable)?,
+        WorldFormat::Binary => bincode::DefaultOptions::new().serialize(&serializable)?,
+    };
+
+    Ok(bytes)
+}
+
+/// Adds the entities in `bytes` to `world` as new entities. Entity references between them,
+/// like a `Projectile`'s owner, are remapped to the new entities.
+pub fn load_world(world: &mut World, bytes: &[u8], format: WorldFormat) -> Result<()> {
+    let registry = component_registry();
+    let seed = registry.as_deserialize_into_world(world);
+
+    match format {
+        WorldFormat::Json => seed.deserialize(&mut serde_json::Deserializer::from_slice(bytes))?,
+        WorldFormat::Binary => seed.deserialize(&mut bincode::Deserializer::from_slice(
+            bytes,
+            bincode::DefaultOptions::new(),
+        ))?,
+    }
+
+    Ok(())
+}
+
+pub fn save_world_to_file<F: LayoutFilter>(world: &World, filter: F, path: &Path) -> Result<()> {
+    let bytes = save_world(world, filter, WorldFormat::from_path(path))?;
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+    }
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn load_world_from_file(world: &mut World, path: &Path) -> Result<()> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    load_world(world, &bytes, WorldFormat::from_path(path))
+        .with_context(|| format!("Failed to load {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::{any, Entity, EntityStore, IntoQuery};
+    use ultraviolet::Vec3;
+
+    fn saved_world() -> World {
+        let mut world = World::default();
+        let shooter = world.push((
+            Vec3::new(1.0, 2.0, 3.0) as Position,
+            Rotation::identity(),
+            Health::new(100.0),
+            Team::Red,
+        ));
+        world.push((
+            Vec3::new(4.0, 5.0, 6.0) as Position,
+            Projectile {
+                owner: shooter,
+                velocity: Vec3::new(0.0, 0.0, -10.0),
+                damage: 25.0,
+                seconds_remaining: 1.0,
+            },
+        ));
+
+        world
+    }
+
+    fn round_trip(format: WorldFormat) -> World {
+        let bytes = save_world(&saved_world(), any(), format).unwrap();
+
+        let mut world = World::default();
+        load_world(&mut world, &bytes, format).unwrap();
+        world
+    }
+
+    fn assert_loaded(world: &World) {
+        let (shooter, health, team) = <(Entity, &Health, &Team)>::query()
+            .iter(world)
+            .map(|(entity, health, team)| (*entity, *health, *team))
+            .next()
+            .expect("Shooter wasn't loaded?");
+        assert_eq!(health.current, 100.0);
+        assert_eq!(team, Team::Red);
+
+        let (position, projectile) = <(&Position, &Projectile)>::query()
+            .iter(world)
+            .next()
+            .expect("Projectile wasn't loaded?");
+        assert_eq!(position.z, 6.0);
+        assert_eq!(projectile.damage, 25.0);
+        assert_eq!(projectile.owner, shooter);
+    }
+
+    #[test]
+    fn json_round_trip_remaps_entities() {
+        assert_loaded(&round_trip(WorldFormat::Json));
+    }
+
+    #[test]
+    fn binary_round_trip_remaps_entities() {
+        assert_loaded(&round_trip(WorldFormat::Binary));
+    }
+
+    #[test]
+    fn loading_twice_creates_separate_entities() {
+        let bytes = save_world(&saved_world(), any(), WorldFormat::Json).unwrap();
+        let mut world = World::default();
+        load_world(&mut world, &bytes, WorldFormat::Json).unwrap();
+        load_world(&mut world, &bytes, WorldFormat::Json).unwrap();
+
+        let mut owners = <&Projectile>::query()
+            .iter(&world)
+            .map(|projectile| projectile.owner)
+            .collect::<Vec<_>>();
+        owners.dedup();
+        assert_eq!(owners.len(), 2);
+        assert!(owners.iter().all(|owner| world.entry_ref(*owner).is_ok()));
+    }
+}
@@ -0,0 +1,74 @@
+//! Compiled SPIR-V stored on disk by a hash of the preprocessed source, so shaders that didn't
+//! change aren't compiled again. Only depends on std and anyhow because `build.rs` includes this
+//! file too.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use anyhow::*;
+
+pub struct ShaderCache {
+    directory: PathBuf,
+}
+
+impl ShaderCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Identifies a shader by its stage and its source with includes and defines already
+    /// applied. The hash may change between Rust versions, which only costs a recompile.
+    pub fn key(stage: &str, source: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        stage.hash(&mut hasher);
+        source.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.spv", key))
+    }
+
+    pub fn get(&self, key: u64) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    pub fn insert(&self, key: u64, spirv: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.directory).with_context(|| {
+            format!("Failed to create shader cache {}", self.directory.display())
+        })?;
+        fs::write(self.path(key), spirv).context("Failed to write cached shader")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_directory::TestDirectory;
+
+    #[test]
+    fn caches_by_stage_and_source() {
+        let directory = TestDirectory::new("shader_cache");
+        // Not there yet, the cache creates it on the first insert
+        let cache = ShaderCache::new(directory.join("cache"));
+
+        let key = ShaderCache::key("fragment", "#version 450\nvoid main() {}\n");
+        assert_eq!(cache.get(key), None);
+
+        cache.insert(key, &[3, 2, 35, 7]).unwrap();
+        assert_eq!(cache.get(key), Some(vec![3, 2, 35, 7]));
+
+        assert_ne!(
+            ShaderCache::key("vertex", "#version 450\nvoid main() {}\n"),
+            key
+        );
+        assert_ne!(
+            ShaderCache::key("fragment", "#version 450\n#define A\nvoid main() {}\n"),
+            key
+        );
+    }
+}
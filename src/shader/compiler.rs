@@ -0,0 +1,83 @@
+//! Compiles GLSL to SPIR-V with shaderc, going through the preprocessor and the cache. `build.rs`
+//! includes this file too.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use super::cache::ShaderCache;
+use super::preprocessor::{preprocess, PreprocessedShader};
+
+/// The stage is taken from the extension.
+pub fn shader_kind(path: &Path) -> Result<shaderc::ShaderKind> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("vert") => Ok(shaderc::ShaderKind::Vertex),
+        Some("frag") => Ok(shaderc::ShaderKind::Fragment),
+        Some("comp") => Ok(shaderc::ShaderKind::Compute),
+        _ => bail!("Unsupported shader: {}", path.display()),
+    }
+}
+
+pub struct ShaderCompiler {
+    /// Only created once something misses the cache, it is expensive to set up.
+    compiler: Option<shaderc::Compiler>,
+    cache: ShaderCache,
+    include_directories: Vec<PathBuf>,
+}
+
+impl ShaderCompiler {
+    pub fn new(cache: ShaderCache, include_directories: Vec<PathBuf>) -> Self {
+        Self {
+            compiler: None,
+            cache,
+            include_directories,
+        }
+    }
+
+    pub fn preprocess(&self, path: &Path, defines: &[String]) -> Result<PreprocessedShader> {
+        preprocess(path, defines, &self.include_directories)
+    }
+
+    pub fn compile(&mut self, path: &Path, defines: &[String]) -> Result<Vec<u8>> {
+        let shader = self.preprocess(path, defines)?;
+        self.compile_preprocessed(path, &shader)
+    }
+
+    /// Compile errors point at the file and line they're in, includes included.
+    pub fn compile_preprocessed(
+        &mut self,
+        path: &Path,
+        shader: &PreprocessedShader,
+    ) -> Result<Vec<u8>> {
+        let kind = shader_kind(path)?;
+        let key = ShaderCache::key(&format!("{:?}", kind), &shader.source);
+        if let Some(spirv) = self.cache.get(key) {
+            return Ok(spirv);
+        }
+
+        if self.compiler.is_none() {
+            self.compiler =
+                Some(shaderc::Compiler::new().context("Unable to create shader compiler")?);
+        }
+        let compiler = self
+            .compiler
+            .as_mut()
+            .expect("shader compiler wasn't created?");
+        let name = path.to_string_lossy();
+        let compiled = match compiler.compile_into_spirv(&shader.source, kind, &name, "main", None)
+        {
+            Ok(compiled) => compiled,
+            Err(shaderc::Error::CompilationError(_, message)) => {
+                bail!("{}", shader.map_diagnostics(&name, &message))
+            }
+            Err(error) => bail!("Failed to compile {}: {}", path.display(), error),
+        };
+
+        let spirv = compiled.as_binary_u8().to_vec();
+        if let Err(error) = self.cache.insert(key, &spirv) {
+            log::warn!("not caching shader {}: {:#}", path.display(), error);
+        }
+
+        Ok(spirv)
+    }
+}
@@ -0,0 +1,51 @@
+pub mod cache;
+#[cfg(feature = "shader-hot-reload")]
+pub mod compiler;
+pub mod preprocessor;
+pub mod reflection;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+/// Shared GLSL headers, searched for `#include`s that aren't next to the including file.
+pub const INCLUDE_DIRECTORY: &str = "./src/assets/include";
+/// Where shaders compiled while the game runs are cached.
+pub const CACHE_DIRECTORY: &str = "./target/shader-cache";
+
+/// Whether `file` is the shader at `path` or one of the files it includes.
+pub fn depends_on(path: &Path, file: &Path) -> bool {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file = canonical(file);
+
+    // A shader with a broken include still depends on itself
+    canonical(path) == file
+        || preprocessor::preprocess(path, &[], &[PathBuf::from(INCLUDE_DIRECTORY)])
+            .map(|shader| {
+                shader
+                    .files
+                    .iter()
+                    .any(|included| canonical(included) == file)
+            })
+            .unwrap_or(false)
+}
+
+/// Compiles a GLSL file to SPIR-V the way `build.rs` does, with `defines` added. Used to pick
+/// up shader edits without rebuilding.
+#[cfg(feature = "shader-hot-reload")]
+pub fn compile_glsl(path: &Path, defines: &[String]) -> Result<Vec<u8>> {
+    compiler::ShaderCompiler::new(
+        cache::ShaderCache::new(CACHE_DIRECTORY),
+        vec![PathBuf::from(INCLUDE_DIRECTORY)],
+    )
+    .compile(path, defines)
+}
+
+#[cfg(not(feature = "shader-hot-reload"))]
+pub fn compile_glsl(path: &Path, _defines: &[String]) -> Result<Vec<u8>> {
+    bail!(
+        "Can't recompile {}, built without the shader-hot-reload feature",
+        path.display()
+    )
+}
@@ -0,0 +1,337 @@
+//! Resolves `#include`s and injects defines before GLSL goes to shaderc. Only depends on std and
+//! anyhow because `build.rs` includes this file too.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+/// GLSL with its includes pasted in, and where each of its lines came from.
+#[derive(Debug, Clone)]
+pub struct PreprocessedShader {
+    pub source: String,
+    /// The shader itself first, then every file it included.
+    pub files: Vec<PathBuf>,
+    /// File index and 1-based line number of every line in `source`.
+    lines: Vec<(usize, usize)>,
+    /// Sets of defines listed with `#pragma permutation` that the shader should also be built
+    /// with.
+    pub permutations: Vec<Vec<String>>,
+}
+
+/// Reads the shader at `path` with `#include "file"` replaced by the file's contents. Includes
+/// are looked up next to the including file first, then in `include_directories`. Every file is
+/// included at most once, so headers don't need include guards. `defines` are written as
+/// `NAME` or `NAME=VALUE` and are added right after `#version`.
+pub fn preprocess(
+    path: &Path,
+    defines: &[String],
+    include_directories: &[PathBuf],
+) -> Result<PreprocessedShader> {
+    let mut shader = PreprocessedShader {
+        source: String::new(),
+        files: Vec::new(),
+        lines: Vec::new(),
+        permutations: Vec::new(),
+    };
+    let root = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read shader {}", path.display()))?;
+    shader.files.push(path.to_path_buf());
+
+    let mut defines_written = false;
+    for (index, line) in root.lines().enumerate() {
+        let location = (0, index + 1);
+
+        if let Some(permutation) = directive(line, "#pragma permutation") {
+            shader.permutations.push(
+                permutation
+                    .split_whitespace()
+                    .map(|define| define.to_string())
+                    .collect(),
+            );
+            continue;
+        }
+
+        shader.include_or_push(line, location, include_directories)?;
+
+        if !defines_written && directive(line, "#version").is_some() {
+            for define in defines {
+                shader.push(&define_line(define), location);
+            }
+            defines_written = true;
+        }
+    }
+
+    // Not much use without a #version, but shaderc will point that out
+    if !defines_written && !defines.is_empty() {
+        let mut source = defines
+            .iter()
+            .map(|define| define_line(define) + "\n")
+            .collect::<String>();
+        source.push_str(&shader.source);
+        shader.source = source;
+        shader.lines.splice(0..0, defines.iter().map(|_| (0, 1)));
+    }
+
+    Ok(shader)
+}
+
+impl PreprocessedShader {
+    fn push(&mut self, line: &str, location: (usize, usize)) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.lines.push(location);
+    }
+
+    fn include_or_push(
+        &mut self,
+        line: &str,
+        location: (usize, usize),
+        include_directories: &[PathBuf],
+    ) -> Result<()> {
+        let include = match directive(line, "#include") {
+            Some(include) => include,
+            None => {
+                self.push(line, location);
+                return Ok(());
+            }
+        };
+
+        let name = include
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .or_else(|| {
+                include
+                    .strip_prefix('<')
+                    .and_then(|name| name.strip_suffix('>'))
+            })
+            .with_context(|| self.error_at(location, "expected #include \"file\""))?;
+
+        let directory = self.files[location.0]
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        let path = std::iter::once(&directory)
+            .chain(include_directories)
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+            .with_context(|| self.error_at(location, &format!("can't find include {}", name)))?;
+
+        // Comparing canonical paths catches the same header reached through different routes
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        let already_included = self
+            .files
+            .iter()
+            .any(|file| fs::canonicalize(file).unwrap_or_else(|_| file.clone()) == canonical);
+        if already_included {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| self.error_at(location, &format!("failed to read {}", name)))?;
+        let included_index = self.files.len();
+        self.files.push(path);
+
+        for (index, included_line) in contents.lines().enumerate() {
+            self.include_or_push(
+                included_line,
+                (included_index, index + 1),
+                include_directories,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn error_at(&self, (file_index, line): (usize, usize), message: &str) -> String {
+        format!(
+            "{}:{}: error: {}",
+            self.files[file_index].display(),
+            line,
+            message
+        )
+    }
+
+    /// Rewrites the `name:line:` locations in a shaderc error, which count lines of `source`,
+    /// to point at the file and line they came from, and quotes the offending line.
+    pub fn map_diagnostics(&self, name: &str, message: &str) -> String {
+        let mut mapped = String::new();
+
+        for message_line in message.lines() {
+            let location = message_line
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .and_then(|rest| {
+                    let (line, rest) = rest.split_at(rest.find(':')?);
+                    Some((line.trim().parse::<usize>().ok()?, rest))
+                });
+
+            match location {
+                Some((line, rest)) if line > 0 && line <= self.lines.len() => {
+                    let (file_index, file_line) = self.lines[line - 1];
+                    let source_line = self.source.lines().nth(line - 1).unwrap_or("");
+                    mapped.push_str(&format!(
+                        "{}:{}{}\n{:>5} | {}\n",
+                        self.files[file_index].display(),
+                        file_line,
+                        rest,
+                        file_line,
+                        source_line.trim_end()
+                    ));
+                }
+                _ => {
+                    mapped.push_str(message_line);
+                    mapped.push('\n');
+                }
+            }
+        }
+
+        mapped.trim_end().to_string()
+    }
+}
+
+/// The rest of `line` if it is the preprocessor directive `name`.
+fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let rest = line.trim_start().strip_prefix(name)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn define_line(define: &str) -> String {
+    match define.find('=') {
+        Some(index) => format!("#define {} {}", &define[..index], &define[index + 1..]),
+        None => format!("#define {}", define),
+    }
+}
+
+/// Where `build.rs` writes the SPIR-V for `path` built with `defines`, e.g.
+/// `shader.frag.HAS_NORMAL_MAP.spv`.
+pub fn spirv_path(path: &Path, defines: &[String]) -> PathBuf {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let permutation = defines
+        .iter()
+        .map(|define| format!(".{}", define.replace('=', "_")))
+        .collect::<String>();
+
+    path.with_extension(format!("{}{}.spv", extension, permutation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_directory::TestDirectory;
+
+    fn shader_directory(name: &str, files: &[(&str, &str)]) -> TestDirectory {
+        let directory = TestDirectory::new(&format!("shader_preprocessor_{}", name));
+        for (file, contents) in files {
+            directory.write(file, contents);
+        }
+        directory
+    }
+
+    #[test]
+    fn includes_each_file_once() {
+        let directory = shader_directory(
+            "includes",
+            &[
+                (
+                    "shader.frag",
+                    "#version 450\n#include \"lighting.glsl\"\n#include <common.glsl>\nvoid main() {}",
+                ),
+                ("lighting.glsl", "#include \"common.glsl\"\nfloat light;"),
+                ("include/common.glsl", "const float PI = 3.14;"),
+            ],
+        );
+
+        let shader = preprocess(
+            &directory.join("shader.frag"),
+            &[],
+            &[directory.join("include")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            shader.source,
+            "#version 450\nconst float PI = 3.14;\nfloat light;\nvoid main() {}\n"
+        );
+        assert_eq!(shader.files.len(), 3);
+    }
+
+    #[test]
+    fn defines_follow_version() {
+        let directory = shader_directory(
+            "defines",
+            &[(
+                "shader.frag",
+                "#version 450\n#pragma permutation HAS_NORMAL_MAP\nvoid main() {}",
+            )],
+        );
+        let path = directory.join("shader.frag");
+        let defines = vec!["HAS_NORMAL_MAP".to_string(), "LIGHT_COUNT=4".to_string()];
+
+        let shader = preprocess(&path, &defines, &[]).unwrap();
+
+        assert_eq!(
+            shader.source,
+            "#version 450\n#define HAS_NORMAL_MAP\n#define LIGHT_COUNT 4\nvoid main() {}\n"
+        );
+        assert_eq!(
+            shader.permutations,
+            vec![vec!["HAS_NORMAL_MAP".to_string()]]
+        );
+        assert_eq!(
+            spirv_path(&path, &shader.permutations[0]),
+            directory.join("shader.frag.HAS_NORMAL_MAP.spv")
+        );
+    }
+
+    #[test]
+    fn diagnostics_point_into_includes() {
+        let directory = shader_directory(
+            "diagnostics",
+            &[
+                (
+                    "shader.frag",
+                    "#version 450\n#include \"lighting.glsl\"\nvoid main() {}",
+                ),
+                ("lighting.glsl", "float light;\nfloat broken = missing;"),
+            ],
+        );
+        let shader = preprocess(&directory.join("shader.frag"), &[], &[]).unwrap();
+
+        let diagnostics = shader.map_diagnostics(
+            "shader.frag",
+            "shader.frag:3: error: 'missing' : undeclared identifier\n1 error generated.",
+        );
+
+        assert_eq!(
+            diagnostics,
+            format!(
+                "{}:2: error: 'missing' : undeclared identifier\n    2 | float broken = missing;\n1 error generated.",
+                directory.join("lighting.glsl").display()
+            )
+        );
+    }
+
+    #[test]
+    fn missing_include_reports_location() {
+        let directory = shader_directory(
+            "missing",
+            &[("shader.vert", "#version 450\n\n#include \"nope.glsl\"")],
+        );
+        let path = directory.join("shader.vert");
+
+        let error = preprocess(&path, &[], &[]).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!("{}:3: error: can't find include nope.glsl", path.display())
+        );
+    }
+}
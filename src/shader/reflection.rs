@@ -0,0 +1,888 @@
+//! Reads the inputs and resource bindings out of compiled SPIR-V, so pipeline layouts can be
+//! derived from the shaders instead of written out to match them.
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+
+use anyhow::*;
+use num_traits::FromPrimitive;
+use spirv_headers::{Decoration, Dim, ExecutionModel, ImageFormat, Op, StorageClass};
+
+const MAGIC_NUMBER: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+/// A `layout(location = ...) in` variable of the shader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderInput {
+    pub name: String,
+    pub location: u32,
+    pub format: wgpu::VertexFormat,
+}
+
+/// A buffer, texture or sampler the shader reads through a bind group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceBinding {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub ty: wgpu::BindingType,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: wgpu::ShaderStage,
+    /// Sorted by location.
+    pub inputs: Vec<ShaderInput>,
+    /// Sorted by set, then binding.
+    pub bindings: Vec<ResourceBinding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarKind {
+    Float,
+    Sint,
+    Uint,
+    Bool,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar {
+        kind: ScalarKind,
+        width: u32,
+    },
+    Vector {
+        component: u32,
+        count: u32,
+    },
+    Matrix {
+        column: u32,
+        count: u32,
+    },
+    Image {
+        sampled_type: u32,
+        dim: Dim,
+        depth: bool,
+        arrayed: bool,
+        multisampled: bool,
+        /// 1 when used with a sampler, 2 for storage images.
+        sampled: u32,
+        format: ImageFormat,
+    },
+    Sampler,
+    SampledImage {
+        image: u32,
+    },
+    Array {
+        element: u32,
+        length: u32,
+    },
+    RuntimeArray,
+    Struct {
+        members: Vec<u32>,
+    },
+    Pointer {
+        pointee: u32,
+    },
+}
+
+/// Decorations on an id or struct member, with their first literal if they have one.
+type Decorations = Vec<(Decoration, Option<u32>)>;
+
+/// The instructions reflection cares about, indexed by result id.
+#[derive(Default)]
+struct Module {
+    execution_model: Option<ExecutionModel>,
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), Decorations>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Id, pointer type and storage class of every global variable.
+    variables: Vec<(u32, u32, StorageClass)>,
+    /// Result of every `OpLoad` and the variable it loaded.
+    loads: HashMap<u32, u32>,
+    /// Result type, image and sampler of every `OpSampledImage`.
+    sampled_images: Vec<(u32, u32, u32)>,
+}
+
+/// Reflects the first entry point of a SPIR-V module.
+pub fn reflect(spirv: &[u8]) -> Result<ShaderReflection> {
+    let chunks = spirv.chunks_exact(4);
+    if !chunks.remainder().is_empty() || spirv.len() < HEADER_WORDS * 4 {
+        bail!(
+            "SPIR-V is {} bytes, not a whole header and words",
+            spirv.len()
+        );
+    }
+
+    let mut words = chunks
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect::<Vec<_>>();
+    if words[0] == MAGIC_NUMBER.swap_bytes() {
+        words.iter_mut().for_each(|word| *word = word.swap_bytes());
+    }
+    if words[0] != MAGIC_NUMBER {
+        bail!("Not SPIR-V, the magic number is {:#010x}", words[0]);
+    }
+
+    let module = Module::parse(&words[HEADER_WORDS..])?;
+    module.reflect()
+}
+
+impl Module {
+    fn parse(mut words: &[u32]) -> Result<Self> {
+        let mut module = Module::default();
+
+        while !words.is_empty() {
+            let word_count = (words[0] >> 16) as usize;
+            if word_count == 0 || word_count > words.len() {
+                bail!("Truncated SPIR-V instruction");
+            }
+            let operands = &words[1..word_count];
+            let operand = |index: usize| {
+                operands
+                    .get(index)
+                    .copied()
+                    .context("SPIR-V instruction is missing operands")
+            };
+
+            match Op::from_u32(words[0] & 0xffff) {
+                Some(Op::EntryPoint) if module.execution_model.is_none() => {
+                    module.execution_model = ExecutionModel::from_u32(operand(0)?);
+                }
+                Some(Op::Name) => {
+                    module
+                        .names
+                        .insert(operand(0)?, literal_string(&operands[1..]));
+                }
+                Some(Op::Decorate) => {
+                    if let Some(decoration) = Decoration::from_u32(operand(1)?) {
+                        module
+                            .decorations
+                            .entry(operand(0)?)
+                            .or_default()
+                            .push((decoration, operands.get(2).copied()));
+                    }
+                }
+                Some(Op::MemberDecorate) => {
+                    if let Some(decoration) = Decoration::from_u32(operand(2)?) {
+                        module
+                            .member_decorations
+                            .entry((operand(0)?, operand(1)?))
+                            .or_default()
+                            .push((decoration, operands.get(3).copied()));
+                    }
+                }
+                Some(Op::TypeBool) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Scalar {
+                            kind: ScalarKind::Bool,
+                            width: 32,
+                        },
+                    );
+                }
+                Some(Op::TypeInt) => {
+                    let kind = match operand(2)? {
+                        0 => ScalarKind::Uint,
+                        _ => ScalarKind::Sint,
+                    };
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Scalar {
+                            kind,
+                            width: operand(1)?,
+                        },
+                    );
+                }
+                Some(Op::TypeFloat) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Scalar {
+                            kind: ScalarKind::Float,
+                            width: operand(1)?,
+                        },
+                    );
+                }
+                Some(Op::TypeVector) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Vector {
+                            component: operand(1)?,
+                            count: operand(2)?,
+                        },
+                    );
+                }
+                Some(Op::TypeMatrix) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Matrix {
+                            column: operand(1)?,
+                            count: operand(2)?,
+                        },
+                    );
+                }
+                Some(Op::TypeImage) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Image {
+                            sampled_type: operand(1)?,
+                            dim: Dim::from_u32(operand(2)?).context("Unknown image dimension")?,
+                            depth: operand(3)? == 1,
+                            arrayed: operand(4)? == 1,
+                            multisampled: operand(5)? == 1,
+                            sampled: operand(6)?,
+                            format: ImageFormat::from_u32(operand(7)?)
+                                .context("Unknown image format")?,
+                        },
+                    );
+                }
+                Some(Op::TypeSampler) => {
+                    module.types.insert(operand(0)?, Type::Sampler);
+                }
+                Some(Op::TypeSampledImage) => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::SampledImage { image: operand(1)? });
+                }
+                Some(Op::TypeArray) => {
+                    let length = operand(2)?;
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Array {
+                            element: operand(1)?,
+                            length: *module
+                                .constants
+                                .get(&length)
+                                .context("Array length isn't a constant")?,
+                        },
+                    );
+                }
+                Some(Op::TypeRuntimeArray) => {
+                    module.types.insert(operand(0)?, Type::RuntimeArray);
+                }
+                Some(Op::TypeStruct) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Struct {
+                            members: operands[1..].to_vec(),
+                        },
+                    );
+                }
+                Some(Op::TypePointer) => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Pointer {
+                            pointee: operand(2)?,
+                        },
+                    );
+                }
+                Some(Op::Constant) => {
+                    module.constants.insert(operand(1)?, operand(2)?);
+                }
+                Some(Op::Variable) => {
+                    if let Some(storage_class) = StorageClass::from_u32(operand(2)?) {
+                        module
+                            .variables
+                            .push((operand(1)?, operand(0)?, storage_class));
+                    }
+                }
+                Some(Op::Load) => {
+                    module.loads.insert(operand(1)?, operand(2)?);
+                }
+                Some(Op::SampledImage) => {
+                    module
+                        .sampled_images
+                        .push((operand(0)?, operand(2)?, operand(3)?));
+                }
+                _ => {}
+            }
+
+            words = &words[word_count..];
+        }
+
+        Ok(module)
+    }
+
+    fn reflect(&self) -> Result<ShaderReflection> {
+        let stage = match self.execution_model {
+            Some(ExecutionModel::Vertex) => wgpu::ShaderStage::VERTEX,
+            Some(ExecutionModel::Fragment) => wgpu::ShaderStage::FRAGMENT,
+            Some(ExecutionModel::GLCompute) => wgpu::ShaderStage::COMPUTE,
+            Some(model) => bail!("Unsupported shader stage {:?}", model),
+            None => bail!("SPIR-V has no entry point"),
+        };
+
+        let mut inputs = Vec::new();
+        let mut bindings = Vec::new();
+
+        for &(id, pointer, storage_class) in &self.variables {
+            let ty = match self.types.get(&pointer) {
+                Some(Type::Pointer { pointee }) => *pointee,
+                _ => bail!("Variable {} isn't a pointer", self.name(id)),
+            };
+
+            match storage_class {
+                StorageClass::Input if !self.has_decoration(id, Decoration::BuiltIn) => {
+                    let location = self
+                        .decoration(id, Decoration::Location)
+                        .with_context(|| format!("Input {} has no location", self.name(id)))?;
+                    inputs.push(ShaderInput {
+                        name: self.name(id),
+                        location,
+                        format: self.vertex_format(ty).with_context(|| {
+                            format!("Input {} has an unsupported type", self.name(id))
+                        })?,
+                    });
+                }
+                StorageClass::Uniform
+                | StorageClass::UniformConstant
+                | StorageClass::StorageBuffer => {
+                    // Uniform blocks are usually declared without an instance name
+                    let name = match self.names.get(&id) {
+                        Some(name) if !name.is_empty() => name.clone(),
+                        _ => self.name(ty),
+                    };
+                    bindings.push(ResourceBinding {
+                        name,
+                        set: self.decoration(id, Decoration::DescriptorSet).unwrap_or(0),
+                        binding: self
+                            .decoration(id, Decoration::Binding)
+                            .with_context(|| format!("{} has no binding", self.name(id)))?,
+                        ty: self
+                            .binding_type(id, ty, storage_class)
+                            .with_context(|| format!("Can't bind {}", self.name(id)))?,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        inputs.sort_by_key(|input| input.location);
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(ShaderReflection {
+            stage,
+            inputs,
+            bindings,
+        })
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names
+            .get(&id)
+            .filter(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("%{}", id))
+    }
+
+    fn decoration(&self, id: u32, decoration: Decoration) -> Option<u32> {
+        find_decoration(self.decorations.get(&id), decoration).flatten()
+    }
+
+    fn has_decoration(&self, id: u32, decoration: Decoration) -> bool {
+        find_decoration(self.decorations.get(&id), decoration).is_some()
+    }
+
+    fn member_decoration(&self, id: u32, member: u32, decoration: Decoration) -> Option<u32> {
+        find_decoration(self.member_decorations.get(&(id, member)), decoration).flatten()
+    }
+
+    fn has_member_decoration(&self, id: u32, member: u32, decoration: Decoration) -> bool {
+        find_decoration(self.member_decorations.get(&(id, member)), decoration).is_some()
+    }
+
+    fn vertex_format(&self, ty: u32) -> Result<wgpu::VertexFormat> {
+        use wgpu::VertexFormat::*;
+
+        let (component, count) = match self.types.get(&ty) {
+            Some(Type::Vector { component, count }) => (*component, *count),
+            Some(Type::Scalar { .. }) => (ty, 1),
+            _ => bail!("Only scalars and vectors can be vertex attributes"),
+        };
+
+        let format = match (self.types.get(&component), count) {
+            (Some(Type::Scalar { kind, width: 32 }), _) => match (kind, count) {
+                (ScalarKind::Float, 1) => Float,
+                (ScalarKind::Float, 2) => Float2,
+                (ScalarKind::Float, 3) => Float3,
+                (ScalarKind::Float, 4) => Float4,
+                (ScalarKind::Sint, 1) => Int,
+                (ScalarKind::Sint, 2) => Int2,
+                (ScalarKind::Sint, 3) => Int3,
+                (ScalarKind::Sint, 4) => Int4,
+                (ScalarKind::Uint, 1) => Uint,
+                (ScalarKind::Uint, 2) => Uint2,
+                (ScalarKind::Uint, 3) => Uint3,
+                (ScalarKind::Uint, 4) => Uint4,
+                _ => bail!("No vertex format for {} {:?}s", count, kind),
+            },
+            _ => bail!("Only 32 bit components are supported"),
+        };
+
+        Ok(format)
+    }
+
+    fn binding_type(
+        &self,
+        variable: u32,
+        ty: u32,
+        storage_class: StorageClass,
+    ) -> Result<wgpu::BindingType> {
+        let binding_type = match self.types.get(&ty) {
+            Some(Type::Struct { members }) => {
+                let storage = storage_class == StorageClass::StorageBuffer
+                    || self.has_decoration(ty, Decoration::BufferBlock);
+                let size = NonZeroU64::new(self.size_of(ty, None)?);
+
+                if storage {
+                    let readonly = self.has_decoration(variable, Decoration::NonWritable)
+                        || (0..members.len() as u32).all(|member| {
+                            self.has_member_decoration(ty, member, Decoration::NonWritable)
+                        });
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: size,
+                        readonly,
+                    }
+                } else {
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: size,
+                    }
+                }
+            }
+            Some(Type::Image {
+                sampled_type,
+                dim,
+                arrayed,
+                multisampled,
+                sampled,
+                format,
+                ..
+            }) => {
+                let dimension = view_dimension(*dim, *arrayed)?;
+
+                if *sampled == 2 {
+                    wgpu::BindingType::StorageTexture {
+                        dimension,
+                        format: storage_texture_format(*format)?,
+                        readonly: self.has_decoration(variable, Decoration::NonWritable),
+                    }
+                } else {
+                    // Depth textures are sampled as floats too
+                    let component_type = match self.types.get(sampled_type) {
+                        Some(Type::Scalar {
+                            kind: ScalarKind::Float,
+                            ..
+                        }) => wgpu::TextureComponentType::Float,
+                        Some(Type::Scalar {
+                            kind: ScalarKind::Sint,
+                            ..
+                        }) => wgpu::TextureComponentType::Sint,
+                        Some(Type::Scalar {
+                            kind: ScalarKind::Uint,
+                            ..
+                        }) => wgpu::TextureComponentType::Uint,
+                        _ => bail!("Texture has an unsupported component type"),
+                    };
+
+                    wgpu::BindingType::SampledTexture {
+                        dimension,
+                        component_type,
+                        multisampled: *multisampled,
+                    }
+                }
+            }
+            Some(Type::Sampler) => wgpu::BindingType::Sampler {
+                comparison: self.is_compared(variable),
+            },
+            Some(Type::SampledImage { .. }) => {
+                bail!("wgpu has no combined image samplers, use a separate texture and sampler")
+            }
+            Some(Type::Array { .. }) | Some(Type::RuntimeArray) => {
+                bail!("Arrays of bindings aren't supported")
+            }
+            _ => bail!("Unsupported binding type"),
+        };
+
+        Ok(binding_type)
+    }
+
+    /// Whether the texture or sampler `variable` is used for depth comparisons, e.g. through
+    /// `sampler2DShadow(t_shadow, s_shadow)`.
+    fn is_compared(&self, variable: u32) -> bool {
+        let is_depth = |ty: Option<&Type>| matches!(ty, Some(Type::Image { depth: true, .. }));
+
+        self.sampled_images
+            .iter()
+            .any(|(result_type, image, sampler)| {
+                let image = self.loads.get(image).copied();
+                let uses_variable =
+                    image == Some(variable) || self.loads.get(sampler) == Some(&variable);
+
+                // glslang marks the combined type as depth even when the texture isn't
+                let combined_image = match self.types.get(result_type) {
+                    Some(Type::SampledImage { image }) => self.types.get(image),
+                    _ => None,
+                };
+                let image_type = image.and_then(|image| self.variable_type(image));
+
+                uses_variable && (is_depth(combined_image) || is_depth(image_type))
+            })
+    }
+
+    fn variable_type(&self, variable: u32) -> Option<&Type> {
+        let (_, pointer, _) = self.variables.iter().find(|(id, _, _)| *id == variable)?;
+        match self.types.get(pointer)? {
+            Type::Pointer { pointee } => self.types.get(pointee),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes of a type laid out in a buffer. `matrix_stride` comes from the struct
+    /// member the matrix is in.
+    fn size_of(&self, ty: u32, matrix_stride: Option<u32>) -> Result<u64> {
+        let size = match self.types.get(&ty) {
+            Some(Type::Scalar { width, .. }) => u64::from(width / 8),
+            Some(Type::Vector { component, count }) => {
+                self.size_of(*component, None)? * u64::from(*count)
+            }
+            Some(Type::Matrix { column, count }) => {
+                let stride = match matrix_stride {
+                    Some(stride) => u64::from(stride),
+                    None => self.size_of(*column, None)?,
+                };
+                stride * u64::from(*count)
+            }
+            Some(Type::Array { element, length }) => {
+                let stride = match self.decoration(ty, Decoration::ArrayStride) {
+                    Some(stride) => u64::from(stride),
+                    None => self.size_of(*element, None)?,
+                };
+                stride * u64::from(*length)
+            }
+            // Sized by whatever buffer ends up bound
+            Some(Type::RuntimeArray) => 0,
+            Some(Type::Struct { members }) => {
+                let mut size = 0;
+                for (member, &member_type) in members.iter().enumerate() {
+                    let member = member as u32;
+                    let offset = self
+                        .member_decoration(ty, member, Decoration::Offset)
+                        .unwrap_or(0);
+                    let matrix_stride =
+                        self.member_decoration(ty, member, Decoration::MatrixStride);
+                    size = size.max(u64::from(offset) + self.size_of(member_type, matrix_stride)?);
+                }
+                size
+            }
+            _ => bail!("Can't size type %{}", ty),
+        };
+
+        Ok(size)
+    }
+}
+
+/// The literal of `decoration` if it is among `decorations`.
+fn find_decoration(
+    decorations: Option<&Decorations>,
+    decoration: Decoration,
+) -> Option<Option<u32>> {
+    decorations?
+        .iter()
+        .find(|(found, _)| *found == decoration)
+        .map(|(_, value)| *value)
+}
+
+fn literal_string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn view_dimension(dim: Dim, arrayed: bool) -> Result<wgpu::TextureViewDimension> {
+    let dimension = match (dim, arrayed) {
+        (Dim::Dim1D, false) => wgpu::TextureViewDimension::D1,
+        (Dim::Dim2D, false) => wgpu::TextureViewDimension::D2,
+        (Dim::Dim2D, true) => wgpu::TextureViewDimension::D2Array,
+        (Dim::Dim3D, false) => wgpu::TextureViewDimension::D3,
+        (Dim::DimCube, false) => wgpu::TextureViewDimension::Cube,
+        (Dim::DimCube, true) => wgpu::TextureViewDimension::CubeArray,
+        _ => bail!("Unsupported texture dimension {:?}", dim),
+    };
+
+    Ok(dimension)
+}
+
+fn storage_texture_format(format: ImageFormat) -> Result<wgpu::TextureFormat> {
+    let format = match format {
+        ImageFormat::Rgba8 => wgpu::TextureFormat::Rgba8Unorm,
+        ImageFormat::Rgba16f => wgpu::TextureFormat::Rgba16Float,
+        ImageFormat::Rgba32f => wgpu::TextureFormat::Rgba32Float,
+        ImageFormat::R32f => wgpu::TextureFormat::R32Float,
+        ImageFormat::R32ui => wgpu::TextureFormat::R32Uint,
+        _ => bail!("Unsupported storage texture format {:?}", format),
+    };
+
+    Ok(format)
+}
+
+/// Number of bind groups a pipeline made of `shaders` uses.
+pub fn bind_group_count(shaders: &[&ShaderReflection]) -> u32 {
+    shaders
+        .iter()
+        .flat_map(|shader| &shader.bindings)
+        .map(|binding| binding.set + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Layout entries of every bind group a pipeline made of `shaders` uses, by set.
+pub fn bind_group_layouts(
+    shaders: &[&ShaderReflection],
+) -> Result<Vec<Vec<wgpu::BindGroupLayoutEntry>>> {
+    (0..bind_group_count(shaders))
+        .map(|set| bind_group_layout_entries(shaders, set))
+        .collect()
+}
+
+/// The layout of bind group `set` as used by `shaders` together, visible to each stage that
+/// uses a binding. Stages disagreeing on what a binding is is an error.
+pub fn bind_group_layout_entries(
+    shaders: &[&ShaderReflection],
+    set: u32,
+) -> Result<Vec<wgpu::BindGroupLayoutEntry>> {
+    let mut entries: Vec<wgpu::BindGroupLayoutEntry> = Vec::new();
+
+    for shader in shaders {
+        for binding in shader.bindings.iter().filter(|binding| binding.set == set) {
+            let entry = match entries
+                .iter_mut()
+                .find(|entry| entry.binding == binding.binding)
+            {
+                Some(entry) => entry,
+                None => {
+                    entries.push(wgpu::BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility: shader.stage,
+                        ty: binding.ty.clone(),
+                        count: None,
+                    });
+                    continue;
+                }
+            };
+
+            entry.visibility |= shader.stage;
+            entry.ty = match (&entry.ty, &binding.ty) {
+                (ty, other) if ty == other => other.clone(),
+                // Stages may only declare part of a uniform block
+                (
+                    wgpu::BindingType::UniformBuffer {
+                        min_binding_size: size,
+                        ..
+                    },
+                    wgpu::BindingType::UniformBuffer {
+                        min_binding_size: other_size,
+                        ..
+                    },
+                ) => wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: (*size).max(*other_size),
+                },
+                (ty, other) => bail!(
+                    "Binding {} of set {} is {:?} in one stage and {:?} in another",
+                    binding.binding,
+                    set,
+                    ty,
+                    other
+                ),
+            };
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.binding);
+    Ok(entries)
+}
+
+/// Marks the uniform buffer at `binding` as bound with a dynamic offset, for buffers holding a
+/// block per draw. Shaders have no way of saying so themselves.
+pub fn use_dynamic_offset(entries: &mut [wgpu::BindGroupLayoutEntry], binding: u32) -> Result<()> {
+    let entry = entries
+        .iter_mut()
+        .find(|entry| entry.binding == binding)
+        .with_context(|| format!("No binding {}", binding))?;
+
+    match &mut entry.ty {
+        wgpu::BindingType::UniformBuffer { dynamic, .. } => {
+            *dynamic = true;
+            Ok(())
+        }
+        ty => bail!("Binding {} is {:?}, not a uniform buffer", binding, ty),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex_shader() -> ShaderReflection {
+        reflect(include_bytes!("../assets/shader.vert.spv")).unwrap()
+    }
+
+    fn fragment_shader() -> ShaderReflection {
+        reflect(include_bytes!("../assets/shader.frag.spv")).unwrap()
+    }
+
+    #[test]
+    fn reflects_vertex_inputs() {
+        let shader = vertex_shader();
+
+        assert_eq!(shader.stage, wgpu::ShaderStage::VERTEX);
+        assert_eq!(
+            shader
+                .inputs
+                .iter()
+                .map(|input| (input.name.as_str(), input.location, input.format))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a_position", 0, wgpu::VertexFormat::Float3),
+                ("a_normal", 1, wgpu::VertexFormat::Float3),
+                ("a_tex_coords", 2, wgpu::VertexFormat::Float2),
+                ("i_model_0", 8, wgpu::VertexFormat::Float4),
+                ("i_model_1", 9, wgpu::VertexFormat::Float4),
+                ("i_model_2", 10, wgpu::VertexFormat::Float4),
+                ("i_model_3", 11, wgpu::VertexFormat::Float4),
+            ]
+        );
+    }
+
+    #[test]
+    fn reflects_bindings() {
+        assert_eq!(
+            vertex_shader().bindings,
+            vec![ResourceBinding {
+                name: "Uniforms".to_string(),
+                set: 1,
+                binding: 0,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: NonZeroU64::new(112),
+                },
+            }]
+        );
+
+        let fragment_shader = fragment_shader();
+        let binding = |name: &str| {
+            fragment_shader
+                .bindings
+                .iter()
+                .find(|binding| binding.name == name)
+                .unwrap()
+        };
+        assert_eq!(fragment_shader.bindings.len(), 7);
+        assert_eq!(
+            binding("t_diffuse").ty,
+            wgpu::BindingType::SampledTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+                component_type: wgpu::TextureComponentType::Float,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            binding("s_diffuse").ty,
+            wgpu::BindingType::Sampler { comparison: false }
+        );
+        let lights = binding("Lights");
+        assert_eq!((lights.set, lights.binding), (1, 1));
+        assert!(matches!(
+            lights.ty,
+            wgpu::BindingType::StorageBuffer { readonly: true, .. }
+        ));
+
+        // Shadow maps are depth textures, compared against through the sampler
+        assert_eq!(
+            binding("t_shadow_maps").ty,
+            wgpu::BindingType::SampledTexture {
+                dimension: wgpu::TextureViewDimension::D2Array,
+                component_type: wgpu::TextureComponentType::Float,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            binding("s_shadow_maps").ty,
+            wgpu::BindingType::Sampler { comparison: true }
+        );
+        let shadow_maps = binding("ShadowMaps");
+        assert_eq!((shadow_maps.set, shadow_maps.binding), (2, 2));
+    }
+
+    #[test]
+    fn merges_stages_into_bind_group_layouts() {
+        let vertex_shader = vertex_shader();
+        let fragment_shader = fragment_shader();
+        let shaders = [&vertex_shader, &fragment_shader];
+
+        assert_eq!(bind_group_count(&shaders), 3);
+
+        let textures = bind_group_layout_entries(&shaders, 0).unwrap();
+        assert_eq!(textures.len(), 2);
+        assert!(textures
+            .iter()
+            .all(|entry| entry.visibility == wgpu::ShaderStage::FRAGMENT));
+
+        // Both stages read the uniforms, only the fragment shader reads the lights
+        let uniforms = bind_group_layout_entries(&shaders, 1).unwrap();
+        assert_eq!(uniforms.len(), 2);
+        assert_eq!(
+            uniforms[0].visibility,
+            wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT
+        );
+        assert_eq!(uniforms[1].visibility, wgpu::ShaderStage::FRAGMENT);
+
+        let shadows = bind_group_layout_entries(&shaders, 2).unwrap();
+        assert_eq!(shadows.len(), 3);
+    }
+
+    #[test]
+    fn only_uniform_buffers_take_dynamic_offsets() {
+        let vertex_shader = vertex_shader();
+        let fragment_shader = fragment_shader();
+        let mut uniforms =
+            bind_group_layout_entries(&[&vertex_shader, &fragment_shader], 1).unwrap();
+
+        use_dynamic_offset(&mut uniforms, 0).unwrap();
+        assert!(matches!(
+            uniforms[0].ty,
+            wgpu::BindingType::UniformBuffer { dynamic: true, .. }
+        ));
+
+        // The lights are a storage buffer, and there's nothing at binding 5
+        assert!(use_dynamic_offset(&mut uniforms, 1).is_err());
+        assert!(use_dynamic_offset(&mut uniforms, 5).is_err());
+    }
+
+    #[test]
+    fn conflicting_bindings_are_errors() {
+        let vertex_shader = vertex_shader();
+        let mut fragment_shader = fragment_shader();
+        // Moves the texture onto the uniforms
+        for binding in &mut fragment_shader.bindings {
+            if binding.name == "t_diffuse" {
+                binding.set = 1;
+            }
+        }
+
+        assert!(bind_group_layout_entries(&[&vertex_shader, &fragment_shader], 1).is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(reflect(&[1, 2, 3]).is_err());
+        assert!(reflect(&[0; 20]).is_err());
+    }
+}
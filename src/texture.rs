@@ -1,5 +1,13 @@
+use std::num::NonZeroU8;
+
 use anyhow::*;
-use image::GenericImageView;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use image::hdr::HdrDecoder;
+use image::{DynamicImage, GenericImageView, Pixel, Rgb, Rgba, RgbaImage};
+
+/// The most samples anisotropic filtering takes, devices that can't filter anisotropically
+/// ignore it.
+const MAX_ANISOTROPY: u8 = 16;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -7,71 +15,699 @@ pub struct Texture {
     pub sampler: wgpu::Sampler,
 }
 
+/// How a texture is sampled, as glTF samplers describe it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplerSettings {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    /// `None` only samples the full size image, never its mip maps.
+    pub mipmap_filter: Option<wgpu::FilterMode>,
+}
+
+/// What glTF suggests for samplers that leave things out: repeating, and trilinear filtering.
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: Some(wgpu::FilterMode::Linear),
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        let default = Self::default();
+        let address_mode = |wrapping_mode| match wrapping_mode {
+            WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+        };
+        let (min_filter, mipmap_filter) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (wgpu::FilterMode::Nearest, None),
+            Some(MinFilter::Linear) => (wgpu::FilterMode::Linear, None),
+            Some(MinFilter::NearestMipmapNearest) => {
+                (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Nearest))
+            }
+            Some(MinFilter::LinearMipmapNearest) => {
+                (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Nearest))
+            }
+            Some(MinFilter::NearestMipmapLinear) => {
+                (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Linear))
+            }
+            Some(MinFilter::LinearMipmapLinear) => {
+                (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Linear))
+            }
+            None => (default.min_filter, default.mipmap_filter),
+        };
+
+        Self {
+            address_mode_u: address_mode(sampler.wrap_s()),
+            address_mode_v: address_mode(sampler.wrap_t()),
+            mag_filter: match sampler.mag_filter() {
+                Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+                Some(MagFilter::Linear) => wgpu::FilterMode::Linear,
+                None => default.mag_filter,
+            },
+            min_filter,
+            mipmap_filter,
+        }
+    }
+
+    fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        // Anisotropic filtering blurs textures that are meant to be blocky
+        let filters_linearly = self.mag_filter == wgpu::FilterMode::Linear
+            && self.min_filter == wgpu::FilterMode::Linear
+            && self.mipmap_filter == Some(wgpu::FilterMode::Linear);
+
+        wgpu::SamplerDescriptor {
+            label: Some("texture_sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter.unwrap_or(wgpu::FilterMode::Nearest),
+            lod_max_clamp: if self.mipmap_filter.is_some() {
+                f32::MAX
+            } else {
+                0.0
+            },
+            anisotropy_clamp: if filters_linearly {
+                NonZeroU8::new(MAX_ANISOTROPY)
+            } else {
+                None
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// What the texels of an 8 bit image mean, so shaders read the values they expect.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors, like base color textures, which the GPU decodes from sRGB when sampling.
+    Srgb,
+    /// Data, like normal, roughness and metallic maps, which shaders read as stored.
+    Linear,
+}
+
+/// Which float format HDR textures are stored in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HdrPrecision {
+    /// `Rgba16Float`, half the memory and filterable on every device.
+    Half,
+    /// `Rgba32Float`, which not every device can filter linearly.
+    Full,
+}
+
+/// An image in linear space, one RGBA value per texel, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearImage {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 4]>,
+}
+
+/// The texels of a texture in the format it is created with.
+struct TextureData {
+    format: wgpu::TextureFormat,
+    bytes_per_texel: u32,
+    /// Width, height and texels of every mip level, starting with the full size image.
+    levels: Vec<(u32, u32, Vec<u8>)>,
+}
+
 impl Texture {
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
+        color_space: ColorSpace,
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            color_space,
+            &SamplerSettings::default(),
+            Some(label),
+        )
     }
 
+    /// Uploads `img` with its mip maps. 8 bit images stay 8 bit in `color_space`, 16 bit ones
+    /// become half floats, decoded from sRGB up front as float formats have no sRGB variant.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
+        color_space: ColorSpace,
+        sampler: &SamplerSettings,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.as_rgba8().unwrap();
-        let dimensions = img.dimensions();
+        let data = encode_image(img, color_space)
+            .with_context(|| format!("Failed creating texture {}", label.unwrap_or("")))?;
+        Ok(Self::upload(device, queue, &data, sampler, label))
+    }
 
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth: 1,
-        };
+    /// Uploads a Radiance `.hdr` image, like an environment map.
+    pub fn from_hdr_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        precision: HdrPrecision,
+        sampler: &SamplerSettings,
+        label: &str,
+    ) -> Result<Self> {
+        let image = decode_hdr(bytes)?;
+        Self::from_hdr(device, queue, &image, precision, sampler, Some(label))
+    }
+
+    pub fn from_hdr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &LinearImage,
+        precision: HdrPrecision,
+        sampler: &SamplerSettings,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let data = encode_hdr(image, precision)
+            .with_context(|| format!("Failed creating texture {}", label.unwrap_or("")))?;
+        Ok(Self::upload(device, queue, &data, sampler, label))
+    }
+
+    fn upload(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &TextureData,
+        sampler: &SamplerSettings,
+        label: Option<&str>,
+    ) -> Self {
+        let (width, height, _) = data.levels[0];
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
-            size,
-            mip_level_count: 1,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: data.levels.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: data.format,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            rgba,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * dimensions.0,
-                rows_per_image: dimensions.1,
-            },
-            size,
-        );
+        for (mip_level, (width, height, texels)) in data.levels.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                texels,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: data.bytes_per_texel * width,
+                    rows_per_image: *height,
+                },
+                wgpu::Extent3d {
+                    width: *width,
+                    height: *height,
+                    depth: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler = device.create_sampler(&sampler.descriptor());
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
+        }
+    }
+}
+
+impl LinearImage {
+    /// Decodes the color channels of `image` from `color_space`, alpha is always linear.
+    pub fn from_rgba8(image: &RgbaImage, color_space: ColorSpace) -> Self {
+        let (width, height) = image.dimensions();
+        let texels = image.pixels().map(|pixel| {
+            let Rgba(channels) = *pixel;
+            to_unit(channels, 255.0)
+        });
+        Self::decode(width, height, texels, color_space)
+    }
+
+    fn from_rgba16(
+        width: u32,
+        height: u32,
+        pixels: impl Iterator<Item = Rgba<u16>>,
+        color_space: ColorSpace,
+    ) -> Self {
+        let texels = pixels.map(|Rgba(channels)| to_unit(channels, 65535.0));
+        Self::decode(width, height, texels, color_space)
+    }
+
+    fn decode(
+        width: u32,
+        height: u32,
+        texels: impl Iterator<Item = [f32; 4]>,
+        color_space: ColorSpace,
+    ) -> Self {
+        let texels = texels
+            .map(|[r, g, b, a]| match color_space {
+                ColorSpace::Srgb => [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a],
+                ColorSpace::Linear => [r, g, b, a],
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// Encodes the color channels into `color_space`, rounding every channel to 8 bits.
+    pub fn to_rgba8(&self, color_space: ColorSpace) -> RgbaImage {
+        let encode = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b, a] = self.texel(x, y);
+            let [r, g, b] = match color_space {
+                ColorSpace::Srgb => [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)],
+                ColorSpace::Linear => [r, g, b],
+            };
+            Rgba([encode(r), encode(g), encode(b), encode(a)])
+        })
+    }
+
+    fn texel(&self, x: u32, y: u32) -> [f32; 4] {
+        self.texels[(y * self.width + x) as usize]
+    }
+
+    /// The mip maps below this image, each half the size of the one before down to 1x1. Every
+    /// texel averages the 2x2 texels above it, which is only right because they are linear.
+    pub fn mip_chain(&self) -> Vec<LinearImage> {
+        let mut levels: Vec<LinearImage> = Vec::new();
+
+        loop {
+            let above = levels.last().unwrap_or(self);
+            let (width, height) = (above.width, above.height);
+            if width <= 1 && height <= 1 {
+                return levels;
+            }
+
+            let (level_width, level_height) = ((width / 2).max(1), (height / 2).max(1));
+            let mut texels = Vec::with_capacity((level_width * level_height) as usize);
+            for y in 0..level_height {
+                for x in 0..level_width {
+                    // Odd sizes and 1 texel wide or high levels reuse the last row or column
+                    let mut sum = [0.0f32; 4];
+                    for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let texel =
+                            above.texel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1));
+                        for channel in 0..4 {
+                            sum[channel] += texel[channel] / 4.0;
+                        }
+                    }
+                    texels.push(sum);
+                }
+            }
+
+            levels.push(LinearImage {
+                width: level_width,
+                height: level_height,
+                texels,
+            });
+        }
+    }
+}
+
+/// Scales integer channels to 0..1.
+fn to_unit<T: Into<f32>>(channels: [T; 4], max: f32) -> [f32; 4] {
+    let [r, g, b, a] = channels;
+    [
+        r.into() / max,
+        g.into() / max,
+        b.into() / max,
+        a.into() / max,
+    ]
+}
+
+fn encode_image(img: &DynamicImage, color_space: ColorSpace) -> Result<TextureData> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        bail!("the image is empty");
+    }
+
+    let wide_pixels: Option<Vec<Rgba<u16>>> = match img {
+        DynamicImage::ImageLuma16(image) => Some(image.pixels().map(Pixel::to_rgba).collect()),
+        DynamicImage::ImageLumaA16(image) => Some(image.pixels().map(Pixel::to_rgba).collect()),
+        DynamicImage::ImageRgb16(image) => Some(image.pixels().map(Pixel::to_rgba).collect()),
+        DynamicImage::ImageRgba16(image) => Some(image.pixels().copied().collect()),
+        _ => None,
+    };
+    if let Some(pixels) = wide_pixels {
+        let image = LinearImage::from_rgba16(width, height, pixels.into_iter(), color_space);
+        return encode_hdr(&image, HdrPrecision::Half);
+    }
+
+    // Every other variant has 8 bits per channel, which `to_rgba` keeps exactly
+    let rgba = img.to_rgba();
+    let mip_chain = LinearImage::from_rgba8(&rgba, color_space).mip_chain();
+    let mut levels = vec![(width, height, rgba.into_raw())];
+    levels.extend(mip_chain.iter().map(|level| {
+        (
+            level.width,
+            level.height,
+            level.to_rgba8(color_space).into_raw(),
+        )
+    }));
+
+    Ok(TextureData {
+        format: match color_space {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        },
+        bytes_per_texel: 4,
+        levels,
+    })
+}
+
+fn encode_hdr(image: &LinearImage, precision: HdrPrecision) -> Result<TextureData> {
+    if image.width == 0 || image.height == 0 {
+        bail!("the image is empty");
+    }
+    if image.texels.len() != (image.width * image.height) as usize {
+        bail!(
+            "a {}x{} image needs {} texels, not {}",
+            image.width,
+            image.height,
+            image.width * image.height,
+            image.texels.len()
+        );
+    }
+
+    let mip_chain = image.mip_chain();
+    let levels = std::iter::once(image)
+        .chain(&mip_chain)
+        .map(|level| {
+            let texels = match precision {
+                HdrPrecision::Half => {
+                    let halves = level
+                        .texels
+                        .iter()
+                        .flat_map(|texel| texel.iter().map(|&channel| f32_to_f16(channel)))
+                        .collect::<Vec<_>>();
+                    bytemuck::cast_slice(&halves).to_vec()
+                }
+                HdrPrecision::Full => bytemuck::cast_slice(&level.texels).to_vec(),
+            };
+            (level.width, level.height, texels)
         })
+        .collect();
+
+    Ok(match precision {
+        HdrPrecision::Half => TextureData {
+            format: wgpu::TextureFormat::Rgba16Float,
+            bytes_per_texel: 8,
+            levels,
+        },
+        HdrPrecision::Full => TextureData {
+            format: wgpu::TextureFormat::Rgba32Float,
+            bytes_per_texel: 16,
+            levels,
+        },
+    })
+}
+
+/// Decodes a Radiance `.hdr` image, which has no alpha, so it's opaque.
+pub fn decode_hdr(bytes: &[u8]) -> Result<LinearImage> {
+    let decoder = HdrDecoder::new(bytes).context("Invalid HDR image")?;
+    let metadata = decoder.metadata();
+    let texels = decoder
+        .read_image_hdr()
+        .context("Failed decoding HDR image")?
+        .into_iter()
+        .map(|Rgb([r, g, b])| [r, g, b, 1.0])
+        .collect();
+
+    Ok(LinearImage {
+        width: metadata.width,
+        height: metadata.height,
+        texels,
+    })
+}
+
+/// The bits of the half float closest to `value`, rounding ties to even like GPUs do.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinities stay infinite, NaNs keep a mantissa bit so they stay NaN
+        return sign | 0x7c00 | if mantissa == 0 { 0 } else { 0x200 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    // Normal halves drop 13 mantissa bits, subnormal ones shift the implicit 1 in too
+    let (mantissa, shift) = if exponent > 0 {
+        (mantissa, 13)
+    } else if exponent >= -10 {
+        (mantissa | 0x80_0000, (14 - exponent) as u32)
+    } else {
+        return sign;
+    };
+    let half = (exponent.max(0) as u32) << 10 | mantissa >> shift;
+    let remainder = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // Carrying out of the mantissa rounds up into the exponent, which is still right
+    let rounded = if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    };
+    sign | rounded as u16
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(color_space: ColorSpace) -> LinearImage {
+        let black = Rgba([0, 0, 0, 0]);
+        let white = Rgba([255, 255, 255, 255]);
+        let image = RgbaImage::from_fn(2, 2, |x, y| if (x + y) % 2 == 0 { black } else { white });
+        LinearImage::from_rgba8(&image, color_space)
+    }
+
+    #[test]
+    fn mip_chains_halve_down_to_a_single_texel() {
+        let image = LinearImage::from_rgba8(&RgbaImage::new(8, 3), ColorSpace::Srgb);
+
+        let sizes = image
+            .mip_chain()
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, vec![(4, 1), (2, 1), (1, 1)]);
+        let single_texel = LinearImage::from_rgba8(&RgbaImage::new(1, 1), ColorSpace::Srgb);
+        assert!(single_texel.mip_chain().is_empty());
+    }
+
+    #[test]
+    fn mip_maps_average_in_linear_space() {
+        let levels = checkerboard(ColorSpace::Srgb).mip_chain();
+
+        // Half as bright as white is 188 in sRGB, not 128, alpha isn't encoded
+        let srgb = levels[0].to_rgba8(ColorSpace::Srgb);
+        assert_eq!(*srgb.get_pixel(0, 0), Rgba([188, 188, 188, 128]));
+
+        // Linear data, like normal maps, averages as it's stored
+        let levels = checkerboard(ColorSpace::Linear).mip_chain();
+        let linear = levels[0].to_rgba8(ColorSpace::Linear);
+        assert_eq!(*linear.get_pixel(0, 0), Rgba([128, 128, 128, 128]));
+    }
+
+    #[test]
+    fn solid_colors_stay_the_same() {
+        let color = Rgba([200, 100, 50, 255]);
+        let image = RgbaImage::from_pixel(5, 7, color);
+
+        for color_space in &[ColorSpace::Srgb, ColorSpace::Linear] {
+            for level in LinearImage::from_rgba8(&image, *color_space).mip_chain() {
+                let encoded = level.to_rgba8(*color_space);
+                assert!(encoded.pixels().all(|pixel| *pixel == color));
+            }
+        }
+    }
+
+    #[test]
+    fn every_image_variant_converts() {
+        let eight_bit = vec![
+            DynamicImage::new_luma8(4, 2),
+            DynamicImage::new_luma_a8(4, 2),
+            DynamicImage::new_rgb8(4, 2),
+            DynamicImage::new_rgba8(4, 2),
+            DynamicImage::new_bgr8(4, 2),
+            DynamicImage::new_bgra8(4, 2),
+        ];
+        let sixteen_bit = vec![
+            DynamicImage::new_luma16(4, 2),
+            DynamicImage::new_luma_a16(4, 2),
+            DynamicImage::new_rgb16(4, 2),
+            DynamicImage::new_rgba16(4, 2),
+        ];
+
+        for image in &eight_bit {
+            let srgb = encode_image(image, ColorSpace::Srgb).unwrap();
+            assert_eq!(srgb.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+            let linear = encode_image(image, ColorSpace::Linear).unwrap();
+            assert_eq!(linear.format, wgpu::TextureFormat::Rgba8Unorm);
+            assert_eq!(linear.levels.len(), 3);
+            assert_eq!(linear.levels[0].2.len(), 4 * 2 * 4);
+        }
+        for image in &sixteen_bit {
+            let data = encode_image(image, ColorSpace::Srgb).unwrap();
+            assert_eq!(data.format, wgpu::TextureFormat::Rgba16Float);
+            assert_eq!(data.levels[0].2.len(), 4 * 2 * 8);
+        }
+    }
+
+    #[test]
+    fn grayscale_fills_every_color_channel() {
+        let gray = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(1, 1, image::Luma([100])));
+
+        let data = encode_image(&gray, ColorSpace::Linear).unwrap();
+
+        assert_eq!(data.levels[0].2, vec![100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_srgb_is_decoded_to_linear_floats() {
+        let pixel = Rgba([65535, 0, 32768, 65535]);
+        let image = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(1, 1, pixel));
+
+        let data = encode_image(&image, ColorSpace::Srgb).unwrap();
+
+        let halves: &[u16] = bytemuck::cast_slice(&data.levels[0].2);
+        // Half of full brightness in sRGB is 0.2140 linear, 0x32d9 is the closest half float
+        assert_eq!(halves, &[0x3c00, 0x0000, 0x32d9, 0x3c00]);
+    }
+
+    #[test]
+    fn empty_images_are_errors() {
+        assert!(encode_image(&DynamicImage::new_rgba8(0, 4), ColorSpace::Srgb).is_err());
+
+        let missing_texels = LinearImage {
+            width: 2,
+            height: 2,
+            texels: vec![[0.0; 4]; 3],
+        };
+        assert!(encode_hdr(&missing_texels, HdrPrecision::Half).is_err());
+    }
+
+    #[test]
+    fn hdr_images_keep_values_above_one() {
+        let texels = vec![Rgb([4.0, 0.5, 100.0]); 4 * 4];
+        let mut bytes = Vec::new();
+        image::hdr::HdrEncoder::new(&mut bytes)
+            .encode(&texels, 4, 4)
+            .unwrap();
+
+        let image = decode_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(image.texels[5], [4.0, 0.5, 100.0, 1.0]);
+
+        let full = encode_hdr(&image, HdrPrecision::Full).unwrap();
+        assert_eq!(full.format, wgpu::TextureFormat::Rgba32Float);
+        let floats: &[f32] = bytemuck::cast_slice(&full.levels[2].2);
+        assert_eq!(floats, &[4.0, 0.5, 100.0, 1.0]);
+
+        let half = encode_hdr(&image, HdrPrecision::Half).unwrap();
+        assert_eq!(half.format, wgpu::TextureFormat::Rgba16Float);
+        assert_eq!(half.levels.len(), 3);
+        assert!(decode_hdr(b"not an hdr image").is_err());
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest_even() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        // Past the largest half float
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        // 1 + 2^-11 is halfway between 1 and the next half float, ties go to the even 1
+        assert_eq!(f32_to_f16(1.0 + 2.0f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+        // Subnormals, the smallest half float is 2^-24
+        assert_eq!(f32_to_f16(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2.0f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_f16(3.0 * 2.0f32.powi(-25)), 0x0002);
+        assert_eq!(f32_to_f16(2.0f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(f32_to_f16(f32::NAN) & 0x03ff, 0);
+    }
+
+    #[test]
+    fn samplers_without_filters_get_trilinear_filtering() {
+        let (document, _, _) = gltf::import("./src/assets/render_test_scene.gltf").unwrap();
+        let texture = document.textures().next().unwrap();
+
+        let settings = SamplerSettings::from_gltf(&texture.sampler());
+
+        assert_eq!(settings, SamplerSettings::default());
+        let descriptor = settings.descriptor();
+        assert_eq!(descriptor.anisotropy_clamp, NonZeroU8::new(MAX_ANISOTROPY));
+        assert_eq!(descriptor.lod_max_clamp, f32::MAX);
+    }
+
+    #[test]
+    fn nearest_filtering_without_mip_maps_stays_sharp() {
+        let settings = SamplerSettings {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: None,
+            ..SamplerSettings::default()
+        };
+
+        let descriptor = settings.descriptor();
+
+        assert_eq!(descriptor.anisotropy_clamp, None);
+        assert_eq!(descriptor.lod_max_clamp, 0.0);
     }
 }